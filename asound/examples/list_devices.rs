@@ -0,0 +1,14 @@
+use asound::*;
+
+fn main() {
+    println!("ALSA Playback Devices:\n----------------------");
+
+    for device in playback_devices().expect("Failed to get device hints") {
+        println!("- {}", device.name);
+        if let Some(description) = device.description {
+            for line in description.lines() {
+                println!("  {line}");
+            }
+        }
+    }
+}
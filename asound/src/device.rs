@@ -0,0 +1,54 @@
+use crate::error::{AlsaError, Result};
+use crate::ffi::*;
+use std::ffi::{CStr, c_char, c_void};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceHint {
+    /// The PCM name passed to `snd_pcm_open`, e.g. `default` or `hw:CARD=PCH,DEV=0`.
+    pub name: String,
+    pub description: Option<String>,
+}
+
+unsafe fn take_hint(hint: *const c_void, id: &CStr) -> Option<String> {
+    unsafe {
+        let ptr = snd_device_name_get_hint(hint, id.as_ptr());
+        if ptr.is_null() {
+            return None;
+        }
+        let value = CStr::from_ptr(ptr as *const c_char)
+            .to_string_lossy()
+            .into_owned();
+        free(ptr as *mut c_void);
+        Some(value)
+    }
+}
+
+/// Lists every PCM that can be opened for playback.
+pub fn playback_devices() -> Result<Vec<DeviceHint>> {
+    let mut devices = Vec::new();
+    unsafe {
+        let mut hints: *mut *mut c_void = std::ptr::null_mut();
+        AlsaError::from_code(snd_device_name_hint(-1, c"pcm".as_ptr(), &mut hints))?;
+
+        let mut hint = hints;
+        while !(*hint).is_null() {
+            // A missing IOID means the PCM supports both directions.
+            let output = match take_hint(*hint, c"IOID") {
+                Some(ioid) => ioid == "Output",
+                None => true,
+            };
+
+            if output && let Some(name) = take_hint(*hint, c"NAME") {
+                devices.push(DeviceHint {
+                    name,
+                    description: take_hint(*hint, c"DESC"),
+                });
+            }
+
+            hint = hint.add(1);
+        }
+
+        snd_device_name_free_hint(hints);
+    }
+    Ok(devices)
+}
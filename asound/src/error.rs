@@ -0,0 +1,43 @@
+use crate::ffi::snd_strerror;
+use core::fmt;
+use std::ffi::{CStr, c_int};
+
+/// A negative errno returned by alsa-lib.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct AlsaError(pub c_int);
+
+impl AlsaError {
+    pub fn from_code(code: c_int) -> Result<c_int> {
+        if code < 0 {
+            Err(AlsaError(code))
+        } else {
+            Ok(code)
+        }
+    }
+
+    pub fn message(&self) -> String {
+        unsafe {
+            let ptr = snd_strerror(self.0);
+            if ptr.is_null() {
+                return String::from("Unknown error");
+            }
+            CStr::from_ptr(ptr).to_string_lossy().into_owned()
+        }
+    }
+}
+
+impl fmt::Debug for AlsaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AlsaError({}) ({})", self.0, self.message())
+    }
+}
+
+impl fmt::Display for AlsaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl std::error::Error for AlsaError {}
+
+pub type Result<T> = std::result::Result<T, AlsaError>;
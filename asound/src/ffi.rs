@@ -0,0 +1,91 @@
+use std::ffi::{c_char, c_int, c_long, c_uint, c_ulong, c_void};
+
+#[repr(C)]
+pub struct snd_pcm_t {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct snd_pcm_hw_params_t {
+    _private: [u8; 0],
+}
+
+pub type snd_pcm_uframes_t = c_ulong;
+pub type snd_pcm_sframes_t = c_long;
+
+pub const SND_PCM_STREAM_PLAYBACK: c_int = 0;
+pub const SND_PCM_STREAM_CAPTURE: c_int = 1;
+
+pub const SND_PCM_NONBLOCK: c_int = 0x0001;
+
+pub const SND_PCM_ACCESS_RW_INTERLEAVED: c_int = 3;
+
+pub const SND_PCM_FORMAT_S16_LE: c_int = 2;
+pub const SND_PCM_FORMAT_S32_LE: c_int = 10;
+pub const SND_PCM_FORMAT_FLOAT_LE: c_int = 14;
+pub const SND_PCM_FORMAT_S24_3LE: c_int = 32;
+
+#[link(name = "asound")]
+unsafe extern "C" {
+    pub fn snd_pcm_open(
+        pcm: *mut *mut snd_pcm_t,
+        name: *const c_char,
+        stream: c_int,
+        mode: c_int,
+    ) -> c_int;
+    pub fn snd_pcm_close(pcm: *mut snd_pcm_t) -> c_int;
+    pub fn snd_pcm_set_params(
+        pcm: *mut snd_pcm_t,
+        format: c_int,
+        access: c_int,
+        channels: c_uint,
+        rate: c_uint,
+        soft_resample: c_int,
+        latency: c_uint,
+    ) -> c_int;
+    pub fn snd_pcm_get_params(
+        pcm: *mut snd_pcm_t,
+        buffer_size: *mut snd_pcm_uframes_t,
+        period_size: *mut snd_pcm_uframes_t,
+    ) -> c_int;
+    pub fn snd_pcm_writei(
+        pcm: *mut snd_pcm_t,
+        buffer: *const c_void,
+        size: snd_pcm_uframes_t,
+    ) -> snd_pcm_sframes_t;
+    pub fn snd_pcm_avail_update(pcm: *mut snd_pcm_t) -> snd_pcm_sframes_t;
+    pub fn snd_pcm_delay(pcm: *mut snd_pcm_t, delay: *mut snd_pcm_sframes_t) -> c_int;
+    pub fn snd_pcm_wait(pcm: *mut snd_pcm_t, timeout: c_int) -> c_int;
+    pub fn snd_pcm_recover(pcm: *mut snd_pcm_t, err: c_int, silent: c_int) -> c_int;
+    pub fn snd_pcm_prepare(pcm: *mut snd_pcm_t) -> c_int;
+    pub fn snd_pcm_drop(pcm: *mut snd_pcm_t) -> c_int;
+    pub fn snd_pcm_drain(pcm: *mut snd_pcm_t) -> c_int;
+
+    pub fn snd_pcm_hw_params_malloc(ptr: *mut *mut snd_pcm_hw_params_t) -> c_int;
+    pub fn snd_pcm_hw_params_free(obj: *mut snd_pcm_hw_params_t);
+    pub fn snd_pcm_hw_params_any(pcm: *mut snd_pcm_t, params: *mut snd_pcm_hw_params_t) -> c_int;
+    pub fn snd_pcm_hw_params_get_channels_max(
+        params: *const snd_pcm_hw_params_t,
+        val: *mut c_uint,
+    ) -> c_int;
+    pub fn snd_pcm_hw_params_test_rate(
+        pcm: *mut snd_pcm_t,
+        params: *mut snd_pcm_hw_params_t,
+        val: c_uint,
+        dir: c_int,
+    ) -> c_int;
+
+    pub fn snd_device_name_hint(
+        card: c_int,
+        iface: *const c_char,
+        hints: *mut *mut *mut c_void,
+    ) -> c_int;
+    pub fn snd_device_name_get_hint(hint: *const c_void, id: *const c_char) -> *mut c_char;
+    pub fn snd_device_name_free_hint(hints: *mut *mut c_void) -> c_int;
+
+    pub fn snd_strerror(errnum: c_int) -> *const c_char;
+}
+
+unsafe extern "C" {
+    pub fn free(ptr: *mut c_void);
+}
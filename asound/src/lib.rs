@@ -0,0 +1,12 @@
+#![cfg(target_os = "linux")]
+#![allow(non_camel_case_types)]
+
+pub mod device;
+pub mod error;
+pub mod ffi;
+pub mod pcm;
+
+pub use device::*;
+pub use error::*;
+pub use ffi::*;
+pub use pcm::*;
@@ -0,0 +1,188 @@
+use crate::error::{AlsaError, Result};
+use crate::ffi::*;
+use std::ffi::{CString, c_int, c_void};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    S16LE,
+    S24LE3,
+    S32LE,
+    F32LE,
+}
+
+impl Format {
+    pub fn bytes(&self) -> usize {
+        match self {
+            Format::S16LE => 2,
+            Format::S24LE3 => 3,
+            Format::S32LE => 4,
+            Format::F32LE => 4,
+        }
+    }
+
+    fn raw(&self) -> c_int {
+        match self {
+            Format::S16LE => SND_PCM_FORMAT_S16_LE,
+            Format::S24LE3 => SND_PCM_FORMAT_S24_3LE,
+            Format::S32LE => SND_PCM_FORMAT_S32_LE,
+            Format::F32LE => SND_PCM_FORMAT_FLOAT_LE,
+        }
+    }
+}
+
+/// A playback PCM handle, closed on drop.
+pub struct Pcm {
+    ptr: *mut snd_pcm_t,
+}
+
+unsafe impl Send for Pcm {}
+
+impl Pcm {
+    pub fn open_playback(name: &str) -> Result<Self> {
+        let name = CString::new(name).map_err(|_| AlsaError(-22))?;
+        let mut ptr = std::ptr::null_mut();
+        unsafe {
+            AlsaError::from_code(snd_pcm_open(
+                &mut ptr,
+                name.as_ptr(),
+                SND_PCM_STREAM_PLAYBACK,
+                0,
+            ))?;
+        }
+        Ok(Self { ptr })
+    }
+
+    /// Must be called before `set_params`, while the configuration space is still open.
+    pub fn max_channels(&self) -> Result<u32> {
+        unsafe {
+            let mut params = std::ptr::null_mut();
+            AlsaError::from_code(snd_pcm_hw_params_malloc(&mut params))?;
+            let mut channels = 0;
+            let result =
+                AlsaError::from_code(snd_pcm_hw_params_any(self.ptr, params)).and_then(|_| {
+                    AlsaError::from_code(snd_pcm_hw_params_get_channels_max(params, &mut channels))
+                });
+            snd_pcm_hw_params_free(params);
+            result.map(|_| channels)
+        }
+    }
+
+    /// Must be called before `set_params`, while the configuration space is still open.
+    pub fn supports_rate(&self, rate: u32) -> bool {
+        unsafe {
+            let mut params = std::ptr::null_mut();
+            if snd_pcm_hw_params_malloc(&mut params) < 0 {
+                return false;
+            }
+            let supported = snd_pcm_hw_params_any(self.ptr, params) >= 0
+                && snd_pcm_hw_params_test_rate(self.ptr, params, rate, 0) == 0;
+            snd_pcm_hw_params_free(params);
+            supported
+        }
+    }
+
+    /// Configures interleaved access without ALSA resampling.
+    pub fn set_params(
+        &self,
+        format: Format,
+        channels: u32,
+        rate: u32,
+        latency_us: u32,
+    ) -> Result<()> {
+        unsafe {
+            AlsaError::from_code(snd_pcm_set_params(
+                self.ptr,
+                format.raw(),
+                SND_PCM_ACCESS_RW_INTERLEAVED,
+                channels,
+                rate,
+                0,
+                latency_us,
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Returns `(buffer_size, period_size)` in frames.
+    pub fn params(&self) -> Result<(usize, usize)> {
+        let mut buffer_size = 0;
+        let mut period_size = 0;
+        unsafe {
+            AlsaError::from_code(snd_pcm_get_params(
+                self.ptr,
+                &mut buffer_size,
+                &mut period_size,
+            ))?;
+        }
+        Ok((buffer_size as usize, period_size as usize))
+    }
+
+    /// Waits up to `timeout_ms` for room in the buffer. Returns `false` on timeout.
+    pub fn wait(&self, timeout_ms: i32) -> Result<bool> {
+        unsafe { AlsaError::from_code(snd_pcm_wait(self.ptr, timeout_ms)).map(|r| r == 1) }
+    }
+
+    pub fn avail(&self) -> Result<usize> {
+        unsafe {
+            let avail = snd_pcm_avail_update(self.ptr);
+            AlsaError::from_code(avail as c_int)?;
+            Ok(avail as usize)
+        }
+    }
+
+    /// Frames queued ahead of the one currently being heard.
+    pub fn delay(&self) -> Result<usize> {
+        let mut delay = 0;
+        unsafe {
+            AlsaError::from_code(snd_pcm_delay(self.ptr, &mut delay))?;
+        }
+        Ok(delay.max(0) as usize)
+    }
+
+    /// Writes interleaved frames and returns how many were consumed.
+    pub fn write_interleaved(&self, buffer: &[u8], frames: usize) -> Result<usize> {
+        unsafe {
+            let written = snd_pcm_writei(
+                self.ptr,
+                buffer.as_ptr() as *const c_void,
+                frames as snd_pcm_uframes_t,
+            );
+            AlsaError::from_code(written as c_int)?;
+            Ok(written as usize)
+        }
+    }
+
+    /// Recovers from an underrun or suspend reported by another call.
+    pub fn recover(&self, err: AlsaError) -> Result<()> {
+        unsafe {
+            AlsaError::from_code(snd_pcm_recover(self.ptr, err.0, 1))?;
+        }
+        Ok(())
+    }
+
+    /// Discards everything queued and leaves the stream ready for new data.
+    pub fn flush(&self) -> Result<()> {
+        unsafe {
+            AlsaError::from_code(snd_pcm_drop(self.ptr))?;
+            AlsaError::from_code(snd_pcm_prepare(self.ptr))?;
+        }
+        Ok(())
+    }
+
+    /// Blocks until everything queued has been played.
+    pub fn drain(&self) -> Result<()> {
+        unsafe {
+            AlsaError::from_code(snd_pcm_drain(self.ptr))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Pcm {
+    fn drop(&mut self) {
+        unsafe {
+            snd_pcm_drop(self.ptr);
+            snd_pcm_close(self.ptr);
+        }
+    }
+}
@@ -163,6 +163,36 @@ impl AudioDevice {
         self.channel_count(K_AUDIO_OBJECT_PROPERTY_SCOPE_OUTPUT)
     }
 
+    /// Frames between the output callback and the speaker: device latency, safety offset and the IO buffer.
+    pub fn output_latency(&self) -> Result<u32> {
+        let latency = self.output_u32(K_AUDIO_DEVICE_PROPERTY_LATENCY)?;
+        let safety = self.output_u32(K_AUDIO_DEVICE_PROPERTY_SAFETY_OFFSET)?;
+        let buffer = self.output_u32(K_AUDIO_DEVICE_PROPERTY_BUFFER_FRAME_SIZE)?;
+        Ok(latency + safety + buffer)
+    }
+
+    fn output_u32(&self, selector: u32) -> Result<u32> {
+        let address = AudioObjectPropertyAddress {
+            m_selector: selector,
+            m_scope: K_AUDIO_OBJECT_PROPERTY_SCOPE_OUTPUT,
+            m_element: K_AUDIO_OBJECT_PROPERTY_ELEMENT_MAIN,
+        };
+        let mut value: u32 = 0;
+        let mut data_size = std::mem::size_of::<u32>() as u32;
+        let status = unsafe {
+            AudioObjectGetPropertyData(
+                self.id,
+                &address,
+                0,
+                std::ptr::null(),
+                &mut data_size,
+                &mut value as *mut u32 as *mut c_void,
+            )
+        };
+        CoreAudioError::from_os_status(status)?;
+        Ok(value)
+    }
+
     fn channel_count(&self, scope: u32) -> Result<u32> {
         let address = AudioObjectPropertyAddress {
             m_selector: K_AUDIO_DEVICE_PROPERTY_STREAM_CONFIGURATION,
@@ -33,6 +33,9 @@ pub const K_AUDIO_HARDWARE_PROPERTY_DEFAULT_OUTPUT_DEVICE: u32 = four_cc(b"dOut"
 pub const K_AUDIO_DEVICE_PROPERTY_DEVICE_NAME_CFSTRING: u32 = four_cc(b"lnam");
 pub const K_AUDIO_DEVICE_PROPERTY_STREAM_CONFIGURATION: u32 = four_cc(b"slay");
 pub const K_AUDIO_DEVICE_PROPERTY_NOMINAL_SAMPLE_RATE: u32 = four_cc(b"nsrt");
+pub const K_AUDIO_DEVICE_PROPERTY_LATENCY: u32 = four_cc(b"ltnc");
+pub const K_AUDIO_DEVICE_PROPERTY_SAFETY_OFFSET: u32 = four_cc(b"saft");
+pub const K_AUDIO_DEVICE_PROPERTY_BUFFER_FRAME_SIZE: u32 = four_cc(b"fsiz");
 
 // Component Constants
 pub const K_AUDIO_UNIT_TYPE_OUTPUT: u32 = four_cc(b"auou");
@@ -0,0 +1,64 @@
+use criterion::{Criterion, Throughput, black_box, criterion_group, criterion_main};
+use onmi::*;
+use std::sync::Arc;
+
+const RATE: u32 = 44100;
+const SECONDS: usize = 10;
+
+/// Ten seconds of a stereo sine as a float WAV in memory.
+fn synthetic() -> Arc<[u8]> {
+    let path = std::env::temp_dir().join(format!("onmi_bench_{}.wav", std::process::id()));
+    let samples: Vec<f32> = (0..RATE as usize * SECONDS)
+        .flat_map(|i| {
+            let x = (i as f32 * 440.0 * std::f32::consts::TAU / RATE as f32).sin();
+            [x, -x]
+        })
+        .collect();
+    let mut wav = WavWriter::create(&path, RATE, 2).unwrap();
+    wav.write_samples(&samples).unwrap();
+    drop(wav);
+
+    let bytes = std::fs::read(&path).unwrap();
+    let _ = std::fs::remove_file(path);
+    bytes.into()
+}
+
+fn decode(c: &mut Criterion) {
+    let bytes = synthetic();
+
+    let mut group = c.benchmark_group("decode");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements((RATE as usize * SECONDS) as u64));
+
+    group.bench_function("next_sample", |b| {
+        b.iter(|| {
+            let mut decoder = Symphonia::from_bytes(Arc::clone(&bytes), Some("wav")).unwrap();
+            let mut sum = 0.0;
+            while let Some(sample) = decoder.next_sample() {
+                sum += sample;
+            }
+            black_box(sum);
+        });
+    });
+
+    group.bench_function("read_frames", |b| {
+        let mut buffer = vec![0.0; 4096];
+        b.iter(|| {
+            let mut decoder = Symphonia::from_bytes(Arc::clone(&bytes), Some("wav")).unwrap();
+            let mut sum = 0.0;
+            loop {
+                let frames = decoder.read_frames(&mut buffer);
+                sum += buffer[..frames * 2].iter().sum::<f32>();
+                if frames * 2 < buffer.len() {
+                    break;
+                }
+            }
+            black_box(sum);
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
@@ -2,17 +2,14 @@ use criterion::{Criterion, black_box, criterion_group, criterion_main};
 use miniwalk::*;
 use onmi::*;
 
-fn custom(files: &[DirEntry]) -> Vec<Result<Song, String>> {
+fn custom(files: &[DirEntry]) -> Vec<Result<Song, Error>> {
     files
         .iter()
-        .map(|file| match flac_metadata(&file.path, false) {
-            Ok(song) => Ok(song),
-            Err(err) => Err(format!("Error: ({err}) @ {}", file.path.display())),
-        })
+        .map(|file| flac_metadata(&file.path, false))
         .collect()
 }
 
-fn symphonia(files: &[DirEntry]) -> Vec<Result<Song, String>> {
+fn symphonia(files: &[DirEntry]) -> Vec<Result<Song, Error>> {
     files
         .iter()
         .map(|entry| metadata(&entry.path, true, false))
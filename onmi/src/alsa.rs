@@ -0,0 +1,279 @@
+use crate::*;
+use asound::{Format, Pcm, playback_devices};
+
+const WAIT_MS: i32 = 50;
+const LATENCY_US: u32 = 100_000;
+const DEFAULT_PCM: &str = "default";
+
+/// Device ids are PCM names, e.g. `default`, `null` or `hw:CARD=PCH,DEV=0`.
+#[derive(Default)]
+pub struct AlsaBackend;
+
+impl AlsaBackend {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// A device for any PCM name, including ones that aren't listed such as `file:'out.raw',raw`.
+    pub fn pcm(name: &str) -> Device {
+        Device {
+            name: name.to_string(),
+            id: name.to_string(),
+        }
+    }
+}
+
+impl AudioBackend for AlsaBackend {
+    fn name(&self) -> &'static str {
+        "ALSA"
+    }
+
+    fn devices(&self) -> Vec<Device> {
+        playback_devices()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|hint| Device {
+                name: hint
+                    .description
+                    .as_deref()
+                    .and_then(|d| d.lines().next())
+                    .map(|d| format!("{d} ({})", hint.name))
+                    .unwrap_or_else(|| hint.name.clone()),
+                id: hint.name,
+            })
+            .collect()
+    }
+
+    // ALSA routes `default` itself, so following it just means moving back onto it.
+    fn default_device(&self) -> Option<Device> {
+        Some(Self::pcm(DEFAULT_PCM))
+    }
+
+    fn open(
+        &self,
+        device: &Device,
+        sample_rate: Option<u32>,
+        channels: usize,
+    ) -> Option<Box<dyn OutputStream>> {
+        let pcm = Pcm::open_playback(&device.id).ok()?;
+
+        // Plugins like `default` take almost any count, so it's capped at the song's.
+        let wanted = match pcm.max_channels() {
+            Ok(0) | Err(_) => 2,
+            Ok(max) => (channels as u32).clamp(1, max),
+        };
+
+        let sample_rate = match sample_rate {
+            Some(rate) => {
+                if !COMMON_SAMPLE_RATES.contains(&rate) {
+                    return None;
+                }
+                rate
+            }
+            None if pcm.supports_rate(48000) => 48000,
+            None => 44100,
+        };
+
+        // Hardware devices without a plugin in front may only take integers,
+        // and some only open in stereo.
+        let (channels, format) = [wanted, 2]
+            .into_iter()
+            .flat_map(|channels| {
+                [
+                    SampleFormat::F32,
+                    SampleFormat::I32,
+                    SampleFormat::I24,
+                    SampleFormat::I16,
+                ]
+                .map(|format| (channels, format))
+            })
+            .find(|(channels, format)| {
+                pcm.set_params(alsa_format(*format), *channels, sample_rate, LATENCY_US)
+                    .is_ok()
+            })?;
+        let (_, period) = pcm.params().ok()?;
+
+        Some(Box::new(AlsaOutput {
+            pcm,
+            device: device.clone(),
+            sample_rate,
+            channels,
+            format,
+            period: period.max(1),
+            buffer: Vec::new(),
+        }))
+    }
+}
+
+fn alsa_format(format: SampleFormat) -> Format {
+    match format {
+        SampleFormat::F32 => Format::F32LE,
+        SampleFormat::I16 => Format::S16LE,
+        SampleFormat::I24 => Format::S24LE3,
+        SampleFormat::I32 => Format::S32LE,
+    }
+}
+
+pub struct AlsaOutput {
+    pub pcm: Pcm,
+    pub device: Device,
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub format: SampleFormat,
+    pub period: usize,
+    buffer: Vec<u8>,
+}
+
+impl AlsaOutput {
+    pub fn fill_buffer(&mut self, renderer: &mut Renderer) -> usize {
+        let frames = match self.pcm.avail() {
+            Ok(avail) => avail.min(self.period),
+            Err(err) => {
+                if self.pcm.recover(err).is_err() {
+                    renderer.state.set_error(RuntimeError::StreamStart);
+                }
+                return 0;
+            }
+        };
+
+        if frames == 0 {
+            return frames;
+        }
+
+        let channels = self.channels as usize;
+        self.buffer
+            .resize(frames * channels * self.format.bytes(), 0);
+        renderer.fill_as(&mut self.buffer, channels, self.format);
+
+        match self.pcm.write_interleaved(&self.buffer, frames) {
+            Ok(written) => written,
+            Err(err) => {
+                if self.pcm.recover(err).is_err() {
+                    renderer.state.set_error(RuntimeError::StreamStart);
+                }
+                0
+            }
+        }
+    }
+}
+
+impl OutputStream for AlsaOutput {
+    fn device(&self) -> &Device {
+        &self.device
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        self.channels as usize
+    }
+
+    fn render(&mut self, renderer: &mut Renderer) {
+        if renderer.update() {
+            let _ = self.pcm.flush();
+        }
+
+        // Let the buffer run dry while idle, the next write recovers from the underrun.
+        if !renderer.is_active() {
+            std::thread::sleep(std::time::Duration::from_millis(WAIT_MS as u64));
+            return;
+        }
+
+        if let Err(err) = self.pcm.wait(WAIT_MS) {
+            let _ = self.pcm.recover(err);
+        }
+
+        self.fill_buffer(renderer);
+    }
+
+    fn latency(&self) -> std::time::Duration {
+        let frames = self.pcm.delay().unwrap_or(0);
+        std::time::Duration::from_secs_f64(frames as f64 / self.sample_rate as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn null_pcm() {
+        let backend = AlsaBackend::new();
+        assert!(backend.devices().iter().any(|d| d.id == "null"));
+        assert!(
+            backend
+                .open(&AlsaBackend::pcm("null"), Some(12345), 2)
+                .is_none()
+        );
+
+        let mut output = backend
+            .open(&AlsaBackend::pcm("null"), Some(44100), 2)
+            .unwrap();
+        assert_eq!(output.sample_rate(), 44100);
+        assert_eq!(output.channels(), 2);
+        // Opened with the song's channels rather than stereo.
+        let surround = backend
+            .open(&AlsaBackend::pcm("null"), Some(44100), 6)
+            .unwrap();
+        assert_eq!(surround.channels(), 6);
+
+        let state = PlayerState::new();
+        state.state.store(State::Playing as u8, Relaxed);
+        let (_producer, ring) = Ring::with_capacity(1024);
+        let mut renderer = Renderer::new(Arc::clone(&state), ring);
+        output.render(&mut renderer);
+        assert_eq!(state.last_error.load(Relaxed), RuntimeError::None as u8);
+    }
+
+    #[test]
+    fn output_thread() {
+        let dir = std::env::temp_dir();
+        let id = std::process::id();
+        let song = dir.join(format!("onmi_alsa_{id}.wav"));
+        let raw = dir.join(format!("onmi_alsa_{id}.raw"));
+        // Small enough to be decoded in full before it starts, so it never underruns.
+        let samples: Vec<f32> = (0..RING_SAMPLES / 4)
+            .map(|i| i as f32 / RING_SAMPLES as f32 * if i % 2 == 0 { 1.0 } else { -1.0 })
+            .collect();
+        let mut wav = WavWriter::create(&song, 44100, 2).unwrap();
+        wav.write_samples(&samples).unwrap();
+        drop(wav);
+
+        // The file plugin writes what the output sends straight to disk.
+        let device = AlsaBackend::pcm(&format!("file:'{}',raw", raw.display()));
+        let mut player = Player::with_backend(Arc::new(AlsaBackend::new()), device);
+        player.set_volume_reduction(100.0);
+        player.set_volume(100);
+        player.set_fade_duration(Duration::ZERO);
+        player.play_song(&song, Some(1.0), false).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        player.play();
+
+        let start = Instant::now();
+        while !player.is_finished() {
+            assert!(start.elapsed() < Duration::from_secs(2));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        player.shutdown();
+        assert_eq!(
+            player.state.last_error.load(Relaxed),
+            RuntimeError::None as u8
+        );
+
+        // The limiter's look-ahead, what was played, then silence to the end of the last period.
+        let written = std::fs::read(&raw).unwrap();
+        let (delay, written) = written.split_at(Limiter::new(44100, 2).latency() * 8);
+        assert!(delay.iter().all(|b| *b == 0));
+        let expected: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        assert_eq!(written[..expected.len()], expected);
+        assert!(written[expected.len()..].iter().all(|b| *b == 0));
+
+        let _ = std::fs::remove_file(song);
+        let _ = std::fs::remove_file(raw);
+    }
+}
@@ -0,0 +1,374 @@
+use crate::*;
+use std::sync::Arc;
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::Duration;
+
+const WAIT_MS: u64 = 50;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    pub name: String,
+    /// Whatever the backend needs to reopen the device, e.g. an endpoint id or PCM name.
+    pub id: String,
+}
+
+pub trait AudioBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn devices(&self) -> Vec<Device>;
+
+    fn default_device(&self) -> Option<Device>;
+
+    fn find(&self, device: &str) -> Option<Device> {
+        self.devices()
+            .into_iter()
+            .find(|d| d.name.as_str() == device)
+    }
+
+    /// Opens `device` at `sample_rate`, or at the device's own rate when `None`.
+    /// `channels` is the song's channel count, backends that choose one open with as many
+    /// as the device takes up to that. The output mixes whatever doesn't match.
+    fn open(
+        &self,
+        device: &Device,
+        sample_rate: Option<u32>,
+        channels: usize,
+    ) -> Option<Box<dyn OutputStream>>;
+
+    /// Like `open`, falling back to the device's own rate if it can't run at `sample_rate`.
+    /// The output resamples whatever doesn't match.
+    fn open_or_native(
+        &self,
+        device: &Device,
+        sample_rate: Option<u32>,
+        channels: usize,
+    ) -> Option<Box<dyn OutputStream>> {
+        self.open(device, sample_rate, channels)
+            .or_else(|| sample_rate.and_then(|_| self.open(device, None, channels)))
+    }
+
+    /// Polled by the output thread while following the default device.
+    /// Returns the new default device if it is no longer `current`.
+    fn default_changed(&self, current: &Device) -> Option<Device> {
+        self.default_device().filter(|d| d.id != current.id)
+    }
+
+    /// Called once on the output thread before anything is rendered.
+    fn init_thread(&self) {}
+}
+
+pub trait OutputStream: Send {
+    fn device(&self) -> &Device;
+
+    fn sample_rate(&self) -> u32;
+
+    fn channels(&self) -> usize;
+
+    /// Called in a loop by the output thread. Push based streams wait for room and render into it.
+    /// Should return within `WAIT_MS` or so, the output thread polls the mailboxes in between.
+    /// Not called while the stream's callback owns the renderer.
+    fn render(&mut self, renderer: &mut Renderer);
+
+    /// Called once before the first `render`. Callback based streams keep `renderer` for their
+    /// callback and return `None`, nothing else touches it until `stop` hands it back.
+    /// Push based streams, or ones that failed to start, return it to the output thread.
+    fn start(&mut self, renderer: Box<Renderer>) -> Option<Box<Renderer>> {
+        Some(renderer)
+    }
+
+    /// Stops the callback and returns the renderer kept by `start`, called before the stream is dropped.
+    fn stop(&mut self) -> Option<Box<Renderer>> {
+        None
+    }
+
+    /// How long until audio rendered now is heard, zero if the backend can't tell.
+    fn latency(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+pub fn default_backend() -> Arc<dyn AudioBackend> {
+    #[cfg(target_os = "windows")]
+    return Arc::new(WasapiBackend::new());
+
+    #[cfg(target_os = "macos")]
+    return Arc::new(CoreAudioBackend::new());
+
+    #[cfg(target_os = "linux")]
+    {
+        #[cfg(feature = "pulse")]
+        if let Some(pulse) = PulseBackend::new() {
+            return Arc::new(pulse);
+        }
+        Arc::new(AlsaBackend::new())
+    }
+
+    #[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
+    Arc::new(NullBackend::default())
+}
+
+pub fn run_output(
+    backend: Arc<dyn AudioBackend>,
+    state: Arc<PlayerState>,
+    output: Option<Box<dyn OutputStream>>,
+    ring: Consumer,
+) {
+    backend.init_thread();
+
+    let mut output = output;
+    let mut renderer = Some(Box::new(Renderer::new(Arc::clone(&state), ring)));
+    if let Some(output) = output.as_mut() {
+        state.output_rate.store(output.sample_rate(), Relaxed);
+        state
+            .output_channels
+            .store(output.channels() as u32, Relaxed);
+        renderer = renderer.and_then(|renderer| output.start(renderer));
+    }
+    let mut events = Events::new(&state);
+
+    loop {
+        if state.shutdown.load(Relaxed) {
+            break;
+        }
+
+        if let Some(new_output) = state.pending_output.take() {
+            swap(&mut output, new_output, &mut renderer, &state, &mut events);
+        }
+
+        if state.follow_default.load(Relaxed)
+            && let Some(current) = output.as_ref()
+            && let Some(def) = backend.default_changed(current.device())
+        {
+            let channels = state.channels.load(Relaxed) as usize;
+            if let Some(new_output) =
+                backend.open_or_native(&def, Some(current.sample_rate()), channels)
+            {
+                swap(&mut output, new_output, &mut renderer, &state, &mut events);
+            } else {
+                state.set_error(RuntimeError::OutputOpen);
+            }
+        }
+
+        // If the device can't run at the song's rate the output stays and the song is resampled.
+        let rate = state.pending_rate.swap(0, Relaxed);
+        if rate != 0
+            && let Some(current) = output.as_ref()
+            && current.sample_rate() != rate
+            && let Some(new_output) = backend.open(
+                current.device(),
+                Some(rate),
+                state.channels.load(Relaxed) as usize,
+            )
+        {
+            swap(&mut output, new_output, &mut renderer, &state, &mut events);
+        }
+
+        events.poll(&state);
+
+        let Some(out) = output.as_mut() else {
+            if let Some(renderer) = renderer.as_mut() {
+                renderer.update();
+            }
+            std::thread::sleep(Duration::from_millis(WAIT_MS));
+            continue;
+        };
+
+        match renderer.as_mut() {
+            Some(renderer) => out.render(renderer),
+            // The stream's callback has it.
+            None => std::thread::park_timeout(Duration::from_millis(WAIT_MS / 5)),
+        }
+        state
+            .latency
+            .store(out.latency().as_nanos() as u64, Relaxed);
+    }
+
+    if let Some(mut output) = output.take() {
+        drop(output.stop());
+    }
+}
+
+/// Takes the renderer back from the old output before it is dropped, then starts the new one.
+fn swap(
+    output: &mut Option<Box<dyn OutputStream>>,
+    mut new_output: Box<dyn OutputStream>,
+    renderer: &mut Option<Box<Renderer>>,
+    state: &PlayerState,
+    events: &mut Events,
+) {
+    let mut old = output.take();
+    if let Some(returned) = old.as_mut().and_then(|old| old.stop()) {
+        *renderer = Some(returned);
+    }
+    if old.as_ref().map(|o| o.device()) != Some(new_output.device()) {
+        events.push(Event::DeviceChanged(new_output.device().clone()));
+    }
+    drop(old);
+    state.output_rate.store(new_output.sample_rate(), Relaxed);
+    state
+        .output_channels
+        .store(new_output.channels() as u32, Relaxed);
+    *renderer = renderer
+        .take()
+        .and_then(|renderer| new_output.start(renderer));
+    *output = Some(new_output);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    struct FakeBackend {
+        default: Mutex<Device>,
+        opened: Mutex<Vec<(Device, Option<u32>)>>,
+        rendered: Arc<AtomicUsize>,
+    }
+
+    /// Acts like a callback based stream when opened on the "callback" device.
+    struct FakeOutput {
+        device: Device,
+        sample_rate: u32,
+        rendered: Arc<AtomicUsize>,
+        held: Option<Box<Renderer>>,
+    }
+
+    fn device(name: &str) -> Device {
+        Device {
+            name: name.to_string(),
+            id: name.to_string(),
+        }
+    }
+
+    impl AudioBackend for FakeBackend {
+        fn name(&self) -> &'static str {
+            "Fake"
+        }
+
+        fn devices(&self) -> Vec<Device> {
+            vec![device("a"), device("b")]
+        }
+
+        fn default_device(&self) -> Option<Device> {
+            Some(self.default.lock().unwrap().clone())
+        }
+
+        fn open(
+            &self,
+            device: &Device,
+            sample_rate: Option<u32>,
+            _: usize,
+        ) -> Option<Box<dyn OutputStream>> {
+            self.opened
+                .lock()
+                .unwrap()
+                .push((device.clone(), sample_rate));
+            Some(Box::new(FakeOutput {
+                device: device.clone(),
+                sample_rate: sample_rate.unwrap_or(48000),
+                rendered: Arc::clone(&self.rendered),
+                held: None,
+            }))
+        }
+    }
+
+    impl OutputStream for FakeOutput {
+        fn device(&self) -> &Device {
+            &self.device
+        }
+
+        fn sample_rate(&self) -> u32 {
+            self.sample_rate
+        }
+
+        fn channels(&self) -> usize {
+            2
+        }
+
+        fn render(&mut self, renderer: &mut Renderer) {
+            let mut buffer = [0u8; 64 * 8];
+            renderer.fill(&mut buffer, 2);
+            self.rendered.fetch_add(1, Relaxed);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        fn start(&mut self, renderer: Box<Renderer>) -> Option<Box<Renderer>> {
+            if self.device.name != "callback" {
+                return Some(renderer);
+            }
+            self.held = Some(renderer);
+            None
+        }
+
+        fn stop(&mut self) -> Option<Box<Renderer>> {
+            self.held.take()
+        }
+    }
+
+    fn wait_until(condition: impl Fn() -> bool) {
+        for _ in 0..200 {
+            if condition() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        panic!("timed out");
+    }
+
+    #[test]
+    fn player_with_fake_backend() {
+        let backend = Arc::new(FakeBackend {
+            default: Mutex::new(device("a")),
+            opened: Mutex::new(Vec::new()),
+            rendered: Arc::new(AtomicUsize::new(0)),
+        });
+
+        let mut player = Player::with_backend(backend.clone(), device("a"));
+        wait_until(|| backend.rendered.load(Relaxed) > 0);
+
+        player.set_output_device(device("b")).unwrap();
+        wait_until(|| player.state.pending_output.ptr.load(Relaxed).is_null());
+
+        *backend.default.lock().unwrap() = device("b");
+        player.follow_default_device(true).unwrap();
+        wait_until(|| player.state.pending_output.ptr.load(Relaxed).is_null());
+
+        *backend.default.lock().unwrap() = device("a");
+        wait_until(|| backend.opened.lock().unwrap().len() == 4);
+
+        player.shutdown();
+
+        let opened = backend.opened.lock().unwrap();
+        assert_eq!(opened[0], (device("a"), None));
+        assert_eq!(opened[1], (device("b"), None));
+        assert_eq!(opened[2], (device("b"), None));
+        assert_eq!(opened[3], (device("a"), Some(48000)));
+        assert_eq!(
+            player.state.last_error.load(Relaxed),
+            RuntimeError::None as u8
+        );
+    }
+
+    #[test]
+    fn callback_stream_owns_renderer() {
+        let backend = Arc::new(FakeBackend {
+            default: Mutex::new(device("a")),
+            opened: Mutex::new(Vec::new()),
+            rendered: Arc::new(AtomicUsize::new(0)),
+        });
+
+        let mut player = Player::with_backend(backend.clone(), device("callback"));
+        std::thread::sleep(Duration::from_millis(50));
+        // The output thread never renders while the callback has the renderer.
+        assert_eq!(backend.rendered.load(Relaxed), 0);
+
+        // It comes back when the stream stops, and moves on to the next one.
+        player.set_output_device(device("a")).unwrap();
+        wait_until(|| backend.rendered.load(Relaxed) > 0);
+
+        player.shutdown();
+    }
+}
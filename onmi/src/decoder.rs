@@ -1,21 +1,33 @@
-use crate::{PlayerState, State};
+use crate::{
+    Equalizer, Error, Layout, Limiter, NOT_QUEUED, PlayerState, Producer, ReplayGain,
+    ResampleQuality, Resampler, Shuffle, State,
+};
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
 use std::path::PathBuf;
-use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::Ordering::{AcqRel, Relaxed};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::{fs::File, path::Path};
 use symphonia::core::formats::{FormatReader, Track, TrackType};
-use symphonia::core::units::TimeBase;
+use symphonia::core::units::{TimeBase, Timestamp};
 use symphonia::{
     core::{
         codecs::audio::{AudioDecoder, AudioDecoderOptions},
         formats::{FormatOptions, SeekMode, SeekTo, probe::Hint},
         io::MediaSourceStream,
-        meta::MetadataOptions,
+        meta::{MetadataOptions, StandardTag},
         units::Time,
     },
     default::get_probe,
 };
 
+pub use symphonia::core::io::MediaSource;
+
+/// Size of the ring between the decoder thread and the output, about 370ms of 44.1kHz stereo.
+pub const RING_SAMPLES: usize = 1 << 15;
+const DECODE_CHUNK: usize = 4096;
+const DECODE_WAIT_MS: u64 = 2;
+
 pub struct Symphonia {
     pub format_reader: Box<dyn FormatReader>,
     pub decoder: Box<dyn AudioDecoder>,
@@ -27,29 +39,184 @@ pub struct Symphonia {
     pub pos: usize,
     pub sample_rate: u32,
     pub channels: u32,
+    /// Speaker positions of the interleaved channels.
+    pub layout: Layout,
     pub time_base: TimeBase,
-    pub path: PathBuf,
+    /// `None` when playing from a `MediaSource`.
+    pub path: Option<PathBuf>,
     pub duration: Duration,
+    /// Encoder delay in frames, cut from the start so tracks join without a gap.
+    pub delay: u64,
+    /// The frame where encoder padding starts, if the container says.
+    pub end: Option<u64>,
+    /// First frame of the decoded packet in `buffer`.
+    pub packet_frame: u64,
+    /// Used by smart crossfade to leave albums alone.
+    pub album: Option<String>,
+    /// Short name of the format that was detected, e.g. `"flac"` or `"isomp4"`.
+    pub container: &'static str,
+    /// Short name of the codec decoding the track, e.g. `"mp3"`.
+    pub codec: &'static str,
+    /// ReplayGain tags in the file itself.
+    pub replay_gain: ReplayGain,
 }
 
-impl Symphonia {
-    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
-        let file = File::open(path.as_ref())?;
-        let path = path.as_ref().to_path_buf();
-        let mss = MediaSourceStream::new(Box::new(file), Default::default());
-        let format_reader = get_probe().probe(
-            &Hint::new(),
+/// Lets a source be probed again after a failed probe dropped the stream around it.
+struct Rewind(Arc<Mutex<Box<dyn MediaSource>>>);
+
+impl Read for Rewind {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().read(buf)
+    }
+}
+
+impl Seek for Rewind {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.lock().unwrap().seek(pos)
+    }
+}
+
+impl MediaSource for Rewind {
+    fn is_seekable(&self) -> bool {
+        self.0.lock().unwrap().is_seekable()
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        self.0.lock().unwrap().byte_len()
+    }
+}
+
+/// Probes with the extension and MIME type, then each on its own, then with no hint
+/// in case they were wrong. Returns the first error if nothing can read the source.
+pub fn probe(
+    source: Box<dyn MediaSource>,
+    extension: Option<&str>,
+    mime: Option<&str>,
+    metadata: MetadataOptions,
+) -> Result<Box<dyn FormatReader>, Error> {
+    let mut hints = Vec::new();
+    for hint in [
+        (extension, mime),
+        (extension, None),
+        (None, mime),
+        (None, None),
+    ] {
+        if !hints.contains(&hint) {
+            hints.push(hint);
+        }
+    }
+
+    let source = Arc::new(Mutex::new(source));
+    let mut first_error = None;
+    for (i, (extension, mime)) in hints.into_iter().enumerate() {
+        if i > 0 && source.lock().unwrap().seek(SeekFrom::Start(0)).is_err() {
+            break;
+        }
+
+        let mut hint = Hint::new();
+        if let Some(extension) = extension {
+            hint.with_extension(extension);
+        }
+        if let Some(mime) = mime {
+            hint.mime_type(mime);
+        }
+
+        let mss = MediaSourceStream::new(Box::new(Rewind(Arc::clone(&source))), Default::default());
+        match get_probe().probe(
+            &hint,
             mss,
             FormatOptions::default()
                 .prebuild_seek_index(false)
                 .seek_index_fill_period_ms(20),
-            MetadataOptions::default(),
-        )?;
+            metadata.clone(),
+        ) {
+            Ok(format_reader) => return Ok(format_reader),
+            Err(err) => {
+                first_error.get_or_insert(Error::from(err));
+            }
+        }
+    }
+
+    Err(first_error.unwrap_or(Error::NoAudioTrack))
+}
+
+/// A song waiting to be joined onto the end of the current one.
+pub struct NextSong {
+    pub decoder: Symphonia,
+    pub gain: f32,
+    /// Queue id, or `NOT_QUEUED`.
+    pub id: u64,
+}
+
+impl Symphonia {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::open(path, None)
+    }
+
+    /// Probes with the file extension and `mime` as hints, see `probe`.
+    pub fn open<P: AsRef<Path>>(path: P, mime: Option<&str>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let extension = path.extension().and_then(|e| e.to_str());
+        let format_reader = probe(Box::new(file), extension, mime, MetadataOptions::default())?;
+        let mut decoder = Self::from_reader(format_reader)?;
+        decoder.path = Some(path.to_path_buf());
+        Ok(decoder)
+    }
+
+    /// Audio held in memory, e.g. a `Vec<u8>` or `Arc<[u8]>`.
+    pub fn from_bytes<T>(bytes: T, hint: Option<&str>) -> Result<Self, Error>
+    where
+        T: AsRef<[u8]> + Send + Sync + 'static,
+    {
+        Self::from_source(Box::new(Cursor::new(bytes)), hint)
+    }
+
+    /// `hint` is a file extension like `"flac"` or a MIME type like `"audio/flac"`,
+    /// without one the format is guessed from the data.
+    pub fn from_source(source: Box<dyn MediaSource>, hint: Option<&str>) -> Result<Self, Error> {
+        let (extension, mime) = match hint {
+            Some(mime) if mime.contains('/') => (None, Some(mime)),
+            extension => (extension, None),
+        };
+        let format_reader = probe(source, extension, mime, MetadataOptions::default())?;
+        Self::from_reader(format_reader)
+    }
+
+    fn from_reader(mut format_reader: Box<dyn FormatReader>) -> Result<Self, Error> {
+        let mut album = None;
+        let mut replay_gain = ReplayGain::default();
+        if let Some(revision) = format_reader.metadata().skip_to_latest() {
+            for std in revision
+                .media
+                .tags
+                .iter()
+                .filter_map(|tag| tag.std.as_ref())
+            {
+                replay_gain.read_tag(std);
+                if let StandardTag::Album(tag) = std
+                    && album.is_none()
+                {
+                    album = Some(tag.to_string());
+                }
+            }
+        }
         let track = format_reader
             .default_track(TrackType::Audio)
-            .unwrap()
+            .ok_or(Error::NoAudioTrack)?
             .to_owned();
-        let time_base = track.time_base.unwrap();
+        let codec_params = track
+            .codec_params
+            .as_ref()
+            .and_then(|params| params.audio())
+            .ok_or(Error::NoAudioTrack)?;
+        let sample_rate = codec_params
+            .sample_rate
+            .filter(|rate| *rate != 0)
+            .ok_or_else(|| Error::UnsupportedFormat(String::from("track has no sample rate")))?;
+        let time_base = track
+            .time_base
+            .ok_or_else(|| Error::UnsupportedFormat(String::from("track has no time base")))?;
         let duration = track
             .duration
             .or(track.num_frames.map(symphonia::core::units::Duration::new))
@@ -57,21 +224,34 @@ impl Symphonia {
             .map(|duration_ts| time_base.calc_time_saturating(duration_ts))
             .map(|time| Duration::from_nanos(time.as_nanos() as u64))
             .unwrap_or_default();
-        let codec_params = track
-            .codec_params
+        let layout = codec_params
+            .channels
             .as_ref()
-            .and_then(|params| params.audio())
-            .unwrap();
+            .map(Layout::from_symphonia)
+            .unwrap_or(Layout::STEREO);
         let decoder = symphonia::default::get_codecs()
             .make_audio_decoder(codec_params, &AudioDecoderOptions::default())?;
+        let container = format_reader.format_info().short_name;
+        let codec = decoder.codec_info().short_name;
+
+        // Delay and padding are counted in frames, which only line up
+        // with packet timestamps when the time base is one frame.
+        let in_frames = time_base == TimeBase::new(1, sample_rate);
+        let delay = match track.delay {
+            Some(delay) if in_frames => delay as u64,
+            _ => 0,
+        };
+        let end = match (track.num_frames, track.padding) {
+            (Some(frames), Some(padding)) if in_frames => {
+                Some(frames.saturating_sub(padding as u64))
+            }
+            _ => None,
+        };
 
         Ok(Self {
-            sample_rate: codec_params.sample_rate.unwrap(),
-            channels: codec_params
-                .channels
-                .as_ref()
-                .map(|c| c.count() as u32)
-                .unwrap_or(2),
+            sample_rate,
+            channels: layout.channels() as u32,
+            layout,
             format_reader,
             decoder,
             track,
@@ -81,47 +261,142 @@ impl Symphonia {
             buffer_len: 0,
             pos: 0,
             time_base,
-            path,
+            path: None,
             duration,
+            delay,
+            end,
+            packet_frame: 0,
+            album,
+            container,
+            codec,
+            replay_gain,
         })
     }
 
-    pub fn seek(&mut self, pos: Duration, state: &PlayerState) {
+    /// Frame of the next sample out of `next_sample`.
+    pub fn position(&self) -> u64 {
+        self.packet_frame + (self.pos / (self.channels as usize).max(1)) as u64
+    }
+
+    /// Frames left going by the duration, `None` if the duration is unknown.
+    pub fn remaining_frames(&self) -> Option<u64> {
+        if self.duration.is_zero() {
+            return None;
+        }
+        let total = self
+            .end
+            .unwrap_or((self.duration.as_secs_f64() * self.sample_rate as f64).round() as u64);
+        Some(total.saturating_sub(self.position()))
+    }
+
+    /// Range of frames in a packet starting at `ts` that is left after cutting the encoder delay and padding.
+    fn audible(&self, ts: Timestamp, frames: usize) -> (usize, usize) {
+        let first = ts.get().saturating_sub(self.track.start_ts.get()).max(0) as u64;
+        let start = self.delay.saturating_sub(first).min(frames as u64) as usize;
+        let end = match self.end {
+            Some(end) => end.saturating_sub(first).min(frames as u64) as usize,
+            None => frames,
+        };
+        (start, end.max(start))
+    }
+
+    /// Goes back to the start without touching the player state, for a queued song that was decoded too early.
+    pub fn rewind(&mut self) {
+        let _ = self.format_reader.seek(
+            SeekMode::Coarse,
+            SeekTo::Time {
+                time: Time::from_nanos_u64(0),
+                track_id: None,
+            },
+        );
+        self.decoder.reset();
+        self.buffer_len = 0;
+        self.pos = 0;
+        self.finished = false;
+        self.error_count = 0;
+        self.packet_frame = 0;
+    }
+
+    /// Coarse seeks resume at the start of the packet holding `pos`, accurate ones decode
+    /// and drop frames up to `pos`. Either way the output position is set to where it landed.
+    pub fn seek(&mut self, pos: Duration, accurate: bool, state: &PlayerState) {
         if pos >= self.duration {
             self.finished = true;
             state.mark_finished();
-            state
-                .elapsed
-                .store(self.duration.as_nanos() as u64, Relaxed);
+            let end = self.duration.as_secs_f64() * self.sample_rate as f64;
+            state.position.store(end.round() as u64, Relaxed);
             return;
         }
 
-        if self
-            .format_reader
-            .seek(
-                SeekMode::Coarse,
-                SeekTo::Time {
-                    time: Time::from_nanos_u64(pos.as_nanos() as u64),
-                    track_id: None,
-                },
-            )
-            .is_ok()
-        {
-            self.decoder.reset();
-            self.buffer_len = 0;
-            self.pos = 0;
-            self.finished = false;
-            state.finished.store(false, Relaxed);
+        let mode = if accurate {
+            SeekMode::Accurate
+        } else {
+            SeekMode::Coarse
+        };
+        let target = (pos.as_secs_f64() * self.sample_rate as f64) as u64;
+        let seeked = self.format_reader.seek(
+            mode,
+            SeekTo::Time {
+                time: Time::from_nanos_u64(pos.as_nanos() as u64),
+                track_id: None,
+            },
+        );
+        let Ok(seeked) = seeked else {
+            // Carries on from where it was decoding, the ring was flushed regardless.
+            state.position.store(self.position(), Relaxed);
+            return;
+        };
+
+        self.decoder.reset();
+        self.buffer_len = 0;
+        self.pos = 0;
+        self.finished = false;
+        state.finished.store(false, Relaxed);
+
+        self.packet_frame = self
+            .time_base
+            .calc_time(seeked.actual_ts)
+            .map(|time| (time.as_secs_f64().max(0.0) * self.sample_rate as f64).round() as u64)
+            .unwrap_or(target);
+        if accurate {
+            self.skip_to(target);
         }
+        state.position.store(self.position(), Relaxed);
+    }
 
-        state.elapsed.store(pos.as_nanos() as u64, Relaxed);
+    /// Decodes and drops frames until `position()` reaches `frame`, or the song ends.
+    fn skip_to(&mut self, frame: u64) {
+        let channels = (self.channels as usize).max(1);
+        while self.position() < frame {
+            if self.pos >= self.buffer_len && !self.fill_packet() {
+                return;
+            }
+            let skip = (frame.saturating_sub(self.position()) as usize).saturating_mul(channels);
+            self.pos = self.pos.saturating_add(skip).min(self.buffer_len);
+        }
     }
 
-    pub fn next_sample(&mut self, state: &PlayerState) -> Option<f32> {
-        if self.pos >= self.buffer_len {
-            if !self.fill_packet(state) {
-                return None;
+    /// Copies whole interleaved frames into `out` and returns how many.
+    /// Fewer than fit means the song ended.
+    pub fn read_frames(&mut self, out: &mut [f32]) -> usize {
+        let channels = (self.channels as usize).max(1);
+        let wanted = out.len() / channels * channels;
+        let mut written = 0;
+        while written < wanted {
+            if self.pos >= self.buffer_len && !self.fill_packet() {
+                break;
             }
+            let n = (self.buffer_len - self.pos).min(wanted - written);
+            out[written..written + n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+            self.pos += n;
+            written += n;
+        }
+        written / channels
+    }
+
+    pub fn next_sample(&mut self) -> Option<f32> {
+        if self.pos >= self.buffer_len && !self.fill_packet() {
+            return None;
         }
 
         let sample = self.buffer[self.pos];
@@ -129,7 +404,7 @@ impl Symphonia {
         Some(sample)
     }
 
-    fn fill_packet(&mut self, state: &PlayerState) -> bool {
+    fn fill_packet(&mut self) -> bool {
         if self.error_count > 2 || self.finished {
             return false;
         }
@@ -145,28 +420,25 @@ impl Symphonia {
             }
             Err(_) => {
                 self.error_count += 1;
-                return self.fill_packet(state);
+                return self.fill_packet();
             }
         };
 
         if next_packet.track_id != self.track.id {
-            return self.fill_packet(state);
+            return self.fill_packet();
         }
 
-        if let Some(time) = self.time_base.calc_time(next_packet.pts) {
-            let time = time.as_secs_f64().max(0.0);
-            let elapsed = Duration::from_secs_f64(time);
-            if elapsed > self.duration {
-                self.finished = true;
-                return false;
-            }
-
-            if state.state.load(Relaxed) != State::Stopped as u8 {
-                state.elapsed.store(elapsed.as_nanos() as u64, Relaxed);
-            }
-        } else {
-            unreachable!("Packet is timeless, one cannot be timeless...? Only me 🗿")
+        // A timestamp that overflows is a broken packet, skip it like one that fails to decode.
+        let Some(time) = self.time_base.calc_time(next_packet.pts) else {
+            self.error_count += 1;
+            return self.fill_packet();
+        };
+        let time = time.as_secs_f64().max(0.0);
+        if Duration::try_from_secs_f64(time).unwrap_or(Duration::MAX) > self.duration {
+            self.finished = true;
+            return false;
         }
+        self.packet_frame = (time * self.sample_rate as f64).round() as u64;
 
         match self.decoder.decode(&next_packet) {
             Ok(decoded) => {
@@ -175,14 +447,477 @@ impl Symphonia {
                     self.buffer.resize(n, 0.0);
                 }
                 decoded.copy_to_slice_interleaved(&mut self.buffer[..n]);
-                self.buffer_len = n;
-                self.pos = 0;
+
+                let channels = (self.channels as usize).max(1);
+                let (start, end) = self.audible(next_packet.pts, n / channels);
+                self.pos = start * channels;
+                self.buffer_len = end * channels;
+                if self.pos >= self.buffer_len {
+                    // All delay or padding.
+                    return self.fill_packet();
+                }
                 true
             }
             Err(_) => {
                 self.error_count += 1;
-                self.fill_packet(state)
+                self.fill_packet()
             }
         }
     }
 }
+
+#[repr(u8)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum FadeCurve {
+    Linear = 0,
+    /// Keeps the summed power constant, no dip in the middle for unrelated songs.
+    EqualPower = 1,
+    /// Smoothstep, lingers on both songs and moves quickly through the middle.
+    SCurve = 2,
+}
+
+impl FadeCurve {
+    pub fn from_u8(curve: u8) -> Self {
+        match curve {
+            x if x == FadeCurve::EqualPower as u8 => FadeCurve::EqualPower,
+            x if x == FadeCurve::SCurve as u8 => FadeCurve::SCurve,
+            _ => FadeCurve::Linear,
+        }
+    }
+
+    /// Gains for the outgoing and incoming song at `t` from 0 to 1.
+    pub fn gains(self, t: f32) -> (f32, f32) {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            FadeCurve::Linear => (1.0 - t, t),
+            FadeCurve::EqualPower => {
+                let angle = t * std::f32::consts::FRAC_PI_2;
+                (angle.cos(), angle.sin())
+            }
+            FadeCurve::SCurve => {
+                let s = t * t * (3.0 - 2.0 * t);
+                (1.0 - s, s)
+            }
+        }
+    }
+}
+
+/// The song fading out under the current one.
+struct Fade {
+    from: Symphonia,
+    from_id: u64,
+    /// The output applies the new song's gain from the boundary on, this makes up the difference.
+    ratio: f32,
+    frame: u64,
+    frames: u64,
+    curve: FadeCurve,
+}
+
+/// Hands `song` to the output at a boundary and makes it the decoder, returns the old one.
+fn join(
+    state: &PlayerState,
+    ring: &mut Producer,
+    decoder: &mut Symphonia,
+    song: NextSong,
+    gapless: bool,
+) -> Symphonia {
+    state.next_gain.store(song.gain.to_bits(), Relaxed);
+    state
+        .next_duration
+        .store(song.decoder.duration.as_nanos() as u64, Relaxed);
+    state
+        .next_sample_rate
+        .store(song.decoder.sample_rate, Relaxed);
+    state.next_gapless.store(gapless, Relaxed);
+    state.next_id.store(song.id, Relaxed);
+    ring.mark_boundary();
+    std::mem::replace(decoder, song.decoder)
+}
+
+/// Opens the song after `current` in the queue, skipping any that fail to open.
+fn next_from_queue(state: &PlayerState, current: u64) -> Option<NextSong> {
+    if current == NOT_QUEUED {
+        return None;
+    }
+
+    let tries = state.queue.lock().unwrap().len();
+    let mut id = current;
+    for _ in 0..tries {
+        let (next, path, tags, in_order) = {
+            let queue = state.queue.lock().unwrap();
+            let next = queue.next_after(Some(id))?;
+            let entry = queue.entry(next)?;
+            let in_order = queue.shuffle != Shuffle::Songs;
+            (
+                next,
+                entry.song.path.clone(),
+                entry.song.replay_gain(),
+                in_order,
+            )
+        };
+
+        if let Ok(decoder) = Symphonia::new(&path) {
+            let tags = if tags.is_empty() {
+                decoder.replay_gain
+            } else {
+                tags
+            };
+            return Some(NextSong {
+                gain: state.replay_gain(&tags, in_order),
+                decoder,
+                id: next,
+            });
+        }
+        id = next;
+    }
+    None
+}
+
+/// Rates, channels and quality of the last resampler handed to the output.
+type ResamplerKey = (u32, u32, usize, ResampleQuality);
+
+/// Builds a resampler for the output whenever the song and output rates stop matching or
+/// the channels or quality change, so the output never builds one itself.
+/// Also drops the one the output sent back.
+fn prepare_resampler(state: &PlayerState, ring: &Producer, built: &mut Option<ResamplerKey>) {
+    drop(state.retired_resampler.take());
+
+    let Some((from, to)) = state.resample_rates() else {
+        *built = None;
+        return;
+    };
+    let quality = ResampleQuality::from_u8(state.resample_quality.load(Relaxed));
+    let key = (from, to, ring.channels(), quality);
+    if *built != Some(key) {
+        *built = Some(key);
+        state
+            .pending_resampler
+            .publish(Resampler::new(from, to, ring.channels(), quality));
+    }
+}
+
+/// Rebuilds the equalizer when the output's rate changes, the output can't.
+/// Also drops the one the output sent back.
+fn prepare_equalizer(state: &PlayerState) {
+    drop(state.retired_eq.take());
+
+    let rate = state.processing_rate();
+    let mut current = state.eq_preset.lock().unwrap();
+    if rate != 0 && current.1 != rate {
+        state
+            .pending_eq
+            .publish(Equalizer::transition(&current.0, &current.0, rate));
+        current.1 = rate;
+    }
+}
+
+/// Builds a limiter whenever the output's rate or channels change, the output can't.
+/// Also drops the one the output sent back.
+fn prepare_limiter(state: &PlayerState, built: &mut (u32, usize)) {
+    drop(state.retired_limiter.take());
+
+    let key = (
+        state.processing_rate(),
+        state.output_channels.load(Relaxed) as usize,
+    );
+    if key.0 != 0 && key.1 != 0 && *built != key {
+        *built = key;
+        state.pending_limiter.publish(Limiter::new(key.0, key.1));
+    }
+}
+
+/// Decodes ahead of the output into `ring`. Owns the decoder, new decoders and seeks
+/// are picked up here and the output only ever copies out of the ring.
+///
+/// When a song ends the queued one is pushed straight after it with a boundary in between,
+/// so the output switches on the exact sample. If the format changes the output is given the
+/// chance to drain and reopen first. With a crossfade the boundary goes where the fade starts
+/// and the old song is mixed in here until it ends.
+pub fn run_decoder(state: Arc<PlayerState>, mut ring: Producer) {
+    let mut decoder: Option<Symphonia> = None;
+    let mut current_id = NOT_QUEUED;
+    // The song before the pending boundary, kept in case it is seeked before the output gets there.
+    let mut previous: Option<(Symphonia, u64)> = None;
+    // A queued song that was put back because of such a seek, or that can't be faded into.
+    let mut next: Option<NextSong> = None;
+    // The song after the pending boundary needs a different output format.
+    let mut reformat = false;
+    let mut fade: Option<Fade> = None;
+    // The queued song was already looked at for this crossfade.
+    let mut fade_checked = false;
+    let mut samples = Vec::with_capacity(DECODE_CHUNK);
+    // Decoded from the song being faded out.
+    let mut old_samples = Vec::with_capacity(DECODE_CHUNK);
+    let mut ended = false;
+    let mut resampler = None;
+    let mut limiter = (0, 0);
+
+    loop {
+        if state.shutdown.load(Relaxed) {
+            break;
+        }
+
+        if let Some(new_decoder) = state.pending_decoder.take() {
+            if !ring.flush(new_decoder.layout, &state.shutdown) {
+                break;
+            }
+            state.finished.store(false, Relaxed);
+            state.position.store(0, Relaxed);
+            decoder = Some(new_decoder);
+            current_id = state.playing_id.load(Relaxed);
+            previous = None;
+            next = None;
+            reformat = false;
+            fade = None;
+            fade_checked = false;
+            ended = false;
+            state.decoder_pending.store(false, Relaxed);
+            state.songs_started.fetch_add(1, Relaxed);
+        }
+
+        if !ring.ring.boundary_pending() {
+            previous = None;
+            if reformat && let Some(decoder) = decoder.as_ref() {
+                state
+                    .channels
+                    .store(decoder.layout.channels() as u32, Relaxed);
+                state.pending_rate.store(decoder.sample_rate, Relaxed);
+                if !ring.flush(decoder.layout, &state.shutdown) {
+                    break;
+                }
+                reformat = false;
+                state.decoder_pending.store(false, Relaxed);
+            }
+        }
+
+        // The flush goes out before the seek is taken, so the output never
+        // sees audio from before the seek without something still pending.
+        if state.seek.load(Relaxed) != u64::MAX {
+            if let Some(decoder) = decoder.as_mut() {
+                let fading = fade.take().map(|fade| (fade.from, fade.from_id));
+                let before_boundary = ring.ring.boundary_pending();
+                if let Some((previous, previous_id)) =
+                    previous.take().or(fading.filter(|_| before_boundary))
+                {
+                    let mut queued = std::mem::replace(decoder, previous);
+                    queued.rewind();
+                    next = Some(NextSong {
+                        decoder: queued,
+                        gain: f32::from_bits(state.next_gain.load(Relaxed)),
+                        id: current_id,
+                    });
+                    current_id = previous_id;
+                    reformat = false;
+                }
+                if !ring.flush(decoder.layout, &state.shutdown) {
+                    break;
+                }
+                let seek = state.seek.swap(u64::MAX, AcqRel);
+                let accurate = state.accurate_seek.load(Relaxed);
+                decoder.seek(Duration::from_nanos(seek), accurate, &state);
+                state
+                    .seeked_to
+                    .store(state.rendered().as_nanos() as u64, Relaxed);
+                state.seeks.fetch_add(1, Relaxed);
+                fade_checked = false;
+                ended = false;
+            } else {
+                state.seek.store(u64::MAX, Relaxed);
+            }
+        }
+
+        prepare_resampler(&state, &ring, &mut resampler);
+        prepare_equalizer(&state);
+        prepare_limiter(&state, &mut limiter);
+        // Freed here, the output can't.
+        drop(state.retired_dsp.take());
+
+        let Some(decoder) = decoder.as_mut() else {
+            std::thread::sleep(Duration::from_millis(DECODE_WAIT_MS));
+            continue;
+        };
+
+        if ended && !reformat && !ring.ring.ended() && !ring.ring.boundary_pending() {
+            let song = state
+                .pending_next
+                .take()
+                .or_else(|| next.take())
+                .or_else(|| next_from_queue(&state, current_id));
+            match song {
+                Some(song) => {
+                    let gapless = song.decoder.sample_rate == decoder.sample_rate
+                        && song.decoder.layout == decoder.layout;
+                    let id = std::mem::replace(&mut current_id, song.id);
+                    previous = Some((join(&state, &mut ring, decoder, song, gapless), id));
+                    reformat = !gapless;
+                    fade_checked = false;
+                    ended = false;
+                }
+                None => ring.set_end(),
+            }
+            continue;
+        }
+
+        // Queued after the end went out, while the output plays out what's buffered.
+        if ended && ring.ring.ended() && !state.pending_next.is_empty() {
+            // Joined on the next pass, unless the output already finished.
+            if !ring.reopen()
+                && let Some(song) = state.pending_next.take()
+            {
+                state.load(&song.decoder, song.gain, song.id);
+                state.pending_rate.store(song.decoder.sample_rate, Relaxed);
+                state.state.store(State::Playing as u8, Relaxed);
+                state.pending_decoder.publish(song.decoder);
+            }
+            continue;
+        }
+
+        let crossfade = state.crossfade_ms.load(Relaxed) as u64 * decoder.sample_rate as u64 / 1000;
+        if crossfade > 0
+            && fade.is_none()
+            && !fade_checked
+            && !ended
+            && !reformat
+            && !ring.ring.boundary_pending()
+            && let Some(remaining) = decoder.remaining_frames()
+            && remaining <= crossfade
+            && let Some(song) = state
+                .pending_next
+                .take()
+                .or_else(|| next.take())
+                .or_else(|| next_from_queue(&state, current_id))
+        {
+            fade_checked = true;
+            let same_album = state.smart_crossfade.load(Relaxed)
+                && decoder.album.is_some()
+                && decoder.album == song.decoder.album;
+
+            if song.decoder.sample_rate == decoder.sample_rate
+                && song.decoder.layout == decoder.layout
+                && !same_album
+            {
+                let ratio = f32::from_bits(state.gain.load(Relaxed)) / song.gain.max(f32::EPSILON);
+                let curve = FadeCurve::from_u8(state.crossfade_curve.load(Relaxed));
+                let from_id = std::mem::replace(&mut current_id, song.id);
+                fade = Some(Fade {
+                    from: join(&state, &mut ring, decoder, song, true),
+                    from_id,
+                    ratio,
+                    frame: 0,
+                    frames: remaining.max(1),
+                    curve,
+                });
+                fade_checked = false;
+            } else {
+                // Joined at the end instead.
+                next = Some(song);
+            }
+        }
+
+        let channels = (decoder.channels as usize).max(1);
+        let wanted = ring.free().min(DECODE_CHUNK) / channels * channels;
+        if ended || reformat || wanted == 0 {
+            std::thread::sleep(Duration::from_millis(DECODE_WAIT_MS));
+            continue;
+        }
+
+        samples.resize(wanted, 0.0);
+        let frames = decoder.read_frames(&mut samples);
+        ended = frames * channels < wanted;
+        samples.truncate(frames * channels);
+
+        if let Some(f) = fade.as_mut() {
+            let n = frames.min((f.frames - f.frame) as usize);
+            old_samples.resize(n * channels, 0.0);
+            let read = f.from.read_frames(&mut old_samples);
+            // The old song ending early fades against silence.
+            old_samples[read * channels..].fill(0.0);
+
+            for (frame, old) in samples
+                .chunks_exact_mut(channels)
+                .zip(old_samples.chunks_exact(channels))
+            {
+                let (out, into) = f.curve.gains(f.frame as f32 / f.frames as f32);
+                for (sample, old) in frame.iter_mut().zip(old) {
+                    *sample = old * f.ratio * out + *sample * into;
+                }
+                f.frame += 1;
+            }
+            if f.frame >= f.frames || ended {
+                fade = None;
+            }
+        }
+
+        ring.push(&samples);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{flac_metadata, metadata};
+
+    #[test]
+    fn fade_curves() {
+        for curve in [FadeCurve::Linear, FadeCurve::EqualPower, FadeCurve::SCurve] {
+            assert_eq!(curve.gains(0.0), (1.0, 0.0));
+            let (out, into) = curve.gains(1.0);
+            assert!(out.abs() < 1e-6 && (into - 1.0).abs() < 1e-6);
+        }
+
+        for i in 0..=10 {
+            let t = i as f32 / 10.0;
+            let (out, into) = FadeCurve::EqualPower.gains(t);
+            assert!((out * out + into * into - 1.0).abs() < 1e-5);
+            let (out, into) = FadeCurve::SCurve.gains(t);
+            assert!((out + into - 1.0).abs() < 1e-6);
+        }
+        assert!(FadeCurve::SCurve.gains(0.1).1 < FadeCurve::Linear.gains(0.1).1);
+    }
+
+    #[test]
+    fn malformed_files() {
+        let file = |name: &str, bytes: &[u8]| {
+            let path = std::env::temp_dir().join(format!("onmi_{}_{name}", std::process::id()));
+            std::fs::write(&path, bytes).unwrap();
+            path
+        };
+
+        let missing = std::env::temp_dir().join("onmi_missing.wav");
+        assert!(matches!(Symphonia::new(&missing), Err(Error::Io(_))));
+        assert!(matches!(
+            metadata("no_extension", false, false),
+            Err(Error::UnsupportedFormat(_))
+        ));
+
+        // RIFF with samples but no format chunk, so no sample rate.
+        let mut wav = b"RIFF\0\0\0\0WAVEdata\x08\0\0\0".to_vec();
+        wav.extend_from_slice(&[0; 8]);
+        assert!(Symphonia::new(file("no_format.wav", &wav)).is_err());
+
+        // Last block is a Vorbis comment, cut off in the middle of a comment.
+        let mut flac = b"fLaC\x84\0\0\x40".to_vec();
+        flac.extend_from_slice(&[0, 0, 0, 0, 1, 0, 0, 0, 32, 0, 0, 0]);
+        flac.extend_from_slice(b"TITLE=");
+        let truncated = file("truncated.flac", &flac);
+        assert!(matches!(
+            flac_metadata(&truncated, false),
+            Err(Error::Io(_))
+        ));
+        assert!(metadata(&truncated, true, false).is_err());
+
+        // A comment longer than the whole file.
+        flac.truncate(16);
+        flac.extend_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+        let oversized = file("oversized.flac", &flac);
+        assert!(matches!(
+            flac_metadata(&oversized, false),
+            Err(Error::Decode(_))
+        ));
+
+        assert!(matches!(
+            flac_metadata(file("not.flac", b"RIFF"), false),
+            Err(Error::UnsupportedFormat(_))
+        ));
+    }
+}
@@ -0,0 +1,75 @@
+/// An effect in the render path, set on the player with `Player::set_dsp_chain`.
+///
+/// Runs on the output thread, or inside the audio callback, so `process` shouldn't
+/// lock, allocate or block. Audio arrives after channel mixing, resampling and the
+/// equalizer, before the volume is applied.
+pub trait Processor: Send {
+    /// Called before the first `process` and again whenever the output's rate or channels change.
+    /// `Player::set_dsp_chain` prepares on the caller's thread, but a later change of output
+    /// prepares on the output thread or inside the audio callback. Nothing is playing then,
+    /// though it should still be quick.
+    fn prepare(&mut self, sample_rate: u32, channels: usize);
+
+    /// Processes interleaved frames in place, with the channels given to `prepare`.
+    fn process(&mut self, samples: &mut [f32]);
+
+    /// Forgets any audio held from before, called when playback jumps on a seek or a new song.
+    /// Songs that follow each other gaplessly play as one stream and don't reset.
+    fn reset(&mut self);
+}
+
+/// Processors run one after another, in the order they were pushed.
+#[derive(Default)]
+pub struct DspChain {
+    processors: Vec<Box<dyn Processor>>,
+    /// Rate and channels of the last `prepare`.
+    prepared: (u32, usize),
+}
+
+impl DspChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, processor: impl Processor + 'static) {
+        self.processors.push(Box::new(processor));
+    }
+
+    pub fn with(mut self, processor: impl Processor + 'static) -> Self {
+        self.push(processor);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.processors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.processors.is_empty()
+    }
+
+    pub fn is_prepared(&self, sample_rate: u32, channels: usize) -> bool {
+        self.prepared == (sample_rate, channels)
+    }
+}
+
+impl Processor for DspChain {
+    fn prepare(&mut self, sample_rate: u32, channels: usize) {
+        self.prepared = (sample_rate, channels);
+        for processor in &mut self.processors {
+            processor.prepare(sample_rate, channels);
+        }
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for processor in &mut self.processors {
+            processor.process(samples);
+        }
+    }
+
+    fn reset(&mut self) {
+        for processor in &mut self.processors {
+            processor.reset();
+        }
+    }
+}
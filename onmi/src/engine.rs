@@ -1,71 +1,511 @@
-use crate::{PlayerState, State, Symphonia};
+use crate::{
+    Consumer, Dither, DspChain, Equalizer, Layout, Limiter, MAX_TAPS, Mix, PlayerState, Processor,
+    Quantizer, Resampler, SampleFormat, State,
+};
+use std::sync::Arc;
 use std::sync::atomic::Ordering::Relaxed;
+use std::time::Duration;
 
-pub fn fill_f32_le(
-    state: &PlayerState,
-    decoder: &mut Option<Symphonia>,
-    buffer: &mut [u8],
-    channels: usize,
-) {
-    buffer.fill(0);
+/// Samples copied out of the ring at a time.
+const FILL_BLOCK: usize = 1024;
 
-    if state.state.load(Relaxed) != State::Playing as u8
-        || state.finished.load(Relaxed)
-        || state.decoder_pending.load(Relaxed)
-        || decoder.is_none()
-    {
-        return;
+/// Gain ramps that keep pauses, seeks and volume changes from clicking.
+pub struct Fader {
+    /// Frames a fade takes, zero makes every change at once.
+    pub length: usize,
+    /// 1.0 while playing, down to 0.0 once paused, stopped or ready to seek.
+    pub level: f32,
+    /// The volume being heard, following `PlayerState::volume`, or silence while muted.
+    pub volume: f32,
+    volume_target: f32,
+    volume_step: f32,
+}
+
+impl Fader {
+    pub fn new(volume: f32) -> Self {
+        Self {
+            length: 0,
+            level: 0.0,
+            volume,
+            volume_target: volume,
+            volume_step: 0.0,
+        }
+    }
+
+    /// The volume gets to `target` over one fade, however far away it is.
+    /// Nothing is heard while the level is down, so then it jumps.
+    pub fn follow_volume(&mut self, target: f32) {
+        if self.length == 0 || self.level == 0.0 {
+            self.volume = target;
+        } else if target != self.volume_target {
+            self.volume_step = (target - self.volume).abs() / self.length as f32;
+        }
+        self.volume_target = target;
     }
 
-    let volume = f32::from_bits(state.volume.load(Relaxed));
-    let gain = f32::from_bits(state.gain.load(Relaxed));
-    let scale = volume * gain;
-    let frame_bytes = size_of::<f32>() * channels;
-    let mut src_frame = [0f32; 16];
+    /// Frames left before a fade out is silent.
+    pub fn frames_to_silence(&self) -> usize {
+        (self.level * self.length as f32).ceil() as usize
+    }
 
-    'outer: for bytes in buffer.chunks_mut(frame_bytes) {
-        if state.finished.load(Relaxed) {
-            break;
+    /// Moves the level on by a frame, towards full level while `playing`.
+    pub fn advance_level(&mut self, playing: bool) {
+        let step = 1.0 / self.length.max(1) as f32;
+        self.level = if playing {
+            (self.level + step).min(1.0)
+        } else {
+            (self.level - step).max(0.0)
+        };
+        // Left over rounding would otherwise take a frame of its own.
+        if self.level < step * 0.5 {
+            self.level = 0.0;
+        } else if self.level > 1.0 - step * 0.5 {
+            self.level = 1.0;
         }
+    }
 
-        let Some(dec) = decoder.as_mut() else {
-            break;
+    /// Moves the volume on by a frame towards its target.
+    pub fn advance_volume(&mut self) {
+        self.volume = if self.volume < self.volume_target {
+            (self.volume + self.volume_step).min(self.volume_target)
+        } else {
+            (self.volume - self.volume_step).max(self.volume_target)
         };
+    }
+}
 
-        let src_ch = (dec.channels as usize).clamp(1, 16);
-        for i in 0..src_ch {
-            match dec.next_sample(state) {
-                Some(sample) => src_frame[i] = sample,
-                None => {
-                    state.mark_finished();
-                    break 'outer;
-                }
+/// The output side of the decoder ring. Lives on the output thread,
+/// or inside the audio callback for callback based streams.
+pub struct Renderer {
+    pub state: Arc<PlayerState>,
+    pub ring: Consumer,
+    /// Kept between fills while the song and output rates differ.
+    resampler: Option<Box<Resampler>>,
+    quantizer: Quantizer,
+    /// Built by `Player::set_equalizer`, or by the decoder thread when the rate changes.
+    equalizer: Box<Equalizer>,
+    /// Set with `Player::set_dsp_chain`, runs after the equalizer.
+    dsp_chain: Box<DspChain>,
+    /// Runs last, after the volume. Always running, `PlayerState::limiter` crossfades it in and out.
+    /// Built by the decoder thread for the output's rate and channels.
+    limiter: Box<Limiter>,
+    /// Rate and channels the processors were last prepared for.
+    prepared: (u32, usize),
+    /// Maps the ring's layout onto the output's channels, built for `mixed`.
+    mix: Mix,
+    mixed: (Layout, usize),
+    fader: Fader,
+}
+
+impl Renderer {
+    pub fn new(state: Arc<PlayerState>, ring: Consumer) -> Self {
+        let mut limiter = Box::new(Limiter::new(0, 2));
+        limiter.set_enabled(state.limiter.load(Relaxed));
+        limiter.reset();
+        Self {
+            ring,
+            resampler: None,
+            quantizer: Quantizer::new(SampleFormat::F32, Dither::Tpdf),
+            equalizer: Box::new(Equalizer::new(0, 2)),
+            dsp_chain: Box::new(DspChain::new()),
+            limiter,
+            prepared: (0, 0),
+            mix: Mix::new(Layout::STEREO, Layout::STEREO),
+            mixed: (Layout::STEREO, 2),
+            fader: Fader::new(f32::from_bits(state.volume.load(Relaxed))),
+            state,
+        }
+    }
+
+    /// Acknowledges a flush from the decoder thread, which happens on a new decoder or a seek.
+    /// Returns true if one happened, so audio queued in the device can be dropped as well.
+    /// A seek waits until what was playing has faded out.
+    pub fn update(&mut self) -> bool {
+        self.prepare_resampler();
+        self.prepare_limiter();
+        if self.fading_for_seek() {
+            return false;
+        }
+        let flushed = self.ring.poll_flush();
+        if flushed {
+            if let Some(resampler) = self.resampler.as_mut() {
+                resampler.reset();
+            }
+            self.equalizer.reset();
+            self.dsp_chain.reset();
+            self.limiter.reset();
+        }
+        flushed
+    }
+
+    /// Returns false while there is nothing to render, push based streams can idle.
+    /// Stays true until a pause or stop has faded out.
+    pub fn is_active(&self) -> bool {
+        let state = &*self.state;
+        (state.state.load(Relaxed) == State::Playing as u8 || self.fader.level > 0.0)
+            && !state.finished.load(Relaxed)
+            && !state.decoder_pending.load(Relaxed)
+            && !state.shutdown.load(Relaxed)
+    }
+
+    /// True once the decoder thread has applied every pending request and
+    /// `frames` frames are buffered, or the stream has ended.
+    /// Only useful for outputs that can afford to wait, like the manual null clock.
+    pub fn is_ready(&self, frames: usize) -> bool {
+        let state = &*self.state;
+        if self.fading_for_seek() {
+            return true;
+        }
+        if state.decoder_pending.load(Relaxed)
+            || state.seek.load(Relaxed) != u64::MAX
+            || self.ring.ring.flush_requested()
+            || !self.can_render(state.output_channels.load(Relaxed) as usize)
+        {
+            return false;
+        }
+
+        // Resampling reads more or less than it writes, plus a filter's worth up front.
+        let frames = match state.resample_rates() {
+            Some((from, to)) => (frames * from as usize).div_ceil(to as usize) + MAX_TAPS / 2,
+            None => frames,
+        };
+
+        // Nothing more arrives before a boundary that needs the output reopened.
+        let reformat = self.ring.ring.boundary_pending() && !state.next_gapless.load(Relaxed);
+        !self.is_active()
+            || self.ring.len() >= frames * self.ring.channels()
+            || self.ring.ring.ended()
+            || reformat
+    }
+
+    /// Fills `buffer` with interleaved `f32` frames, silence if there is nothing to play.
+    pub fn fill(&mut self, buffer: &mut [u8], channels: usize) {
+        self.fill_as(buffer, channels, SampleFormat::F32);
+    }
+
+    /// Like `fill` for outputs opened with an integer format, dithered as set on the player.
+    pub fn fill_as(&mut self, buffer: &mut [u8], channels: usize, format: SampleFormat) {
+        self.update();
+        self.prepare_processors(channels);
+        let fade_ms = self.state.fade_ms.load(Relaxed) as usize;
+        self.fader.length = fade_ms * self.prepared.0 as usize / 1000;
+
+        let dither = Dither::from_u8(self.state.dither.load(Relaxed));
+        if self.quantizer.format != format || self.quantizer.dither != dither {
+            self.quantizer = Quantizer::new(format, dither);
+        }
+
+        let layout = self.ring.layout();
+        if self.mixed != (layout, channels) {
+            self.mixed = (layout, channels);
+            self.mix = Mix::new(layout, Layout::default_for(channels));
+        }
+
+        self.fill_output(buffer, channels);
+    }
+
+    /// A seek is waiting for the audio before it to fade out.
+    fn fading_for_seek(&self) -> bool {
+        let state = &*self.state;
+        self.fader.level > 0.0
+            && self.fader.length > 0
+            && state.seek.load(Relaxed) != u64::MAX
+            && state.state.load(Relaxed) == State::Playing as u8
+            && !state.decoder_pending.load(Relaxed)
+            && !state.shutdown.load(Relaxed)
+    }
+
+    /// Picks up changes from the player and keeps the processors at the output's rate and channels.
+    fn prepare_processors(&mut self, channels: usize) {
+        let state = &*self.state;
+        let rate = state.processing_rate();
+        if self.prepared != (rate, channels) {
+            self.prepared = (rate, channels);
+            // Its replacement at a new rate comes from the decoder thread.
+            self.equalizer.channels = channels.max(1);
+            // On this thread, as `Processor::prepare` warns, but only once the output changed.
+            self.dsp_chain.prepare(rate, channels);
+        }
+
+        self.limiter.set_enabled(state.limiter.load(Relaxed));
+        self.limiter
+            .set_ceiling(f32::from_bits(state.limiter_ceiling.load(Relaxed)));
+        let release = Duration::from_millis(state.limiter_release_ms.load(Relaxed) as u64);
+        if self.limiter.release() != release {
+            self.limiter.set_release(release);
+        }
+
+        if state.retired_eq.is_empty()
+            && let Some(mut equalizer) = state.pending_eq.take_box()
+        {
+            equalizer.take_over(&self.equalizer);
+            let old = std::mem::replace(&mut self.equalizer, equalizer);
+            // Only the output sends anything back, so there's room.
+            let _ = state.retired_eq.put_box(old);
+        }
+        if state.retired_dsp.is_empty()
+            && let Some(mut chain) = state.pending_dsp.take_box()
+        {
+            // Only when the output changed since it was sent.
+            if !chain.is_prepared(rate, channels) {
+                chain.prepare(rate, channels);
             }
+            let old = std::mem::replace(&mut self.dsp_chain, chain);
+            let _ = state.retired_dsp.put_box(old);
         }
+    }
 
-        if channels == 1 {
-            let sample = if src_ch >= 2 {
-                (src_frame[0] + src_frame[1]) * 0.5
+    /// Swaps in the limiter the decoder thread built for the output's rate and channels,
+    /// the old one goes back to be dropped there.
+    fn prepare_limiter(&mut self) {
+        let state = &*self.state;
+        let rate = state.processing_rate();
+        let channels = state.output_channels.load(Relaxed) as usize;
+        if state.retired_limiter.is_empty()
+            && let Some(mut limiter) = state.pending_limiter.take_box()
+        {
+            // One built before the output changed again goes straight back, the decoder
+            // thread is already building its replacement.
+            let old = if limiter.is_prepared(rate, channels) {
+                limiter.take_over(&self.limiter);
+                let latency = limiter.latency() as u64 * 1_000_000_000 / rate as u64;
+                state.limiter_latency.store(latency, Relaxed);
+                std::mem::replace(&mut self.limiter, limiter)
             } else {
-                src_frame[0]
+                limiter
             };
-            bytes[0..4].copy_from_slice(&(sample * scale).to_le_bytes());
+            let _ = state.retired_limiter.put_box(old);
+        }
+    }
+
+    /// Swaps in the resampler the decoder thread built for the current rates. The one it
+    /// replaces goes back to be dropped there, nothing is allocated or freed here.
+    fn prepare_resampler(&mut self) {
+        let state = &*self.state;
+        if state.pending_resampler.is_empty() {
+            return;
+        }
+        if let Some(old) = self.resampler.take()
+            && let Err(old) = state.retired_resampler.put_box(old)
+        {
+            // The last one hasn't been picked up yet.
+            self.resampler = Some(old);
+            return;
+        }
+        self.resampler = state.pending_resampler.take_box();
+    }
+
+    /// A resampler for another quality still works until its replacement arrives.
+    fn resampler_matches(&self, resampler: &Resampler) -> bool {
+        self.state.resample_rates() == Some((resampler.from, resampler.to))
+            && resampler.channels == self.ring.channels()
+    }
+
+    /// False while the resampler for differing rates, or the limiter for the output's rate
+    /// and `channels`, hasn't arrived from the decoder thread.
+    fn can_render(&self, channels: usize) -> bool {
+        let state = &*self.state;
+        let resampler = state.resample_rates().is_none()
+            || self
+                .resampler
+                .as_deref()
+                .is_some_and(|resampler| self.resampler_matches(resampler));
+        resampler && self.limiter.is_prepared(state.processing_rate(), channels)
+    }
+
+    /// Copies frames out of the ring, never decodes. Running dry counts as an underrun
+    /// unless the decoder reached the end, in which case the song is finished.
+    /// Frames go through the resampler when there is one, then the equalizer and the DSP chain
+    /// run on the mixed frames, and the limiter once the volume is applied.
+    /// `buffer` is written in the quantizer's format.
+    /// Pauses, stops and seeks fade out through the fader before the output goes silent.
+    fn fill_output(&mut self, buffer: &mut [u8], channels: usize) {
+        let resampling = self.state.resample_rates().is_some();
+        let can_render = self.can_render(channels);
+        let Self {
+            state,
+            ring,
+            resampler,
+            quantizer,
+            equalizer,
+            dsp_chain,
+            limiter,
+            mix,
+            fader,
+            ..
+        } = self;
+        let state = &**state;
+        let mut resampler = resampler.as_deref_mut().filter(|_| resampling);
+        let mut processors: [&mut dyn Processor; 2] = [&mut **equalizer, &mut **dsp_chain];
+        buffer.fill(0);
+
+        if state.finished.load(Relaxed) || state.decoder_pending.load(Relaxed) || !can_render {
+            return;
+        }
+
+        let playing = || {
+            state.state.load(Relaxed) == State::Playing as u8
+                && state.seek.load(Relaxed) == u64::MAX
+        };
+        fader.follow_volume(if state.muted.load(Relaxed) {
+            0.0
         } else {
-            let left = src_frame[0] * scale;
-            let right = if src_ch >= 2 {
-                src_frame[1] * scale
+            f32::from_bits(state.volume.load(Relaxed))
+        });
+        if !playing() && fader.level == 0.0 {
+            return;
+        }
+
+        let mut gain = f32::from_bits(state.gain.load(Relaxed));
+        let sample_bytes = quantizer.format.bytes();
+        let frame_bytes = sample_bytes * channels;
+        let mut block = [0f32; FILL_BLOCK];
+        let mut resampled = [0f32; FILL_BLOCK];
+        let mut mixed = [0f32; FILL_BLOCK];
+
+        let src_ch = ring.channels();
+
+        let frames = buffer.len() / frame_bytes;
+        let mut frame = 0;
+        let mut clipped = 0;
+
+        while frame < frames {
+            if state.finished.load(Relaxed) {
+                break;
+            }
+
+            // The output may reopen for the next song, so the end of this one comes out first.
+            let mut draining = false;
+            if ring.at_boundary() {
+                if !state.next_gapless.load(Relaxed) && limiter.held() > 0 {
+                    draining = true;
+                } else {
+                    ring.pass_boundary();
+                    state.start_next();
+                    if state.decoder_pending.load(Relaxed) {
+                        break;
+                    }
+                    gain = f32::from_bits(state.gain.load(Relaxed));
+                }
+            }
+
+            let playing = playing();
+            if !playing && fader.level == 0.0 {
+                break;
+            }
+
+            let mut wanted = (frames - frame).min(FILL_BLOCK / src_ch.max(channels));
+            if !playing {
+                let left = fader.frames_to_silence();
+                if left == 0 {
+                    fader.level = 0.0;
+                    break;
+                }
+                wanted = wanted.min(left);
+            }
+            let needed = resampler
+                .as_deref()
+                .map_or(wanted, |resampler| resampler.input_needed(wanted));
+
+            let mut read = 0;
+            if needed > 0 && !draining {
+                let available = ring.available() / src_ch;
+                let drained = available == 0 && ring.is_drained();
+                // The end of the song is still in the resampler's filter, then the limiter.
+                let tail = drained && resampler.as_deref_mut().is_some_and(|r| r.finish());
+                draining = drained && !tail && limiter.held() > 0;
+                if available == 0 && !tail && !draining {
+                    if drained {
+                        // Unless a song was queued since and the decoder took the end back.
+                        if ring.close() {
+                            state.mark_finished();
+                        }
+                    } else if !ring.ring.flush_requested() {
+                        state.underruns.fetch_add(1, Relaxed);
+                        state
+                            .underrun_frames
+                            .fetch_add((frames - frame) as u64, Relaxed);
+                    }
+                    // Nothing left to fade out with.
+                    if !playing {
+                        fader.level = 0.0;
+                    }
+                    break;
+                }
+
+                read = available.min(needed).min(FILL_BLOCK / src_ch);
+                ring.pop(&mut block[..read * src_ch]);
+            }
+
+            let n = if draining {
+                limiter.drain(&mut mixed[..wanted * channels])
             } else {
-                left
+                // The position counts what has been played, in frames of the song.
+                let (samples, n) = match resampler.as_deref_mut() {
+                    Some(resampler) => {
+                        resampler.push(&block[..read * src_ch]);
+                        let n = resampler.pull(&mut resampled[..wanted * src_ch]);
+                        let advanced = resampler.take_advanced();
+                        state.position.fetch_add(advanced as u64, Relaxed);
+                        (&resampled[..n * src_ch], n)
+                    }
+                    None => {
+                        state.position.fetch_add(read as u64, Relaxed);
+                        (&block[..read * src_ch], read)
+                    }
+                };
+
+                let mixed = &mut mixed[..n * channels];
+                for (src_frame, dst_frame) in samples
+                    .chunks_exact(src_ch)
+                    .zip(mixed.chunks_exact_mut(channels))
+                {
+                    mix.apply(src_frame, dst_frame);
+                }
+                for processor in processors.iter_mut() {
+                    processor.process(mixed);
+                }
+
+                for samples in mixed.chunks_exact_mut(channels) {
+                    fader.advance_volume();
+                    let scale = fader.volume * gain;
+                    for sample in samples {
+                        *sample *= scale;
+                    }
+                }
+                limiter.process(mixed);
+                n
             };
-            bytes[0..4].copy_from_slice(&left.to_le_bytes());
-            if channels >= 2 {
-                bytes[4..8].copy_from_slice(&right.to_le_bytes());
+
+            // Faded after the look-ahead, so what it holds when paused comes out on resuming.
+            let mixed = &mut mixed[..n * channels];
+            for samples in mixed.chunks_exact_mut(channels) {
+                fader.advance_level(playing);
+                for sample in samples {
+                    *sample *= fader.level;
+                }
             }
-            for c in 2..channels {
-                let sample = if c % 2 == 0 { left } else { right };
-                let off = c * 4;
-                bytes[off..off + 4].copy_from_slice(&sample.to_le_bytes());
+
+            let bytes = &mut buffer[frame * frame_bytes..(frame + n) * frame_bytes];
+            for (i, (sample, bytes)) in mixed
+                .iter()
+                .zip(bytes.chunks_exact_mut(sample_bytes))
+                .enumerate()
+            {
+                if sample.abs() > 1.0 {
+                    clipped += 1;
+                }
+                quantizer.write(i % channels, *sample, bytes);
             }
+            frame += n;
+        }
+
+        if clipped > 0 {
+            state.clipped_samples.fetch_add(clipped, Relaxed);
+        }
+        let limited = limiter.take_limited();
+        if limited > 0 {
+            state.limited_samples.fetch_add(limited, Relaxed);
         }
     }
 }
@@ -0,0 +1,682 @@
+use crate::{Error, MAX_CHANNELS, Processor, db_to_linear};
+use std::f64::consts::PI;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// How long parameter changes take to settle, long enough not to click.
+const SMOOTHING_SECONDS: f64 = 0.02;
+/// Frames between coefficient updates while a band is moving.
+const SMOOTHING_BLOCK: usize = 32;
+/// Q of a filter that doesn't give one, a Butterworth response.
+const DEFAULT_Q: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterKind {
+    /// Boosts or cuts around the frequency.
+    Peaking,
+    /// Boosts or cuts everything below the frequency.
+    LowShelf,
+    /// Boosts or cuts everything above the frequency.
+    HighShelf,
+    /// Removes everything above the frequency, gain is ignored.
+    LowPass,
+    /// Removes everything below the frequency, gain is ignored.
+    HighPass,
+}
+
+impl FilterKind {
+    fn name(self) -> &'static str {
+        match self {
+            FilterKind::Peaking => "peaking",
+            FilterKind::LowShelf => "low_shelf",
+            FilterKind::HighShelf => "high_shelf",
+            FilterKind::LowPass => "low_pass",
+            FilterKind::HighPass => "high_pass",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [
+            FilterKind::Peaking,
+            FilterKind::LowShelf,
+            FilterKind::HighShelf,
+            FilterKind::LowPass,
+            FilterKind::HighPass,
+        ]
+        .into_iter()
+        .find(|kind| kind.name() == name)
+    }
+
+    /// Filter types written by AutoEQ and Equalizer APO.
+    fn from_autoeq(name: &str) -> Option<Self> {
+        match name {
+            "PK" | "PEQ" => Some(FilterKind::Peaking),
+            "LS" | "LSC" | "LSQ" => Some(FilterKind::LowShelf),
+            "HS" | "HSC" | "HSQ" => Some(FilterKind::HighShelf),
+            "LP" | "LPQ" => Some(FilterKind::LowPass),
+            "HP" | "HPQ" => Some(FilterKind::HighPass),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Band {
+    pub kind: FilterKind,
+    /// Center or corner frequency in Hz.
+    pub freq: f32,
+    /// In dB.
+    pub gain: f32,
+    pub q: f32,
+}
+
+impl Band {
+    pub fn new(kind: FilterKind, freq: f32, gain: f32, q: f32) -> Self {
+        Self {
+            kind,
+            freq,
+            gain,
+            q,
+        }
+    }
+}
+
+/// Equalizer settings, set on the player with `Player::set_equalizer`.
+///
+/// Saved as plain text, one setting per line:
+///
+/// ```text
+/// preamp -4.5
+/// low_shelf 105 4.5 0.7
+/// peaking 2500 -3 1.41
+/// ```
+///
+/// Bands are `kind freq gain q`, blank lines and lines starting with `#` are skipped.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EqPreset {
+    /// Gain in dB applied before the bands, usually negative to leave room for boosts.
+    pub preamp: f32,
+    pub bands: Vec<Band>,
+}
+
+impl EqPreset {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        Ok(std::fs::write(path, self.to_string())?)
+    }
+
+    /// Reads an AutoEQ `ParametricEQ.txt` headphone profile, which is also what
+    /// Equalizer APO writes. Filters that are turned off are skipped.
+    pub fn from_autoeq(text: &str) -> Result<Self, Error> {
+        let mut preset = EqPreset::default();
+        for line in text.lines().map(str::trim) {
+            let invalid = || Error::Preset(line.to_string());
+            let Some((key, rest)) = line.split_once(':') else {
+                continue;
+            };
+            if key.eq_ignore_ascii_case("preamp") {
+                let db = rest.split_whitespace().next().ok_or_else(invalid)?;
+                preset.preamp = db
+                    .parse()
+                    .ok()
+                    .filter(|db: &f32| db.is_finite())
+                    .ok_or_else(invalid)?;
+                continue;
+            }
+            if !key.starts_with("Filter") {
+                continue;
+            }
+
+            let words: Vec<&str> = rest.split_whitespace().collect();
+            if words.first() != Some(&"ON") {
+                continue;
+            }
+            let kind = words
+                .get(1)
+                .and_then(|kind| FilterKind::from_autoeq(kind))
+                .ok_or_else(invalid)?;
+            let value = |name: &str| -> Result<Option<f32>, Error> {
+                match words.iter().position(|word| *word == name) {
+                    Some(i) => words
+                        .get(i + 1)
+                        .and_then(|value| value.parse::<f32>().ok())
+                        .filter(|value| value.is_finite())
+                        .map(Some)
+                        .ok_or_else(invalid),
+                    None => Ok(None),
+                }
+            };
+            let freq = value("Fc")?.ok_or_else(invalid)?;
+            let gain = value("Gain")?.unwrap_or(0.0);
+            let q = value("Q")?.unwrap_or(DEFAULT_Q);
+            preset.bands.push(Band::new(kind, freq, gain, q));
+        }
+        Ok(preset)
+    }
+}
+
+impl fmt::Display for EqPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "preamp {}", self.preamp)?;
+        for band in &self.bands {
+            writeln!(
+                f,
+                "{} {} {} {}",
+                band.kind.name(),
+                band.freq,
+                band.gain,
+                band.q
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for EqPreset {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self, Error> {
+        let mut preset = EqPreset::default();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || Error::Preset(line.to_string());
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let number = |i: usize| -> Result<f32, Error> {
+                let value: f32 = words
+                    .get(i)
+                    .and_then(|word| word.parse().ok())
+                    .ok_or_else(invalid)?;
+                value.is_finite().then_some(value).ok_or_else(invalid)
+            };
+
+            match words[0] {
+                "preamp" if words.len() == 2 => preset.preamp = number(1)?,
+                name if words.len() == 4 => {
+                    let kind = FilterKind::from_name(name).ok_or_else(invalid)?;
+                    preset
+                        .bands
+                        .push(Band::new(kind, number(1)?, number(2)?, number(3)?));
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(preset)
+    }
+}
+
+/// Biquad coefficients normalised so `a0` is 1, from the Audio EQ Cookbook.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Coefs {
+    b: [f64; 3],
+    a: [f64; 2],
+}
+
+impl Coefs {
+    fn new(band: &Band, rate: f64) -> Self {
+        let freq = (band.freq as f64).clamp(1.0, rate * 0.49);
+        let q = (band.q as f64).max(0.01);
+        let w0 = 2.0 * PI * freq / rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q);
+        let a = 10f64.powf(band.gain as f64 / 40.0);
+        let shelf = 2.0 * a.sqrt() * alpha;
+
+        let (b, a) = match band.kind {
+            FilterKind::Peaking => (
+                [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+                [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+            ),
+            FilterKind::LowShelf => (
+                [
+                    a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+                ],
+                [
+                    (a + 1.0) + (a - 1.0) * cos + shelf,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - shelf,
+                ],
+            ),
+            FilterKind::HighShelf => (
+                [
+                    a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+                ],
+                [
+                    (a + 1.0) - (a - 1.0) * cos + shelf,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - shelf,
+                ],
+            ),
+            FilterKind::LowPass => (
+                [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+                [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            ),
+            FilterKind::HighPass => (
+                [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+                [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            ),
+        };
+        Self {
+            b: [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            a: [a[1] / a[0], a[2] / a[0]],
+        }
+    }
+}
+
+/// A band as it's being played, moving towards its settings.
+struct Filter {
+    target: Band,
+    current: Band,
+    coefs: Coefs,
+    /// Transposed direct form II state of each channel.
+    z: [[f64; 2]; MAX_CHANNELS],
+    /// How much of the filtered signal is heard, bands fade in and out when added or removed.
+    mix: f32,
+    removed: bool,
+}
+
+impl Filter {
+    fn new(band: Band, rate: f64) -> Self {
+        Self {
+            target: band,
+            current: band,
+            coefs: Coefs::new(&band, rate),
+            z: [[0.0; 2]; MAX_CHANNELS],
+            mix: 0.0,
+            removed: false,
+        }
+    }
+
+    /// Moves the parameters part of the way to their targets, `amount` from 0 to 1.
+    fn smooth(&mut self, amount: f32, rate: f64) {
+        let (current, target) = (&mut self.current, &self.target);
+        if current == target {
+            return;
+        }
+        // Frequency and Q move in octaves, so the sweep sounds even.
+        let towards = |from: f32, to: f32| from * (to / from).powf(amount);
+        current.freq = towards(current.freq, target.freq);
+        current.q = towards(current.q, target.q);
+        current.gain += (target.gain - current.gain) * amount;
+        if (current.freq / target.freq - 1.0).abs() < 1e-4
+            && (current.q / target.q - 1.0).abs() < 1e-4
+            && (current.gain - target.gain).abs() < 1e-3
+        {
+            *current = *target;
+        }
+        self.coefs = Coefs::new(current, rate);
+    }
+
+    fn process(&mut self, channel: usize, x: f32) -> f32 {
+        let Coefs { b, a } = self.coefs;
+        let z = &mut self.z[channel];
+        let input = x as f64;
+        let y = b[0] * input + z[0];
+        z[0] = b[1] * input - a[0] * y + z[1];
+        z[1] = b[2] * input - a[1] * y;
+        x + (y as f32 - x) * self.mix
+    }
+}
+
+/// Runs an `EqPreset` on the output, changes are smoothed so they can be made while playing.
+pub struct Equalizer {
+    pub sample_rate: u32,
+    pub channels: usize,
+    /// Kept to start over when the rate changes.
+    preset: EqPreset,
+    filters: Vec<Filter>,
+    preamp: f32,
+    target_preamp: f32,
+    /// Frames until the next smoothing step.
+    countdown: usize,
+    /// How far parameters move each step, and the preamp each frame.
+    amount: f32,
+    preamp_amount: f32,
+    /// How far a fading band moves each frame.
+    fade: f32,
+}
+
+impl Equalizer {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let rate = sample_rate.max(1) as f64;
+        let frames = (rate * SMOOTHING_SECONDS).max(1.0);
+        let steps = (frames / SMOOTHING_BLOCK as f64).max(1.0);
+        Self {
+            sample_rate,
+            channels: channels.max(1),
+            preset: EqPreset::default(),
+            filters: Vec::new(),
+            preamp: 1.0,
+            target_preamp: 1.0,
+            countdown: 0,
+            // Within 2% of the target after `SMOOTHING_SECONDS`.
+            amount: (1.0 - (-4.0 / steps).exp()) as f32,
+            preamp_amount: (1.0 - (-4.0 / frames).exp()) as f32,
+            fade: (1.0 / frames) as f32,
+        }
+    }
+
+    /// Moves to `preset`. Bands of the same kind in the same place glide to their new
+    /// settings, anything else fades out while its replacement fades in.
+    pub fn set(&mut self, preset: &EqPreset) {
+        self.target_preamp = db_to_linear(preset.preamp);
+        let rate = self.sample_rate.max(1) as f64;
+        // Nothing below can recover from a zero frequency or Q.
+        let mut bands = preset.bands.iter().map(|band| Band {
+            freq: band.freq.max(1.0),
+            q: band.q.max(0.01),
+            ..*band
+        });
+        let mut added = Vec::new();
+        for filter in self.filters.iter_mut().filter(|filter| !filter.removed) {
+            match bands.next() {
+                Some(band) if band.kind == filter.target.kind => filter.target = band,
+                Some(band) => {
+                    filter.removed = true;
+                    added.push(Filter::new(band, rate));
+                }
+                None => filter.removed = true,
+            }
+        }
+        added.extend(bands.map(|band| Filter::new(band, rate)));
+        self.filters.extend(added);
+        self.preset.clone_from(preset);
+    }
+
+    /// Built off the output for a new preset. Starts with the bands of `from` settled and
+    /// moves to `to` like `set` would, `take_over` brings in the running state when it's swapped in.
+    pub fn transition(from: &EqPreset, to: &EqPreset, sample_rate: u32) -> Self {
+        let mut equalizer = Equalizer::new(sample_rate, 2);
+        equalizer.set(from);
+        equalizer.preamp = equalizer.target_preamp;
+        for filter in &mut equalizer.filters {
+            filter.mix = 1.0;
+        }
+        equalizer.set(to);
+        equalizer
+    }
+
+    /// Carries the running filters of `old` over to a `transition` from its preset, so swapping
+    /// it in doesn't click. Doesn't allocate. Bands still fading out of `old` are cut short.
+    pub fn take_over(&mut self, old: &Equalizer) {
+        let rate = self.sample_rate.max(1) as f64;
+        self.channels = old.channels;
+        self.preamp = old.preamp;
+        self.countdown = old.countdown;
+        let running = old.filters.iter().filter(|filter| !filter.removed);
+        for (filter, old) in self.filters.iter_mut().zip(running) {
+            if filter.current.kind != old.current.kind {
+                continue;
+            }
+            filter.current = old.current;
+            filter.coefs = Coefs::new(&filter.current, rate);
+            filter.z = old.z;
+            filter.mix = filter.mix.min(old.mix);
+        }
+    }
+
+    /// True when it does nothing and can be skipped.
+    pub fn is_bypassed(&self) -> bool {
+        self.filters.is_empty() && self.preamp == 1.0 && self.target_preamp == 1.0
+    }
+
+    fn process_frame(&mut self, frame: &mut [f32]) {
+        if self.countdown == 0 {
+            self.countdown = SMOOTHING_BLOCK;
+            self.step();
+        }
+        self.countdown -= 1;
+
+        self.preamp += (self.target_preamp - self.preamp) * self.preamp_amount;
+        for filter in &mut self.filters {
+            filter.mix = if filter.removed {
+                (filter.mix - self.fade).max(0.0)
+            } else {
+                (filter.mix + self.fade).min(1.0)
+            };
+        }
+
+        for (channel, sample) in frame.iter_mut().take(MAX_CHANNELS).enumerate() {
+            let mut x = *sample * self.preamp;
+            for filter in &mut self.filters {
+                x = filter.process(channel, x);
+            }
+            *sample = x;
+        }
+    }
+
+    fn step(&mut self) {
+        let rate = self.sample_rate.max(1) as f64;
+        if (self.preamp - self.target_preamp).abs() < 1e-4 {
+            self.preamp = self.target_preamp;
+        }
+        for filter in &mut self.filters {
+            filter.smooth(self.amount, rate);
+        }
+        self.filters
+            .retain(|filter| !(filter.removed && filter.mix == 0.0));
+    }
+}
+
+impl Processor for Equalizer {
+    /// A new rate starts over with the same preset.
+    fn prepare(&mut self, sample_rate: u32, channels: usize) {
+        if sample_rate != self.sample_rate {
+            let preset = std::mem::take(&mut self.preset);
+            *self = Equalizer::new(sample_rate, channels);
+            self.set(&preset);
+        }
+        self.channels = channels.max(1);
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        if self.is_bypassed() {
+            return;
+        }
+        for frame in samples.chunks_exact_mut(self.channels) {
+            self.process_frame(frame);
+        }
+    }
+
+    /// Settings are kept, running filters are cleared.
+    fn reset(&mut self) {
+        for filter in &mut self.filters {
+            filter.z = [[0.0; 2]; MAX_CHANNELS];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48000;
+
+    /// Gain in dB of `freq` through the equalizer once it has settled.
+    fn response(equalizer: &mut Equalizer, freq: f64) -> f64 {
+        let len = RATE as usize / 4;
+        let mut peak = 0f32;
+        for i in 0..len {
+            let x = (2.0 * PI * freq * i as f64 / RATE as f64).sin() as f32;
+            let mut frame = [x, x];
+            equalizer.process(&mut frame);
+            assert_eq!(frame[0], frame[1]);
+            if i > len / 2 {
+                peak = peak.max(frame[0].abs());
+            }
+        }
+        20.0 * (peak as f64).log10()
+    }
+
+    fn settled(preset: &EqPreset) -> Equalizer {
+        let mut equalizer = Equalizer::new(RATE, 2);
+        equalizer.set(preset);
+        equalizer
+    }
+
+    #[test]
+    fn filters() {
+        let band = |kind, freq, gain| EqPreset {
+            preamp: 0.0,
+            bands: vec![Band::new(kind, freq, gain, DEFAULT_Q)],
+        };
+
+        let mut eq = settled(&band(FilterKind::Peaking, 1000.0, 6.0));
+        assert!((response(&mut eq, 1000.0) - 6.0).abs() < 0.1);
+        assert!(response(&mut eq, 50.0).abs() < 0.1);
+
+        let mut eq = settled(&band(FilterKind::LowShelf, 200.0, -6.0));
+        assert!((response(&mut eq, 30.0) + 6.0).abs() < 0.2);
+        assert!(response(&mut eq, 5000.0).abs() < 0.1);
+
+        let mut eq = settled(&band(FilterKind::HighShelf, 4000.0, 3.0));
+        assert!((response(&mut eq, 18000.0) - 3.0).abs() < 0.2);
+        assert!(response(&mut eq, 100.0).abs() < 0.1);
+
+        let mut eq = settled(&band(FilterKind::LowPass, 1000.0, 0.0));
+        assert!((response(&mut eq, 1000.0) + 3.0).abs() < 0.1);
+        assert!(response(&mut eq, 10000.0) < -35.0);
+
+        let mut eq = settled(&band(FilterKind::HighPass, 1000.0, 0.0));
+        assert!((response(&mut eq, 1000.0) + 3.0).abs() < 0.1);
+        assert!(response(&mut eq, 100.0) < -35.0);
+
+        let mut eq = settled(&EqPreset {
+            preamp: -6.0,
+            bands: Vec::new(),
+        });
+        assert!((response(&mut eq, 1000.0) + 6.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn changes_are_smooth() {
+        let loud = EqPreset {
+            preamp: -12.0,
+            bands: vec![Band::new(FilterKind::Peaking, 100.0, 12.0, 1.0)],
+        };
+        let other = EqPreset {
+            preamp: 0.0,
+            bands: vec![Band::new(FilterKind::HighPass, 2000.0, 0.0, 0.7)],
+        };
+
+        // A constant input, any step in the settings would show up as a jump.
+        let mut eq = Equalizer::new(RATE, 1);
+        let mut last = 0.0;
+        let mut largest = 0f32;
+        for i in 0..RATE as usize {
+            match i {
+                4800 => eq.set(&loud),
+                9600 => eq.set(&other),
+                19200 => eq.set(&EqPreset::default()),
+                _ => {}
+            }
+            let x = (2.0 * PI * 440.0 * i as f64 / RATE as f64).sin() as f32 * 0.5;
+            let mut frame = [x];
+            eq.process(&mut frame);
+            largest = largest.max((frame[0] - last).abs());
+            last = frame[0];
+        }
+        // 440Hz at 0.5 moves at most 0.029 a sample.
+        assert!(largest < 0.04, "{largest}");
+        assert!(eq.is_bypassed());
+    }
+
+    #[test]
+    fn transition_takes_over() {
+        let before = EqPreset {
+            preamp: -3.0,
+            bands: vec![
+                Band::new(FilterKind::Peaking, 100.0, 6.0, 1.0),
+                Band::new(FilterKind::LowPass, 8000.0, 0.0, DEFAULT_Q),
+            ],
+        };
+        let after = EqPreset {
+            preamp: 0.0,
+            bands: vec![
+                Band::new(FilterKind::Peaking, 300.0, -6.0, 2.0),
+                Band::new(FilterKind::HighPass, 50.0, 0.0, DEFAULT_Q),
+                Band::new(FilterKind::HighShelf, 5000.0, 3.0, DEFAULT_Q),
+            ],
+        };
+        let input: Vec<f32> = (0..RATE as usize / 2)
+            .map(|i| (2.0 * PI * 440.0 * i as f64 / RATE as f64).sin() as f32 * 0.5)
+            .collect();
+        let (first, second) = input.split_at(input.len() / 2);
+
+        // Swapping in a transition built elsewhere sounds the same as changing it in place.
+        let mut in_place = Equalizer::new(RATE, 1);
+        let mut running = Equalizer::new(RATE, 1);
+        in_place.set(&before);
+        running.set(&before);
+        let mut a = first.to_vec();
+        let mut b = first.to_vec();
+        in_place.process(&mut a);
+        running.process(&mut b);
+
+        in_place.set(&after);
+        let mut swapped = Equalizer::transition(&before, &after, RATE);
+        swapped.take_over(&running);
+        let mut a = second.to_vec();
+        let mut b = second.to_vec();
+        in_place.process(&mut a);
+        swapped.process(&mut b);
+        assert!(a.iter().zip(&b).all(|(a, b)| (a - b).abs() < 1e-6));
+    }
+
+    #[test]
+    fn presets() {
+        let preset = EqPreset {
+            preamp: -4.5,
+            bands: vec![
+                Band::new(FilterKind::LowShelf, 105.0, 4.5, 0.7),
+                Band::new(FilterKind::Peaking, 2500.0, -3.0, 1.41),
+                Band::new(FilterKind::HighPass, 20.0, 0.0, 0.5),
+            ],
+        };
+        let text = preset.to_string();
+        assert_eq!(text.parse::<EqPreset>().unwrap(), preset);
+
+        let text = "# mine\n\npreamp -1\npeaking 100 2 1\n";
+        assert_eq!(text.parse::<EqPreset>().unwrap().bands.len(), 1);
+        assert!("peaking 100 2".parse::<EqPreset>().is_err());
+        assert!("notch 100 2 1".parse::<EqPreset>().is_err());
+        assert!("preamp loud".parse::<EqPreset>().is_err());
+    }
+
+    #[test]
+    fn autoeq() {
+        let text = "Preamp: -6.2 dB\r\n\
+            Filter 1: ON LSC Fc 105 Hz Gain 6.5 dB Q 0.70\r\n\
+            Filter 2: ON PK Fc 180 Hz Gain -3.0 dB Q 0.63\r\n\
+            Filter 3: OFF PK Fc 1000 Hz Gain 9.0 dB Q 1.00\r\n\
+            Filter 4: ON HP Fc 20 Hz\r\n\
+            Filter 5: ON HSC Fc 10000 Hz Gain -2.0 dB Q 0.70\r\n";
+        let preset = EqPreset::from_autoeq(text).unwrap();
+        assert_eq!(preset.preamp, -6.2);
+        assert_eq!(
+            preset.bands,
+            [
+                Band::new(FilterKind::LowShelf, 105.0, 6.5, 0.7),
+                Band::new(FilterKind::Peaking, 180.0, -3.0, 0.63),
+                Band::new(FilterKind::HighPass, 20.0, 0.0, DEFAULT_Q),
+                Band::new(FilterKind::HighShelf, 10000.0, -2.0, 0.7),
+            ]
+        );
+
+        assert!(EqPreset::from_autoeq("Filter 1: ON NO Fc 50 Hz").is_err());
+        assert!(EqPreset::from_autoeq("Filter 1: ON PK Gain 1 dB").is_err());
+        assert!(EqPreset::from_autoeq("Filter 1: ON PK Fc inf Hz").is_err());
+        assert!(EqPreset::from_autoeq("Filter 1: ON PK Fc 50 Hz Gain NaN dB").is_err());
+        assert!(EqPreset::from_autoeq("Filter 1: ON PK Fc 50 Hz Q -inf").is_err());
+        assert!(EqPreset::from_autoeq("Preamp: inf dB").is_err());
+    }
+}
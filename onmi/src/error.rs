@@ -0,0 +1,62 @@
+use core::fmt;
+
+/// Everything that can go wrong opening or playing a song.
+/// Problems after playback started are reported as a `RuntimeError` instead.
+#[derive(Debug)]
+pub enum Error {
+    /// The file couldn't be opened or read.
+    Io(std::io::Error),
+    /// Not a container or codec that can be decoded, or the track is missing something needed to play it.
+    UnsupportedFormat(String),
+    /// The file opened but there is no audio in it.
+    NoAudioTrack,
+    /// The file is malformed.
+    Decode(String),
+    /// The output device couldn't be found or opened.
+    Device(String),
+    /// Index past the end of the queue.
+    QueueIndex(usize),
+    /// An equalizer preset line that couldn't be read.
+    Preset(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{err}"),
+            Error::UnsupportedFormat(what) => write!(f, "Unsupported format: {what}"),
+            Error::NoAudioTrack => write!(f, "No audio track"),
+            Error::Decode(what) => write!(f, "Decode error: {what}"),
+            Error::Device(name) => write!(f, "Failed to open device: {name}"),
+            Error::QueueIndex(index) => write!(f, "Queue index {index} out of range"),
+            Error::Preset(line) => write!(f, "Invalid equalizer preset line: {line}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<symphonia::core::errors::Error> for Error {
+    fn from(err: symphonia::core::errors::Error) -> Self {
+        use symphonia::core::errors::Error as E;
+        match err {
+            E::IoError(err) => Error::Io(err),
+            E::Unsupported(what) => Error::UnsupportedFormat(what.to_string()),
+            E::DecodeError(what) => Error::Decode(what.to_string()),
+            err => Error::Decode(err.to_string()),
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use crate::{Device, PlayerState, RuntimeError, State};
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+pub const POSITION_TICK: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    TrackStarted {
+        duration: Duration,
+    },
+    /// The song played to its end, either before the next one starts or before stopping.
+    TrackFinished,
+    StateChanged(State),
+    Seeked(Duration),
+    VolumeChanged(u8),
+    MuteChanged(bool),
+    DeviceChanged(Device),
+    Error(RuntimeError),
+    /// Sent every `POSITION_TICK` while playing.
+    Position(Duration),
+}
+
+/// Turns changes in `PlayerState` into events. Lives on the output thread, it never blocks:
+/// if a subscriber is being added the events wait for the next poll.
+pub struct Events {
+    pending: Vec<Event>,
+    state: u8,
+    volume: u8,
+    muted: bool,
+    songs_started: u64,
+    songs_finished: u64,
+    seeks: u64,
+    errors: u64,
+    last_tick: Instant,
+}
+
+impl Events {
+    pub fn new(state: &PlayerState) -> Self {
+        Self {
+            pending: Vec::new(),
+            state: state.state.load(Relaxed),
+            volume: state.volume_percent(),
+            muted: state.muted.load(Relaxed),
+            songs_started: state.songs_started.load(Relaxed),
+            songs_finished: state.songs_finished.load(Relaxed),
+            seeks: state.seeks.load(Relaxed),
+            errors: state.errors.load(Relaxed),
+            last_tick: Instant::now(),
+        }
+    }
+
+    pub fn push(&mut self, event: Event) {
+        self.pending.push(event);
+    }
+
+    pub fn poll(&mut self, state: &PlayerState) {
+        let finished = state.songs_finished.load(Relaxed);
+        for _ in self.songs_finished..finished {
+            self.pending.push(Event::TrackFinished);
+        }
+        self.songs_finished = finished;
+
+        let started = state.songs_started.load(Relaxed);
+        if started != self.songs_started {
+            self.songs_started = started;
+            self.pending.push(Event::TrackStarted {
+                duration: Duration::from_nanos(state.duration.load(Relaxed)),
+            });
+        }
+
+        let seeks = state.seeks.load(Relaxed);
+        if seeks != self.seeks {
+            self.seeks = seeks;
+            self.pending.push(Event::Seeked(Duration::from_nanos(
+                state.seeked_to.load(Relaxed),
+            )));
+        }
+
+        let current = state.state.load(Relaxed);
+        if current != self.state {
+            self.state = current;
+            self.pending
+                .push(Event::StateChanged(State::from_u8(current)));
+        }
+
+        let volume = state.volume_percent();
+        if volume != self.volume {
+            self.volume = volume;
+            self.pending.push(Event::VolumeChanged(volume));
+        }
+
+        let muted = state.muted.load(Relaxed);
+        if muted != self.muted {
+            self.muted = muted;
+            self.pending.push(Event::MuteChanged(muted));
+        }
+
+        // Errors can repeat every write, one event per poll is plenty.
+        let errors = state.errors.load(Relaxed);
+        if errors != self.errors {
+            self.errors = errors;
+            self.pending.push(Event::Error(RuntimeError::from_u8(
+                state.last_error.load(Relaxed),
+            )));
+        }
+
+        if current == State::Playing as u8 && self.last_tick.elapsed() >= POSITION_TICK {
+            self.last_tick = Instant::now();
+            self.pending.push(Event::Position(state.elapsed()));
+        }
+
+        self.send(state);
+    }
+
+    fn send(&mut self, state: &PlayerState) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let Ok(mut subscribers) = state.subscribers.try_lock() else {
+            return;
+        };
+        for event in self.pending.drain(..) {
+            subscribers.retain(|s: &Sender<Event>| s.send(event.clone()).is_ok());
+        }
+    }
+}
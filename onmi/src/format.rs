@@ -0,0 +1,260 @@
+use crate::MAX_CHANNELS;
+
+/// Little-endian sample formats an output can be opened with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleFormat {
+    F32,
+    I16,
+    /// Packed into three bytes.
+    I24,
+    I32,
+}
+
+impl SampleFormat {
+    pub fn bytes(self) -> usize {
+        match self {
+            SampleFormat::F32 => 4,
+            SampleFormat::I16 => 2,
+            SampleFormat::I24 => 3,
+            SampleFormat::I32 => 4,
+        }
+    }
+}
+
+/// Noise added before rounding to an integer format. Float output is never dithered.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dither {
+    /// Plain rounding, quiet passages turn into distortion instead of noise.
+    None = 0,
+    /// Triangular noise of ±1 LSB, the error is independent of the signal.
+    Tpdf = 1,
+    /// TPDF with the error fed back through a second order highpass, moving the noise
+    /// out of the range the ear is most sensitive to at the cost of more noise overall.
+    NoiseShaped = 2,
+}
+
+impl Dither {
+    pub fn from_u8(dither: u8) -> Self {
+        match dither {
+            x if x == Dither::None as u8 => Dither::None,
+            x if x == Dither::NoiseShaped as u8 => Dither::NoiseShaped,
+            _ => Dither::Tpdf,
+        }
+    }
+}
+
+/// Converts `f32` samples to the output format. Keeps the dither generator and
+/// the noise shaping history, so one is kept per stream.
+#[derive(Clone, Debug)]
+pub struct Quantizer {
+    pub format: SampleFormat,
+    pub dither: Dither,
+    rng: u32,
+    /// The last two quantisation errors of each channel, in LSBs.
+    error: [[f64; 2]; MAX_CHANNELS],
+}
+
+impl Quantizer {
+    pub fn new(format: SampleFormat, dither: Dither) -> Self {
+        Self {
+            format,
+            dither,
+            rng: 0x9E37_79B9,
+            error: [[0.0; 2]; MAX_CHANNELS],
+        }
+    }
+
+    /// Writes `sample` into `bytes`, which is `format.bytes()` long.
+    pub fn write(&mut self, channel: usize, sample: f32, bytes: &mut [u8]) {
+        match self.format {
+            SampleFormat::F32 => bytes.copy_from_slice(&sample.to_le_bytes()),
+            SampleFormat::I16 => {
+                let sample = self.quantize(channel, sample, 16) as i16;
+                bytes.copy_from_slice(&sample.to_le_bytes());
+            }
+            SampleFormat::I24 => {
+                let sample = self.quantize(channel, sample, 24);
+                bytes.copy_from_slice(&sample.to_le_bytes()[..3]);
+            }
+            SampleFormat::I32 => {
+                let sample = self.quantize(channel, sample, 32);
+                bytes.copy_from_slice(&sample.to_le_bytes());
+            }
+        }
+    }
+
+    fn quantize(&mut self, channel: usize, sample: f32, bits: u32) -> i32 {
+        let scale = (1u64 << (bits - 1)) as f64;
+        let x = sample as f64 * scale;
+        // An `f32` has 24 bits of precision, there's nothing below that to dither.
+        let dither = if bits > 24 { Dither::None } else { self.dither };
+
+        let quantized = match dither {
+            Dither::None => x.round(),
+            Dither::Tpdf => (x + self.tpdf()).round(),
+            Dither::NoiseShaped => {
+                let dither = self.tpdf();
+                let error = &mut self.error[channel.min(MAX_CHANNELS - 1)];
+                // Noise transfer function (1 - z^-1)^2.
+                let shaped = x - 2.0 * error[0] + error[1];
+                let quantized = (shaped + dither).round();
+                // Clipping makes the error huge, don't let it ring on afterwards.
+                error[1] = error[0];
+                error[0] = (quantized - shaped).clamp(-2.0, 2.0);
+                quantized
+            }
+        };
+        quantized.clamp(-scale, scale - 1.0) as i32
+    }
+
+    /// Triangular noise between -1 and 1 LSB.
+    fn tpdf(&mut self) -> f64 {
+        self.uniform() + self.uniform() - 1.0
+    }
+
+    fn uniform(&mut self) -> f64 {
+        // xorshift32, plenty for noise.
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng as f64 / (u32::MAX as f64 + 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const RATE: f64 = 44100.0;
+    const LSB_16: f64 = 1.0 / 32768.0;
+
+    fn sine(amplitude: f64, freq: f64, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| (amplitude * (2.0 * PI * freq * i as f64 / RATE).sin()) as f32)
+            .collect()
+    }
+
+    /// Quantises `input` to 16 bits and returns the output as floats.
+    fn quantize(input: &[f32], dither: Dither) -> Vec<f64> {
+        let mut quantizer = Quantizer::new(SampleFormat::I16, dither);
+        let mut bytes = [0u8; 2];
+        input
+            .iter()
+            .map(|sample| {
+                quantizer.write(0, *sample, &mut bytes);
+                i16::from_le_bytes(bytes) as f64 * LSB_16
+            })
+            .collect()
+    }
+
+    fn error(input: &[f32], output: &[f64]) -> Vec<f64> {
+        input
+            .iter()
+            .zip(output)
+            .map(|(x, y)| y - *x as f64)
+            .collect()
+    }
+
+    fn rms_db(samples: &[f64]) -> f64 {
+        let power = samples.iter().map(|x| x * x).sum::<f64>() / samples.len() as f64;
+        10.0 * power.log10()
+    }
+
+    /// Four one pole lowpasses at 2kHz, roughly the band where hearing is most sensitive.
+    fn low_band(samples: &[f64]) -> Vec<f64> {
+        let a = (-2.0 * PI * 2000.0 / RATE).exp();
+        let mut state = [0.0; 4];
+        samples
+            .iter()
+            .map(|x| {
+                let mut y = *x;
+                for s in &mut state {
+                    *s = y * (1.0 - a) + *s * a;
+                    y = *s;
+                }
+                y
+            })
+            .collect()
+    }
+
+    #[test]
+    fn formats() {
+        let mut bytes = [0u8; 4];
+        let mut write = |format, sample| {
+            let mut quantizer = Quantizer::new(format, Dither::None);
+            let len = SampleFormat::bytes(format);
+            quantizer.write(0, sample, &mut bytes[..len]);
+            bytes[..len].to_vec()
+        };
+
+        assert_eq!(write(SampleFormat::F32, 0.5), 0.5f32.to_le_bytes());
+        assert_eq!(write(SampleFormat::I16, 0.5), 16384i16.to_le_bytes());
+        assert_eq!(write(SampleFormat::I16, 1.0), i16::MAX.to_le_bytes());
+        assert_eq!(write(SampleFormat::I16, -1.5), i16::MIN.to_le_bytes());
+        assert_eq!(write(SampleFormat::I24, 0.5), [0x00, 0x00, 0x40]);
+        assert_eq!(write(SampleFormat::I24, -1.0), [0x00, 0x00, 0x80]);
+        assert_eq!(write(SampleFormat::I24, 1.0), [0xFF, 0xFF, 0x7F]);
+        assert_eq!(
+            write(SampleFormat::I32, -0.25),
+            (-(1i32 << 29)).to_le_bytes()
+        );
+        assert_eq!(write(SampleFormat::I32, 1.0), i32::MAX.to_le_bytes());
+    }
+
+    #[test]
+    fn tpdf_noise_level() {
+        let input = sine(0.25, 997.0, 1 << 16);
+        let output = quantize(&input, Dither::Tpdf);
+
+        // Rounding adds 1/12 LSB² and the dither 1/6 LSB², half an LSB RMS.
+        let expected = 20.0 * (0.5 * LSB_16).log10();
+        let noise = rms_db(&error(&input, &output));
+        assert!(
+            (noise - expected).abs() < 0.5,
+            "{noise:.2}dB, {expected:.2}dB"
+        );
+
+        // Undithered it's 1/12 LSB², but it follows the signal.
+        let plain = rms_db(&error(&input, &quantize(&input, Dither::None)));
+        let expected = 20.0 * (LSB_16 / 12f64.sqrt()).log10();
+        assert!(
+            (plain - expected).abs() < 0.5,
+            "{plain:.2}dB, {expected:.2}dB"
+        );
+    }
+
+    #[test]
+    fn dither_keeps_signals_below_one_lsb() {
+        let input = sine(0.4 * LSB_16, 1000.0, 1 << 16);
+        assert!(quantize(&input, Dither::None).iter().all(|s| *s == 0.0));
+
+        // On average the dithered output still follows the signal.
+        let output = quantize(&input, Dither::Tpdf);
+        let correlation = input
+            .iter()
+            .zip(&output)
+            .map(|(x, y)| *x as f64 * y)
+            .sum::<f64>()
+            / input.iter().map(|x| (*x as f64).powi(2)).sum::<f64>();
+        assert!((correlation - 1.0).abs() < 0.1, "{correlation}");
+    }
+
+    #[test]
+    fn noise_shaping() {
+        let input = sine(0.25, 997.0, 1 << 16);
+        let tpdf = error(&input, &quantize(&input, Dither::Tpdf));
+        let shaped = error(&input, &quantize(&input, Dither::NoiseShaped));
+
+        // Less noise where it's audible, more in total.
+        let tpdf_low = rms_db(&low_band(&tpdf));
+        let shaped_low = rms_db(&low_band(&shaped));
+        assert!(
+            shaped_low < tpdf_low - 15.0,
+            "{shaped_low:.1}dB vs {tpdf_low:.1}dB"
+        );
+        assert!(rms_db(&shaped) > rms_db(&tpdf));
+        assert!(rms_db(&shaped) < rms_db(&tpdf) + 9.0);
+    }
+}
@@ -1,12 +1,42 @@
+pub mod backend;
 pub mod decoder;
+pub mod dsp;
 pub mod engine;
+pub mod eq;
+pub mod error;
+pub mod event;
+pub mod format;
+pub mod limiter;
+pub mod loudness;
 pub mod metadata;
+pub mod mix;
+pub mod null;
+pub mod queue;
+pub mod replaygain;
+pub mod resample;
+pub mod ring;
 pub mod state;
+pub mod volume;
 
+pub use backend::*;
 pub use decoder::*;
+pub use dsp::*;
 pub use engine::*;
+pub use eq::*;
+pub use error::*;
+pub use event::*;
+pub use format::*;
+pub use limiter::*;
+pub use loudness::*;
 pub use metadata::*;
+pub use mix::*;
+pub use null::*;
+pub use queue::*;
+pub use replaygain::*;
+pub use resample::*;
+pub use ring::*;
 pub use state::*;
+pub use volume::*;
 
 #[cfg(target_os = "macos")]
 pub mod macos;
@@ -18,8 +48,19 @@ pub mod windows;
 #[cfg(target_os = "windows")]
 pub use windows::*;
 
+#[cfg(target_os = "linux")]
+pub mod alsa;
+#[cfg(target_os = "linux")]
+pub use alsa::*;
+
+#[cfg(all(target_os = "linux", feature = "pulse"))]
+pub mod pulse;
+#[cfg(all(target_os = "linux", feature = "pulse"))]
+pub use pulse::*;
+
 use std::sync::Arc;
 use std::sync::atomic::Ordering::Relaxed;
+use std::sync::mpsc::{Receiver, channel};
 use std::thread::JoinHandle;
 use std::time::Duration;
 
@@ -39,27 +80,55 @@ pub enum State {
     Stopped = 2,
 }
 
+impl State {
+    pub fn from_u8(state: u8) -> Self {
+        match state {
+            x if x == State::Playing as u8 => State::Playing,
+            x if x == State::Paused as u8 => State::Paused,
+            _ => State::Stopped,
+        }
+    }
+}
+
 pub struct Player {
     pub state: Arc<PlayerState>,
+    pub backend: Arc<dyn AudioBackend>,
     pub device: Device,
-    pub current_song_sample_rate: Option<u32>,
     thread: Option<JoinHandle<()>>,
+    decoder_thread: Option<JoinHandle<()>>,
 }
 
 impl Player {
     pub fn new(device: Device) -> Self {
+        Self::with_backend(default_backend(), device)
+    }
+
+    pub fn with_backend(backend: Arc<dyn AudioBackend>, device: Device) -> Self {
         let state = PlayerState::new();
-        let d = device.clone();
+        let output = backend.open(&device, None, Layout::STEREO.channels());
+        if output.is_none() {
+            state.set_error(RuntimeError::OutputOpen);
+        }
+
+        let (producer, consumer) = Ring::with_capacity(RING_SAMPLES);
+
+        let thread_state = Arc::clone(&state);
+        let decoder_thread = std::thread::spawn(move || {
+            run_decoder(thread_state, producer);
+        });
+
+        let thread_backend = Arc::clone(&backend);
         let thread_state = Arc::clone(&state);
         let thread = std::thread::spawn(move || {
-            run_output(thread_state, new_output(d, None));
+            run_output(thread_backend, thread_state, output, consumer);
         });
 
         Self {
             state,
+            backend,
             device,
-            current_song_sample_rate: None,
             thread: Some(thread),
+            decoder_thread: Some(decoder_thread),
         }
     }
 
@@ -76,42 +145,57 @@ impl Player {
         self.state.state.store(State::Stopped as u8, Relaxed);
     }
 
+    /// `replay_gain` is a linear gain to play at, `None` uses the song's
+    /// ReplayGain tags as set with `set_replay_gain_mode`.
     pub fn play_song(
         &mut self,
         path: impl AsRef<std::path::Path>,
         replay_gain: Option<f32>,
         start_playback: bool,
-    ) -> Result<(), String> {
-        let decoder = match Symphonia::new(&path) {
-            Ok(s) => s,
-            Err(e) => {
-                return Err(format!(
-                    "Failed to play: {}, Error: {e}",
-                    path.as_ref().to_string_lossy()
-                ));
-            }
-        };
+    ) -> Result<(), Error> {
+        let decoder = Symphonia::new(path)?;
+        self.play_decoder(decoder, replay_gain, start_playback, NOT_QUEUED);
+        Ok(())
+    }
+
+    /// Like `play_song` for audio that isn't a file. `hint` is an extension or MIME type,
+    /// see `Symphonia::from_source`.
+    pub fn play_source(
+        &mut self,
+        source: Box<dyn MediaSource>,
+        hint: Option<&str>,
+        replay_gain: Option<f32>,
+        start_playback: bool,
+    ) -> Result<(), Error> {
+        let decoder = Symphonia::from_source(source, hint)?;
+        self.play_decoder(decoder, replay_gain, start_playback, NOT_QUEUED);
+        Ok(())
+    }
 
-        if self.current_song_sample_rate.unwrap_or_default() != decoder.sample_rate {
-            if let Some(output) = try_new_output(self.device.clone(), Some(decoder.sample_rate)) {
+    fn play_decoder(
+        &mut self,
+        decoder: Symphonia,
+        replay_gain: Option<f32>,
+        start_playback: bool,
+        id: u64,
+    ) {
+        if self.current_song_sample_rate() != Some(decoder.sample_rate) {
+            if let Some(output) = self.backend.open_or_native(
+                &self.device,
+                Some(decoder.sample_rate),
+                decoder.layout.channels(),
+            ) {
                 self.state.pending_output.publish(output);
             } else {
                 self.state.set_error(RuntimeError::OutputOpen);
             }
         }
 
-        self.current_song_sample_rate = Some(decoder.sample_rate);
-
+        self.state.pending_next.take();
         self.state.state.store(State::Stopped as u8, Relaxed);
-        self.state.elapsed.store(0, Relaxed);
-        self.state.finished.store(false, Relaxed);
-        self.state
-            .duration
-            .store(decoder.duration.as_nanos() as u64, Relaxed);
-        self.state
-            .gain
-            .store(replay_gain.unwrap_or(0.5).to_bits(), Relaxed);
-        self.state.decoder_pending.store(true, Relaxed);
+        let gain =
+            replay_gain.unwrap_or_else(|| self.state.replay_gain(&decoder.replay_gain, false));
+        self.state.load(&decoder, gain, id);
         self.state.pending_decoder.publish(decoder);
 
         if start_playback {
@@ -119,10 +203,198 @@ impl Player {
         } else {
             self.state.state.store(State::Paused as u8, Relaxed);
         }
+    }
+
+    /// Opens `path` now and plays it straight after the current song without a gap.
+    /// The output is only reopened if the sample rate differs.
+    ///
+    /// Queued after the current one finished decoding, about `RING_SAMPLES` ahead of its end,
+    /// it still joins if the output hasn't played out what's buffered yet. Otherwise it
+    /// starts like `play_song`.
+    /// Queuing again replaces the previous one until it has started.
+    pub fn queue_next(
+        &mut self,
+        path: impl AsRef<std::path::Path>,
+        replay_gain: Option<f32>,
+    ) -> Result<(), Error> {
+        if self.is_finished() || self.current_song_sample_rate().is_none() {
+            return self.play_song(path, replay_gain, true);
+        }
+
+        let decoder = Symphonia::new(&path)?;
+
+        self.state.next_queued.store(true, Relaxed);
+        self.state.pending_next.publish(NextSong {
+            gain: replay_gain
+                .unwrap_or_else(|| self.state.replay_gain(&decoder.replay_gain, false)),
+            decoder,
+            id: NOT_QUEUED,
+        });
 
         Ok(())
     }
 
+    /// True from `queue_next` until the output reaches the first sample of that song.
+    pub fn is_next_queued(&self) -> bool {
+        self.state.next_queued.load(Relaxed)
+    }
+
+    /// Songs played from the queue advance to the next one on their own,
+    /// joined gaplessly or crossfaded like `queue_next`.
+    pub fn queue_append(&self, song: Song) {
+        self.state.queue.lock().unwrap().append(song);
+    }
+
+    pub fn queue_insert(&self, index: usize, song: Song) {
+        self.state.queue.lock().unwrap().insert(index, song);
+    }
+
+    /// Removing the playing song lets it finish, then continues with the one after it.
+    pub fn queue_remove(&self, index: usize) -> Option<Song> {
+        self.state.queue.lock().unwrap().remove(index)
+    }
+
+    pub fn queue_move(&self, from: usize, to: usize) {
+        self.state.queue.lock().unwrap().move_entry(from, to);
+    }
+
+    pub fn queue_clear(&self) {
+        self.state.queue.lock().unwrap().clear();
+    }
+
+    /// The queue in the order songs were added, shuffling doesn't change it.
+    pub fn queue(&self) -> Vec<Song> {
+        let queue = self.state.queue.lock().unwrap();
+        queue.entries.iter().map(|e| e.song.clone()).collect()
+    }
+
+    /// Index into `queue()` of the song being heard, `None` if it wasn't played from the queue.
+    pub fn queue_index(&self) -> Option<usize> {
+        let id = self.state.playing_id.load(Relaxed);
+        self.state.queue.lock().unwrap().index_of(id)
+    }
+
+    pub fn set_repeat(&self, repeat: Repeat) {
+        self.state.queue.lock().unwrap().repeat = repeat;
+    }
+
+    pub fn repeat(&self) -> Repeat {
+        self.state.queue.lock().unwrap().repeat
+    }
+
+    /// The playing song stays where it is, everything else is shuffled after it.
+    pub fn set_shuffle(&self, shuffle: Shuffle) {
+        let current = self.state.playing_id.load(Relaxed);
+        self.state
+            .queue
+            .lock()
+            .unwrap()
+            .set_shuffle(shuffle, Some(current));
+    }
+
+    pub fn shuffle(&self) -> Shuffle {
+        self.state.queue.lock().unwrap().shuffle
+    }
+
+    /// Plays the song at `index` in `queue()`.
+    pub fn jump(&mut self, index: usize) -> Result<(), Error> {
+        let entry = self.state.queue.lock().unwrap().get(index).cloned();
+        let Some(entry) = entry else {
+            return Err(Error::QueueIndex(index));
+        };
+        self.play_entry(entry)
+    }
+
+    /// Skips to the next song in play order, stops at the end of the queue unless repeating.
+    pub fn skip_next(&mut self) -> Result<(), Error> {
+        let queue = self.state.queue.lock().unwrap();
+        let next = queue.skip_after(self.playing_id());
+        let entry = next.and_then(|id| queue.entry(id)).cloned();
+        drop(queue);
+
+        match entry {
+            Some(entry) => self.play_entry(entry),
+            None => {
+                self.stop();
+                Ok(())
+            }
+        }
+    }
+
+    /// Goes back a song in play order, or to the start of the first one.
+    pub fn skip_previous(&mut self) -> Result<(), Error> {
+        let queue = self.state.queue.lock().unwrap();
+        let previous = queue.previous_before(self.playing_id());
+        let entry = previous.and_then(|id| queue.entry(id)).cloned();
+        drop(queue);
+
+        match entry {
+            Some(entry) => self.play_entry(entry),
+            None => {
+                self.seek_to(Duration::ZERO);
+                Ok(())
+            }
+        }
+    }
+
+    fn playing_id(&self) -> Option<u64> {
+        Some(self.state.playing_id.load(Relaxed)).filter(|id| *id != NOT_QUEUED)
+    }
+
+    fn play_entry(&mut self, entry: QueueEntry) -> Result<(), Error> {
+        let decoder = Symphonia::new(&entry.song.path)?;
+        let tags = match entry.song.replay_gain() {
+            tags if tags.is_empty() => decoder.replay_gain,
+            tags => tags,
+        };
+        let in_order = self.state.queue.lock().unwrap().shuffle != Shuffle::Songs;
+        let gain = self.state.replay_gain(&tags, in_order);
+        self.play_decoder(decoder, Some(gain), true, entry.id);
+        Ok(())
+    }
+
+    /// Fades queued songs into each other over `duration`, zero turns it off.
+    /// Songs that need the output reopened are still joined back to back.
+    pub fn set_crossfade(&self, duration: Duration, curve: FadeCurve) {
+        self.state
+            .crossfade_ms
+            .store(duration.as_millis().min(u32::MAX as u128) as u32, Relaxed);
+        self.state.crossfade_curve.store(curve as u8, Relaxed);
+    }
+
+    pub fn crossfade(&self) -> (Duration, FadeCurve) {
+        let curve = FadeCurve::from_u8(self.state.crossfade_curve.load(Relaxed));
+        (
+            Duration::from_millis(self.state.crossfade_ms.load(Relaxed) as u64),
+            curve,
+        )
+    }
+
+    /// How long pausing, resuming, seeking, stopping and volume changes take to fade,
+    /// up to `MAX_FADE_MS`. Zero makes them instant, which can click.
+    pub fn set_fade_duration(&self, duration: Duration) {
+        self.state.fade_ms.store(
+            duration.as_millis().min(MAX_FADE_MS as u128) as u32,
+            Relaxed,
+        );
+    }
+
+    pub fn fade_duration(&self) -> Duration {
+        Duration::from_millis(self.state.fade_ms.load(Relaxed) as u64)
+    }
+
+    /// Skips the crossfade between consecutive songs of the same album.
+    pub fn set_smart_crossfade(&self, smart: bool) {
+        self.state.smart_crossfade.store(smart, Relaxed);
+    }
+
+    pub fn current_song_sample_rate(&self) -> Option<u32> {
+        match self.state.sample_rate.load(Relaxed) {
+            0 => None,
+            rate => Some(rate),
+        }
+    }
+
     pub fn play(&self) {
         self.state.state.store(State::Playing as u8, Relaxed);
     }
@@ -131,41 +403,108 @@ impl Player {
         self.state.state.store(State::Paused as u8, Relaxed);
     }
 
+    /// Volume at 100% is `100 / reduction`, the level stays at the same percentage.
     pub fn set_volume_reduction(&self, reduction: f32) {
         let reduction = reduction.max(1.0);
-        let level = (f32::from_bits(self.state.volume.load(Relaxed))
-            * f32::from_bits(self.state.volume_reduction.load(Relaxed)))
-        .clamp(0.0, 100.0);
+        let curve = self.volume_curve();
+        let percent = linear_to_percent(
+            self.volume_linear(),
+            curve,
+            f32::from_bits(self.state.volume_reduction.load(Relaxed)),
+        );
         self.state
             .volume_reduction
             .store(reduction.to_bits(), Relaxed);
-        self.state
-            .volume
-            .store((level / reduction).to_bits(), Relaxed);
+        self.set_volume_linear(percent_to_linear(percent, curve, reduction));
     }
 
+    /// Volume from 0 to 100 through the volume curve, see `percent_to_linear`.
     pub fn set_volume(&self, volume: u8) {
-        let reduction = f32::from_bits(self.state.volume_reduction.load(Relaxed));
-        self.state
-            .volume
-            .store((volume.clamp(0, 100) as f32 / reduction).to_bits(), Relaxed);
+        self.set_volume_linear(percent_to_linear(
+            volume.clamp(0, 100) as f32,
+            self.volume_curve(),
+            f32::from_bits(self.state.volume_reduction.load(Relaxed)),
+        ));
     }
 
+    /// Rounded to the nearest percent, and 100 for anything louder.
     pub fn volume(&self) -> u8 {
-        let reduction = f32::from_bits(self.state.volume_reduction.load(Relaxed));
-        (f32::from_bits(self.state.volume.load(Relaxed)) * reduction) as u8
+        self.state.volume_percent()
+    }
+
+    /// Volume as a gain on the song's samples, 1.0 plays them unchanged.
+    /// Limited to `MAX_VOLUME_DB`.
+    pub fn set_volume_linear(&self, volume: f32) {
+        let volume = if volume > 0.0 {
+            volume.min(db_to_linear(MAX_VOLUME_DB))
+        } else {
+            0.0
+        };
+        self.state.volume.store(volume.to_bits(), Relaxed);
+    }
+
+    pub fn volume_linear(&self) -> f32 {
+        f32::from_bits(self.state.volume.load(Relaxed))
+    }
+
+    /// Volume in decibels, 0dB is a linear volume of 1.0 and negative infinity is silence.
+    /// Limited to `MAX_VOLUME_DB`.
+    pub fn set_volume_db(&self, db: f32) {
+        self.set_volume_linear(db_to_linear(db));
+    }
+
+    pub fn volume_db(&self) -> f32 {
+        linear_to_db(self.volume_linear())
+    }
+
+    /// Changes how `set_volume` percentages map to gains. The volume doesn't change,
+    /// only the percentage it is reported at.
+    pub fn set_volume_curve(&self, curve: VolumeCurve) {
+        self.state.volume_curve.store(curve as u8, Relaxed);
+    }
+
+    pub fn volume_curve(&self) -> VolumeCurve {
+        VolumeCurve::from_u8(self.state.volume_curve.load(Relaxed))
+    }
+
+    /// Silences the output without losing the volume, which can still be changed while muted.
+    pub fn set_muted(&self, muted: bool) {
+        self.state.muted.store(muted, Relaxed);
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.state.muted.load(Relaxed)
+    }
+
+    /// Returns whether the player is muted now.
+    pub fn toggle_mute(&self) -> bool {
+        !self.state.muted.fetch_xor(true, Relaxed)
     }
 
     pub fn state(&self) -> State {
-        match self.state.state.load(Relaxed) {
-            x if x == State::Playing as u8 => State::Playing,
-            x if x == State::Paused as u8 => State::Paused,
-            _ => State::Stopped,
-        }
+        State::from_u8(self.state.state.load(Relaxed))
+    }
+
+    pub fn last_error(&self) -> RuntimeError {
+        RuntimeError::from_u8(self.state.last_error.load(Relaxed))
+    }
+
+    /// Events are sent from the output thread as it notices changes, so they can lag
+    /// the calls that caused them by a period or so. Drop the receiver to unsubscribe.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (sender, receiver) = channel();
+        self.state.subscribers.lock().unwrap().push(sender);
+        receiver
     }
 
+    /// What is being heard now, the frames written minus the output's latency.
     pub fn elapsed(&self) -> Duration {
-        Duration::from_nanos(self.state.elapsed.load(Relaxed))
+        self.state.elapsed()
+    }
+
+    /// Frames of the current song written to the output, at the song's sample rate.
+    pub fn position_frames(&self) -> u64 {
+        self.state.position.load(Relaxed)
     }
 
     pub fn duration(&self) -> Duration {
@@ -176,6 +515,14 @@ impl Player {
         self.state.finished.load(Relaxed)
     }
 
+    /// Number of times the output ran out of decoded audio, and the frames of silence that caused.
+    pub fn underruns(&self) -> (u64, u64) {
+        (
+            self.state.underruns.load(Relaxed),
+            self.state.underrun_frames.load(Relaxed),
+        )
+    }
+
     pub fn volume_up(&self) {
         self.set_volume((self.volume() + 5).clamp(0, 100));
     }
@@ -188,6 +535,105 @@ impl Player {
         self.state.seek.store(position.as_nanos() as u64, Relaxed);
     }
 
+    /// Accurate seeks land on the exact frame asked for, at the cost of decoding from the
+    /// packet before it. Coarse seeks, the default, start from that packet.
+    pub fn set_accurate_seek(&self, accurate: bool) {
+        self.state.accurate_seek.store(accurate, Relaxed);
+    }
+
+    /// Songs the device can't be opened at are resampled to its own rate with this quality.
+    pub fn set_resample_quality(&self, quality: ResampleQuality) {
+        self.state.resample_quality.store(quality as u8, Relaxed);
+    }
+
+    /// Which ReplayGain tags songs play with, from the next song on.
+    /// Songs given an explicit gain ignore this.
+    pub fn set_replay_gain_mode(&self, mode: ReplayGainMode) {
+        self.state.replay_gain_mode.store(mode as u8, Relaxed);
+    }
+
+    /// Raises or lowers tagged songs by `db`, up to `MAX_REPLAY_GAIN_PREAMP` either way.
+    /// Peaks are still kept below full scale. Infinite or NaN values are ignored.
+    pub fn set_replay_gain_preamp(&self, db: f32) {
+        if !db.is_finite() {
+            return;
+        }
+        let db = db.clamp(-MAX_REPLAY_GAIN_PREAMP, MAX_REPLAY_GAIN_PREAMP);
+        self.state.replay_gain_preamp.store(db.to_bits(), Relaxed);
+    }
+
+    /// Linear gain for songs without ReplayGain tags, `DEFAULT_FALLBACK_GAIN` by default.
+    pub fn set_fallback_gain(&self, gain: f32) {
+        self.state.fallback_gain.store(gain.to_bits(), Relaxed);
+    }
+
+    /// Only used by outputs that were opened with an integer format, float output is never dithered.
+    pub fn set_dither(&self, dither: Dither) {
+        self.state.dither.store(dither as u8, Relaxed);
+    }
+
+    /// Replaces the equalizer settings, the change is smoothed so it can be made while playing.
+    /// An empty preset turns the equalizer off.
+    pub fn set_equalizer(&self, preset: EqPreset) {
+        let mut current = self.state.eq_preset.lock().unwrap();
+        let rate = self.state.processing_rate();
+        self.state
+            .pending_eq
+            .publish(Equalizer::transition(&current.0, &preset, rate));
+        *current = (preset, rate);
+    }
+
+    /// Replaces the effects run on the output. The chain is prepared here for the output's
+    /// rate and channels and the old one is dropped by the decoder thread.
+    /// `DspChain::new()` removes them.
+    pub fn set_dsp_chain(&self, mut chain: DspChain) {
+        chain.prepare(
+            self.state.processing_rate(),
+            self.state.output_channels.load(Relaxed) as usize,
+        );
+        self.state.pending_dsp.publish(chain);
+    }
+
+    /// Runs the output through a look-ahead true peak limiter after the volume, so gains
+    /// above unity don't clip. Turning it on or off crossfades so it can be done while playing,
+    /// the output is delayed by a little over `LIMITER_LOOKAHEAD` either way.
+    pub fn set_limiter(&self, enabled: bool) {
+        self.state.limiter.store(enabled, Relaxed);
+    }
+
+    /// Highest true peak the limiter lets through in dBTP, up to 0.
+    pub fn set_limiter_ceiling(&self, ceiling: f32) {
+        self.state
+            .limiter_ceiling
+            .store(ceiling.min(0.0).to_bits(), Relaxed);
+    }
+
+    /// Time the limiter takes to turn back up after a peak, between 1ms and 5s.
+    pub fn set_limiter_release(&self, release: Duration) {
+        let release = release.clamp(MIN_LIMITER_RELEASE, MAX_LIMITER_RELEASE);
+        self.state
+            .limiter_release_ms
+            .store(release.as_millis() as u32, Relaxed);
+    }
+
+    /// Number of samples the limiter turned down, and samples that went past full scale and
+    /// clipped when written to the output, whether the limiter was on or not.
+    pub fn limited_samples(&self) -> (u64, u64) {
+        (
+            self.state.limited_samples.load(Relaxed),
+            self.state.clipped_samples.load(Relaxed),
+        )
+    }
+
+    /// The rate the output is running at, which differs from
+    /// `current_song_sample_rate` while resampling.
+    pub fn output_sample_rate(&self) -> Option<u32> {
+        match self.state.output_rate.load(Relaxed) {
+            0 => None,
+            rate => Some(rate),
+        }
+    }
+
     pub fn seek_forward(&self, secs: f32) {
         self.state.seek.store(
             (self.elapsed() + Duration::from_secs_f32(secs)).as_nanos() as u64,
@@ -204,25 +650,45 @@ impl Player {
         );
     }
 
-    pub fn set_output_device(&mut self, device: Device) {
-        self.device = device.clone();
+    /// The device is switched to even if it fails to open, so it's retried with the next song.
+    pub fn set_output_device(&mut self, device: Device) -> Result<(), Error> {
         self.state.follow_default.store(false, Relaxed);
-        if let Some(output) = try_new_output(device, self.current_song_sample_rate) {
-            self.state.pending_output.publish(output);
-        } else {
-            self.state.set_error(RuntimeError::OutputOpen);
-        }
+        let output = self.backend.open_or_native(
+            &device,
+            self.current_song_sample_rate(),
+            self.state.channels.load(Relaxed) as usize,
+        );
+        self.device = device;
+        self.publish_output(output)
     }
 
-    pub fn follow_default_device(&mut self, follow: bool) {
+    pub fn follow_default_device(&mut self, follow: bool) -> Result<(), Error> {
         self.state.follow_default.store(follow, Relaxed);
-        if follow {
-            let device = OutputDevices::new().default_device();
-            self.device = device.clone();
-            if let Some(output) = try_new_output(device, self.current_song_sample_rate) {
+        if !follow {
+            return Ok(());
+        }
+        let Some(device) = self.backend.default_device() else {
+            self.state.set_error(RuntimeError::OutputOpen);
+            return Err(Error::Device(String::from("no default device")));
+        };
+        let output = self.backend.open_or_native(
+            &device,
+            self.current_song_sample_rate(),
+            self.state.channels.load(Relaxed) as usize,
+        );
+        self.device = device;
+        self.publish_output(output)
+    }
+
+    fn publish_output(&self, output: Option<Box<dyn OutputStream>>) -> Result<(), Error> {
+        match output {
+            Some(output) => {
                 self.state.pending_output.publish(output);
-            } else {
+                Ok(())
+            }
+            None => {
                 self.state.set_error(RuntimeError::OutputOpen);
+                Err(Error::Device(self.device.name.clone()))
             }
         }
     }
@@ -232,6 +698,9 @@ impl Player {
         if let Some(thread) = self.thread.take() {
             let _ = thread.join();
         }
+        if let Some(thread) = self.decoder_thread.take() {
+            let _ = thread.join();
+        }
     }
 }
 
@@ -0,0 +1,359 @@
+use crate::loudness::TruePeak;
+use crate::{Processor, db_to_linear};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Highest true peak let through, in dBTP. The headroom covers lossy encoders and
+/// the reconstruction filters of most DACs.
+pub const DEFAULT_LIMITER_CEILING: f32 = -1.0;
+pub const DEFAULT_LIMITER_RELEASE: Duration = Duration::from_millis(100);
+pub const MIN_LIMITER_RELEASE: Duration = Duration::from_millis(1);
+pub const MAX_LIMITER_RELEASE: Duration = Duration::from_secs(5);
+/// How long before a peak the gain starts coming down, also the delay the limiter adds.
+pub const LIMITER_LOOKAHEAD: Duration = Duration::from_millis(2);
+/// How long turning it on or off crossfades between the input and the limited output.
+const BYPASS_FADE: Duration = Duration::from_millis(10);
+
+/// Look-ahead true peak limiter. The gain ramps down ahead of a peak so the output stays
+/// under the ceiling without clipping, then recovers over the release time.
+pub struct Limiter {
+    sample_rate: u32,
+    channels: usize,
+    /// Linear.
+    ceiling: f32,
+    release: Duration,
+    /// How far the gain recovers each frame.
+    release_amount: f64,
+    peaks: TruePeak,
+    /// Frames the gain takes to ramp down, the length of `minimums`.
+    attack: usize,
+    /// Interleaved frames on their way through, the one at `delay_pos` is the oldest.
+    delay: Vec<f32>,
+    delay_pos: usize,
+    /// Frames of input still in `delay`, the rest of it is silence.
+    held: usize,
+    /// Gains needed by the frames still in `delay` and the one just pushed, increasing from
+    /// the front, with the frame each was needed for.
+    needed: VecDeque<(u64, f32)>,
+    /// The smallest needed gain at each of the last `attack` frames, averaged into a ramp.
+    minimums: Vec<f32>,
+    minimums_pos: usize,
+    minimums_sum: f32,
+    frame: u64,
+    /// Kept in f64 so the release can get all the way back to unity.
+    gain: f64,
+    /// Samples turned down since the last `take_limited`.
+    limited: u64,
+    enabled: bool,
+    /// How much of the limited output is heard, moving towards 1 while enabled and 0 while not.
+    wet: f32,
+    wet_step: f32,
+}
+
+impl Limiter {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+        let attack = ((sample_rate as f64 * LIMITER_LOOKAHEAD.as_secs_f64()) as usize).max(1);
+        // A peak between samples is found up to `TruePeak::DELAY` frames after its sample,
+        // the audio is held back that much longer so the gain is down in time.
+        let delay = attack + TruePeak::DELAY;
+        let mut limiter = Self {
+            sample_rate,
+            channels,
+            ceiling: db_to_linear(DEFAULT_LIMITER_CEILING),
+            release: DEFAULT_LIMITER_RELEASE,
+            release_amount: 0.0,
+            peaks: TruePeak::new(sample_rate, channels),
+            attack,
+            delay: vec![0.0; delay * channels],
+            delay_pos: 0,
+            held: 0,
+            needed: VecDeque::with_capacity(delay + 1),
+            minimums: vec![1.0; attack],
+            minimums_pos: 0,
+            minimums_sum: attack as f32,
+            frame: 0,
+            gain: 1.0,
+            limited: 0,
+            enabled: true,
+            wet: 1.0,
+            wet_step: (1.0 / (sample_rate as f64 * BYPASS_FADE.as_secs_f64()).max(1.0)) as f32,
+        };
+        limiter.set_release(limiter.release);
+        limiter
+    }
+
+    /// Highest true peak in dBTP, 0 is full scale.
+    pub fn set_ceiling(&mut self, ceiling: f32) {
+        self.ceiling = db_to_linear(ceiling.min(0.0));
+    }
+
+    pub fn release(&self) -> Duration {
+        self.release
+    }
+
+    /// Time for the gain to come back up once a peak has passed.
+    pub fn set_release(&mut self, release: Duration) {
+        self.release = release;
+        let frames = (self.sample_rate as f64 * release.as_secs_f64()).max(1.0);
+        // Within 2% of the target after `release`.
+        self.release_amount = 1.0 - (-4.0 / frames).exp();
+    }
+
+    /// Keeps running while off, so turning it back on crossfades into audio already in the
+    /// look-ahead instead of starting from silence. Off passes the input through the same
+    /// delay, so toggling never skips or repeats any of it.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Built for `sample_rate` and `channels`.
+    pub fn is_prepared(&self, sample_rate: u32, channels: usize) -> bool {
+        (self.sample_rate, self.channels) == (sample_rate, channels)
+    }
+
+    /// Carries the settings and counts of `old` over to a limiter built for a new rate or
+    /// channel count, without allocating. The audio held in its look-ahead is not.
+    pub fn take_over(&mut self, old: &Limiter) {
+        self.ceiling = old.ceiling;
+        self.set_release(old.release);
+        self.limited = old.limited;
+        self.enabled = old.enabled;
+        self.wet = old.wet;
+    }
+
+    /// Frames of delay the look-ahead adds, on or off.
+    pub fn latency(&self) -> usize {
+        self.delay.len() / self.channels
+    }
+
+    /// Frames of input that haven't come out of the look-ahead yet.
+    pub fn held(&self) -> usize {
+        self.held
+    }
+
+    /// Pushes silence through to get what is held out into `samples`,
+    /// returns the frames written.
+    pub fn drain(&mut self, samples: &mut [f32]) -> usize {
+        let frames = (samples.len() / self.channels).min(self.held);
+        let samples = &mut samples[..frames * self.channels];
+        samples.fill(0.0);
+        for frame in samples.chunks_exact_mut(self.channels) {
+            self.process_frame(frame);
+        }
+        self.held -= frames;
+        frames
+    }
+
+    /// Returns the number of samples turned down since the last call.
+    pub fn take_limited(&mut self) -> u64 {
+        std::mem::take(&mut self.limited)
+    }
+
+    fn process_frame(&mut self, frame: &mut [f32]) {
+        let mut peak = 0f32;
+        for (channel, sample) in frame.iter().enumerate() {
+            peak = peak.max(self.peaks.push(channel, *sample));
+        }
+        let needed = if peak > self.ceiling {
+            self.ceiling / peak
+        } else {
+            1.0
+        };
+
+        // Smallest gain needed by any frame in the delay, including this one.
+        while let Some(&(_, gain)) = self.needed.back()
+            && gain >= needed
+        {
+            self.needed.pop_back();
+        }
+        self.needed.push_back((self.frame, needed));
+        let window = self.latency() as u64 + 1;
+        while let Some(&(frame, _)) = self.needed.front()
+            && frame + window <= self.frame
+        {
+            self.needed.pop_front();
+        }
+        let minimum = self.needed.front().map_or(1.0, |(_, gain)| *gain);
+        self.frame += 1;
+
+        // Every minimum averaged here covers the frame leaving the delay,
+        // so the ramp is never above the gain that frame needs.
+        self.minimums_sum += minimum - self.minimums[self.minimums_pos];
+        self.minimums[self.minimums_pos] = minimum;
+        self.minimums_pos += 1;
+        if self.minimums_pos == self.attack {
+            self.minimums_pos = 0;
+            // Keeps rounding in the running sum from building up.
+            self.minimums_sum = self.minimums.iter().sum();
+        }
+        let target = (self.minimums_sum / self.attack as f32) as f64;
+        // Down straight away, the ramp is in the target already. Up over the release.
+        self.gain = if target - self.gain < 1e-6 {
+            target
+        } else {
+            self.gain + (target - self.gain) * self.release_amount
+        };
+
+        self.wet = if self.enabled {
+            (self.wet + self.wet_step).min(1.0)
+        } else {
+            (self.wet - self.wet_step).max(0.0)
+        };
+        let start = self.delay_pos * self.channels;
+        let delayed = &mut self.delay[start..start + self.channels];
+        for (sample, delayed) in frame.iter_mut().zip(delayed) {
+            let oldest = *delayed;
+            *delayed = *sample;
+            let limited = (oldest * self.gain as f32).clamp(-self.ceiling, self.ceiling);
+            // Both sides come out of the delay, so the crossfade lines up.
+            *sample = if self.wet == 1.0 {
+                limited
+            } else if self.wet > 0.0 {
+                oldest + (limited - oldest) * self.wet
+            } else {
+                oldest
+            };
+        }
+        self.delay_pos = (self.delay_pos + 1) % self.latency();
+        if self.gain < 1.0 && self.wet > 0.0 {
+            self.limited += self.channels as u64;
+        }
+    }
+}
+
+impl Processor for Limiter {
+    /// A new rate or channel count starts over with the same settings.
+    fn prepare(&mut self, sample_rate: u32, channels: usize) {
+        if !self.is_prepared(sample_rate, channels.max(1)) {
+            let mut limiter = Limiter::new(sample_rate, channels);
+            limiter.take_over(self);
+            *self = limiter;
+        }
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_exact_mut(self.channels) {
+            self.process_frame(frame);
+        }
+        self.held = (self.held + samples.len() / self.channels).min(self.latency());
+    }
+
+    /// Settings are kept, audio held in the look-ahead is dropped.
+    /// A crossfade from turning it on or off finishes straight away.
+    fn reset(&mut self) {
+        self.wet = if self.enabled { 1.0 } else { 0.0 };
+        self.peaks.reset();
+        self.delay.fill(0.0);
+        self.held = 0;
+        self.needed.clear();
+        self.minimums.fill(1.0);
+        self.minimums_sum = self.attack as f32;
+        self.gain = 1.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Layout, LoudnessMeter};
+    use std::f64::consts::PI;
+
+    const RATE: u32 = 44100;
+
+    /// Stereo sine at `amplitude`, a quarter of the sample rate over a non integer period
+    /// so peaks fall between samples.
+    fn sine(amplitude: f32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .flat_map(|i| {
+                let x =
+                    amplitude * (2.0 * PI * 11025.5 * i as f64 / RATE as f64 + 0.7).sin() as f32;
+                [x, x]
+            })
+            .collect()
+    }
+
+    fn true_peak(samples: &[f32]) -> f32 {
+        let mut meter = LoudnessMeter::new(RATE, Layout::default_for(2));
+        meter.push(samples);
+        meter.finish().true_peak
+    }
+
+    #[test]
+    fn stays_under_ceiling() {
+        let mut limiter = Limiter::new(RATE, 2);
+        let quiet = sine(0.5, RATE as usize / 2);
+        let loud = sine(2.0, RATE as usize / 10);
+        let mut samples = [&quiet[..], &loud, &quiet].concat();
+        limiter.process(&mut samples);
+
+        let ceiling = db_to_linear(DEFAULT_LIMITER_CEILING);
+        assert!(samples.iter().all(|s| s.abs() <= ceiling));
+        // Interpolation makes the true peak of the output a little less precise.
+        assert!(
+            true_peak(&samples) < ceiling * 1.02,
+            "{}",
+            true_peak(&samples)
+        );
+        assert!(limiter.take_limited() > 2 * RATE as u64 / 10);
+        assert_eq!(limiter.take_limited(), 0);
+
+        // Back to where it was once the release is over.
+        let latency = limiter.latency() * 2;
+        let end = &samples[samples.len() - 1000..];
+        let expected = &quiet[quiet.len() - 1000 - latency..quiet.len() - latency];
+        assert_eq!(end, expected);
+    }
+
+    #[test]
+    fn quiet_passes_through() {
+        let mut limiter = Limiter::new(RATE, 2);
+        let input = sine(0.8, 4096);
+        let mut samples = input.clone();
+        limiter.process(&mut samples);
+
+        let latency = limiter.latency() * 2;
+        assert!(samples[..latency].iter().all(|s| *s == 0.0));
+        assert_eq!(samples[latency..], input[..input.len() - latency]);
+        assert_eq!(limiter.take_limited(), 0);
+
+        // What's still held comes out when drained, then nothing more.
+        let mut tail = vec![1.0; latency * 2];
+        assert_eq!(limiter.drain(&mut tail), latency / 2);
+        assert_eq!(tail[..latency], input[input.len() - latency..]);
+        assert_eq!(limiter.drain(&mut tail), 0);
+    }
+
+    #[test]
+    fn ramps_ahead_of_peak() {
+        let mut limiter = Limiter::new(RATE, 1);
+        let mut samples = vec![0.5; 1000];
+        samples[600] = 1.0;
+        limiter.process(&mut samples);
+
+        let latency = limiter.latency();
+        let ceiling = db_to_linear(DEFAULT_LIMITER_CEILING);
+        // The gain is already down before the peak and slid there without a step.
+        assert!(samples[599 + latency] <= 0.5 * ceiling * 1.0001);
+        let gains: Vec<f32> = samples[latency..600 + latency]
+            .iter()
+            .map(|s| s / 0.5)
+            .collect();
+        assert!(gains.windows(2).all(|w| w[0] - w[1] < 0.01));
+    }
+
+    #[test]
+    fn takes_over_settings() {
+        let mut old = Limiter::new(RATE, 2);
+        old.set_ceiling(-6.0);
+        old.set_release(Duration::from_millis(20));
+        old.process(&mut sine(2.0, 1000));
+
+        let mut limiter = Limiter::new(48000, 6);
+        limiter.take_over(&old);
+        assert!(limiter.is_prepared(48000, 6));
+        assert_eq!(limiter.ceiling, old.ceiling);
+        assert_eq!(limiter.release(), Duration::from_millis(20));
+        assert!(limiter.take_limited() > 0);
+    }
+}
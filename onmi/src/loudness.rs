@@ -0,0 +1,493 @@
+use crate::resample::{kaiser, sinc};
+use crate::{Error, Layout, ReplayGain, Symphonia, db_to_linear};
+use std::path::Path;
+
+/// Loudness ReplayGain 2.0 brings songs to, in LUFS.
+pub const REFERENCE_LOUDNESS: f64 = -18.0;
+
+/// Blocks quieter than this are silence and never counted, in LUFS.
+const ABSOLUTE_GATE: f64 = -70.0;
+/// Blocks more than this far below the average are ignored by the integrated loudness, in LU.
+const RELATIVE_GATE: f64 = -10.0;
+/// The same for the loudness range.
+const RANGE_GATE: f64 = -20.0;
+/// Momentary blocks are 400ms and short-term blocks 3s, both moved along in 100ms steps.
+const MOMENTARY_STEPS: usize = 4;
+const SHORT_TERM_STEPS: usize = 30;
+/// Taps per phase of the true peak interpolator.
+const PEAK_TAPS: usize = 12;
+
+/// Loudness of a song or an album per ITU BS.1770-4 and EBU R128.
+#[derive(Clone, Debug)]
+pub struct Loudness {
+    /// Gated integrated loudness in LUFS, negative infinity for silence.
+    pub integrated: f64,
+    /// Loudness range in LU, how far apart the quiet and loud parts are.
+    pub range: f64,
+    /// Largest sample, linear where 1.0 is full scale.
+    pub sample_peak: f32,
+    /// Largest value between samples as well, found by oversampling. Also linear.
+    pub true_peak: f32,
+    /// Mean square of each momentary and short-term block, kept for album loudness.
+    momentary: Vec<f64>,
+    short_term: Vec<f64>,
+}
+
+impl Loudness {
+    /// Linear gain to bring this to `REFERENCE_LOUDNESS`, the same as `Song::gain`.
+    /// Zero for silence, which plays at the fallback gain like an untagged song.
+    pub fn gain(&self) -> f32 {
+        if self.integrated.is_finite() {
+            db_to_linear((REFERENCE_LOUDNESS - self.integrated) as f32)
+        } else {
+            0.0
+        }
+    }
+
+    /// Tags for a song with this loudness, using true peaks.
+    pub fn replay_gain(&self, album: Option<&Loudness>) -> ReplayGain {
+        ReplayGain {
+            track_gain: self.gain(),
+            track_peak: self.true_peak,
+            album_gain: album.map_or(0.0, Loudness::gain),
+            album_peak: album.map_or(0.0, |album| album.true_peak),
+        }
+    }
+}
+
+/// Nothing measured is silence.
+impl Default for Loudness {
+    fn default() -> Self {
+        Self {
+            integrated: f64::NEG_INFINITY,
+            range: 0.0,
+            sample_peak: 0.0,
+            true_peak: 0.0,
+            momentary: Vec::new(),
+            short_term: Vec::new(),
+        }
+    }
+}
+
+/// Loudness of an album, measured as if its songs were played back to back.
+pub fn album_loudness(songs: &[Loudness]) -> Loudness {
+    let momentary: Vec<f64> = songs.iter().flat_map(|s| &s.momentary).copied().collect();
+    let short_term: Vec<f64> = songs.iter().flat_map(|s| &s.short_term).copied().collect();
+    Loudness {
+        integrated: integrated(&momentary),
+        range: range(&short_term),
+        sample_peak: songs.iter().map(|s| s.sample_peak).fold(0.0, f32::max),
+        true_peak: songs.iter().map(|s| s.true_peak).fold(0.0, f32::max),
+        momentary,
+        short_term,
+    }
+}
+
+/// Decodes the whole file and measures it.
+pub fn analyze_loudness(path: impl AsRef<Path>) -> Result<Loudness, Error> {
+    let mut decoder = Symphonia::new(path)?;
+    let mut meter = LoudnessMeter::new(decoder.sample_rate, decoder.layout);
+    let mut buffer = vec![0.0; 4096 * decoder.layout.channels().max(1)];
+    loop {
+        let frames = decoder.read_frames(&mut buffer);
+        meter.push(&buffer[..frames * meter.channels()]);
+        if frames * meter.channels() < buffer.len() {
+            break;
+        }
+    }
+    Ok(meter.finish())
+}
+
+/// Measures each song and the album they make up, in that order.
+pub fn analyze_album<P: AsRef<Path>>(paths: &[P]) -> Result<(Vec<Loudness>, Loudness), Error> {
+    let songs = paths
+        .iter()
+        .map(analyze_loudness)
+        .collect::<Result<Vec<_>, _>>()?;
+    let album = album_loudness(&songs);
+    Ok((songs, album))
+}
+
+/// Measures interleaved frames as they're pushed.
+pub struct LoudnessMeter {
+    /// BS.1770 weight of each channel, zero for the LFE.
+    weights: Vec<f64>,
+    filters: Vec<[Biquad; 2]>,
+    /// Frames in a 100ms step.
+    step: usize,
+    step_frames: usize,
+    step_sum: f64,
+    /// Mean squares of the steps so far, only the last `SHORT_TERM_STEPS` are used.
+    steps: Vec<f64>,
+    momentary: Vec<f64>,
+    short_term: Vec<f64>,
+    sample_peak: f32,
+    true_peak: f32,
+    peaks: TruePeak,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32, layout: Layout) -> Self {
+        let rate = sample_rate.max(1) as f64;
+        let mut weights: Vec<f64> = (0..32)
+            .map(|bit| 1u32 << bit)
+            .filter(|speaker| layout.has(*speaker))
+            .map(|speaker| match speaker {
+                Layout::LFE => 0.0,
+                Layout::BACK_LEFT | Layout::BACK_RIGHT | Layout::SIDE_LEFT | Layout::SIDE_RIGHT => {
+                    1.41
+                }
+                _ => 1.0,
+            })
+            .collect();
+        if weights.is_empty() {
+            weights.push(1.0);
+        }
+        let channels = weights.len();
+
+        Self {
+            weights,
+            filters: (0..channels)
+                .map(|_| [Biquad::shelf(rate), Biquad::highpass(rate)])
+                .collect(),
+            step: (sample_rate as usize / 10).max(1),
+            step_frames: 0,
+            step_sum: 0.0,
+            steps: Vec::new(),
+            momentary: Vec::new(),
+            short_term: Vec::new(),
+            sample_peak: 0.0,
+            true_peak: 0.0,
+            peaks: TruePeak::new(sample_rate, channels),
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.weights.len()
+    }
+
+    pub fn push(&mut self, frames: &[f32]) {
+        let channels = self.channels();
+        for frame in frames.chunks_exact(channels) {
+            let mut sum = 0.0;
+            for (c, sample) in frame.iter().enumerate() {
+                self.sample_peak = self.sample_peak.max(sample.abs());
+                self.true_peak = self.true_peak.max(self.peaks.push(c, *sample));
+                let [shelf, highpass] = &mut self.filters[c];
+                let weighted = highpass.process(shelf.process(*sample as f64));
+                sum += self.weights[c] * weighted * weighted;
+            }
+
+            self.step_sum += sum;
+            self.step_frames += 1;
+            if self.step_frames == self.step {
+                self.steps.push(self.step_sum / self.step as f64);
+                self.step_sum = 0.0;
+                self.step_frames = 0;
+                self.end_step();
+            }
+        }
+    }
+
+    /// Results for everything pushed so far. A trailing partial 100ms step is left out.
+    pub fn finish(self) -> Loudness {
+        Loudness {
+            integrated: integrated(&self.momentary),
+            range: range(&self.short_term),
+            sample_peak: self.sample_peak,
+            true_peak: self.true_peak.max(self.sample_peak),
+            momentary: self.momentary,
+            short_term: self.short_term,
+        }
+    }
+
+    fn end_step(&mut self) {
+        let n = self.steps.len();
+        let mean = |steps: &[f64]| steps.iter().sum::<f64>() / steps.len() as f64;
+        if n >= MOMENTARY_STEPS {
+            self.momentary
+                .push(mean(&self.steps[n - MOMENTARY_STEPS..]));
+        }
+        if n >= SHORT_TERM_STEPS {
+            self.short_term
+                .push(mean(&self.steps[n - SHORT_TERM_STEPS..]));
+            self.steps.drain(..n - SHORT_TERM_STEPS);
+        }
+    }
+}
+
+/// Finds peaks between samples by interpolating each channel, per ITU BS.1770 annex 2.
+pub(crate) struct TruePeak {
+    /// `factor` rows of `PEAK_TAPS` coefficients, empty when the rate is high enough already.
+    filter: Vec<f32>,
+    /// The last `PEAK_TAPS` samples of each channel, newest first.
+    history: Vec<[f32; PEAK_TAPS]>,
+}
+
+impl TruePeak {
+    /// Frames a peak can show up after the sample it follows.
+    pub const DELAY: usize = PEAK_TAPS;
+
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        // Below 96kHz peaks between samples need 4x oversampling to be within 0.5dB.
+        let factor = match sample_rate {
+            0..96000 => 4,
+            96000..192000 => 2,
+            _ => 1,
+        };
+        let mut filter = Vec::new();
+        if factor > 1 {
+            let len = PEAK_TAPS * factor;
+            let center = (len - 1) as f64 / 2.0;
+            let prototype: Vec<f64> = (0..len)
+                .map(|i| {
+                    let x = i as f64 - center;
+                    sinc(x / factor as f64) * kaiser(x / (len as f64 / 2.0), 6.0)
+                })
+                .collect();
+            for phase in 0..factor {
+                let row: Vec<f64> = (0..PEAK_TAPS)
+                    .map(|tap| prototype[tap * factor + phase])
+                    .collect();
+                let sum: f64 = row.iter().sum();
+                filter.extend(row.iter().map(|c| (c / sum) as f32));
+            }
+        }
+        Self {
+            filter,
+            history: vec![[0.0; PEAK_TAPS]; channels],
+        }
+    }
+
+    /// Largest magnitude between the last sample of `channel` and `sample`, including `sample`.
+    pub fn push(&mut self, channel: usize, sample: f32) -> f32 {
+        let mut peak = sample.abs();
+        if self.filter.is_empty() {
+            return peak;
+        }
+
+        let history = &mut self.history[channel];
+        history.copy_within(..PEAK_TAPS - 1, 1);
+        history[0] = sample;
+        for row in self.filter.chunks_exact(PEAK_TAPS) {
+            let y: f32 = row.iter().zip(history.iter()).map(|(c, x)| c * x).sum();
+            peak = peak.max(y.abs());
+        }
+        peak
+    }
+
+    pub fn reset(&mut self) {
+        self.history.fill([0.0; PEAK_TAPS]);
+    }
+}
+
+/// Stage of the K-weighting filter, coefficients follow BS.1770 for any rate.
+#[derive(Clone, Copy, Debug)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    /// About +4dB above 2kHz, for the effect of the head.
+    fn shelf(rate: f64) -> Self {
+        let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+        let k = (std::f64::consts::PI * f0 / rate).tan();
+        let vh = 10f64.powf(gain / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        Self {
+            b: [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    /// Rolls off below about 40Hz.
+    fn highpass(rate: f64) -> Self {
+        let (f0, q) = (38.13547087602444, 0.5003270373238773);
+        let k = (std::f64::consts::PI * f0 / rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        Self {
+            b: [1.0, -2.0, 1.0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+fn lufs(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+/// Mean square that reads as `lufs`.
+fn mean_square(lufs: f64) -> f64 {
+    10f64.powf((lufs + 0.691) / 10.0)
+}
+
+/// Blocks above the absolute gate and above the mean of those plus `relative` LU.
+fn gated(blocks: &[f64], relative: f64) -> Vec<f64> {
+    let absolute = mean_square(ABSOLUTE_GATE);
+    let loud: Vec<f64> = blocks.iter().copied().filter(|b| *b > absolute).collect();
+    if loud.is_empty() {
+        return loud;
+    }
+    let mean = loud.iter().sum::<f64>() / loud.len() as f64;
+    let relative = mean * 10f64.powf(relative / 10.0);
+    loud.into_iter().filter(|b| *b > relative).collect()
+}
+
+fn integrated(momentary: &[f64]) -> f64 {
+    let blocks = gated(momentary, RELATIVE_GATE);
+    if blocks.is_empty() {
+        return f64::NEG_INFINITY;
+    }
+    lufs(blocks.iter().sum::<f64>() / blocks.len() as f64)
+}
+
+/// Spread between the 10th and 95th percentile of short-term loudness, per EBU Tech 3342.
+fn range(short_term: &[f64]) -> f64 {
+    let mut blocks = gated(short_term, RANGE_GATE);
+    if blocks.is_empty() {
+        return 0.0;
+    }
+    blocks.sort_by(f64::total_cmp);
+    let percentile = |p: f64| lufs(blocks[((blocks.len() - 1) as f64 * p).round() as usize]);
+    percentile(0.95) - percentile(0.10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WavWriter;
+    use std::f64::consts::PI;
+
+    const RATE: u32 = 48000;
+
+    /// Stereo sine at `dbfs` in both channels.
+    fn sine(dbfs: f64, freq: f64, seconds: f64) -> Vec<f32> {
+        let amplitude = 10f64.powf(dbfs / 20.0);
+        (0..(RATE as f64 * seconds) as usize)
+            .flat_map(|i| {
+                let x = (amplitude * (2.0 * PI * freq * i as f64 / RATE as f64).sin()) as f32;
+                [x, x]
+            })
+            .collect()
+    }
+
+    fn measure(parts: &[Vec<f32>]) -> Loudness {
+        let mut meter = LoudnessMeter::new(RATE, Layout::STEREO);
+        for part in parts {
+            meter.push(part);
+        }
+        meter.finish()
+    }
+
+    #[test]
+    fn integrated_loudness() {
+        // A 997Hz sine reads the same in LUFS as in dBFS, per EBU Tech 3341.
+        let loudness = measure(&[sine(-23.0, 997.0, 5.0)]);
+        assert!(
+            (loudness.integrated + 23.0).abs() < 0.1,
+            "{}",
+            loudness.integrated
+        );
+        assert!((loudness.gain() - db_to_linear(5.0)).abs() < 0.01);
+
+        // Quiet parts are gated out, apart from the few blocks that overlap the loud part.
+        let loudness = measure(&[
+            sine(-36.0, 1000.0, 2.0),
+            sine(-23.0, 1000.0, 20.0),
+            sine(-36.0, 1000.0, 2.0),
+        ]);
+        assert!(
+            (loudness.integrated + 23.0).abs() < 0.1,
+            "{}",
+            loudness.integrated
+        );
+
+        let silence = measure(&[vec![0.0; 2 * RATE as usize]]);
+        assert_eq!(silence.integrated, f64::NEG_INFINITY);
+        assert_eq!(silence.gain(), 0.0);
+        assert_eq!(silence.range, 0.0);
+    }
+
+    #[test]
+    fn loudness_range() {
+        let loudness = measure(&[sine(-20.0, 1000.0, 10.0), sine(-30.0, 1000.0, 10.0)]);
+        assert!((loudness.range - 10.0).abs() < 1.0, "{}", loudness.range);
+
+        let steady = measure(&[sine(-20.0, 1000.0, 5.0)]);
+        assert!(steady.range < 0.1, "{}", steady.range);
+    }
+
+    #[test]
+    fn true_peak() {
+        // At a quarter of the rate and 45 degrees out, every sample misses the crest by 3dB.
+        let samples: Vec<f32> = (0..RATE as usize)
+            .flat_map(|i| {
+                let x = (0.5 * (PI / 2.0 * i as f64 + PI / 4.0).sin()) as f32;
+                [x, 0.0]
+            })
+            .collect();
+        let loudness = measure(&[samples]);
+        assert!((loudness.sample_peak - 0.3536).abs() < 0.001);
+        let db = 20.0 * (loudness.true_peak / 0.5).log10();
+        assert!(db.abs() < 0.2, "{db:.2}dB");
+    }
+
+    #[test]
+    fn album() {
+        let quiet = measure(&[sine(-30.0, 1000.0, 4.0)]);
+        let loud = measure(&[sine(-20.0, 1000.0, 4.0)]);
+        let album = album_loudness(&[quiet.clone(), loud.clone()]);
+
+        // Equal lengths average the power, both are within the relative gate.
+        let expected = 10.0 * ((0.001 + 0.01) / 2.0f64).log10();
+        assert!(
+            (album.integrated - expected).abs() < 0.1,
+            "{}",
+            album.integrated
+        );
+        assert_eq!(album.true_peak, loud.true_peak);
+
+        let tags = quiet.replay_gain(Some(&album));
+        assert_eq!(tags.track_gain, quiet.gain());
+        assert_eq!(tags.album_gain, album.gain());
+        assert_eq!(tags.album_peak, loud.true_peak);
+
+        // An empty album is silence, not a song at 0 LUFS.
+        let empty = album_loudness(&[]);
+        assert_eq!(empty.integrated, Loudness::default().integrated);
+        assert_eq!(Loudness::default().gain(), 0.0);
+    }
+
+    #[test]
+    fn analyze_file() {
+        let path = std::env::temp_dir().join(format!("onmi_loudness_{}.wav", std::process::id()));
+        let mut wav = WavWriter::create(&path, RATE, 2).unwrap();
+        wav.write_samples(&sine(-23.0, 997.0, 3.0)).unwrap();
+        drop(wav);
+
+        let loudness = analyze_loudness(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert!(
+            (loudness.integrated + 23.0).abs() < 0.1,
+            "{}",
+            loudness.integrated
+        );
+        assert!((loudness.sample_peak - db_to_linear(-23.0)).abs() < 0.001);
+    }
+}
@@ -6,232 +6,204 @@ use coreaudio::{
     K_AUDIO_OBJECT_SYSTEM_OBJECT,
 };
 use std::ffi::c_void;
-use std::sync::atomic::Ordering::{AcqRel, Relaxed};
-use std::sync::Arc;
+use std::sync::atomic::Ordering::Relaxed;
 use std::time::Duration;
 
-#[derive(Debug, Clone)]
-pub struct Device {
-    pub audio: AudioDevice,
-    pub name: String,
-}
+#[derive(Default)]
+pub struct CoreAudioBackend;
 
-pub struct OutputDevices;
-
-impl OutputDevices {
+impl CoreAudioBackend {
     pub fn new() -> Self {
         Self
     }
 
-    pub fn default_device(&self) -> Device {
-        let audio = AudioDevice::default_output().unwrap();
+    fn device(audio: AudioDevice) -> Device {
         Device {
             name: audio.name().unwrap_or_else(|_| "Unknown".to_string()),
-            audio,
+            id: audio.id.to_string(),
         }
     }
+}
+
+fn default_output_id() -> Option<AudioObjectID> {
+    let address = AudioObjectPropertyAddress {
+        m_selector: K_AUDIO_HARDWARE_PROPERTY_DEFAULT_OUTPUT_DEVICE,
+        m_scope: K_AUDIO_OBJECT_PROPERTY_SCOPE_GLOBAL,
+        m_element: K_AUDIO_OBJECT_PROPERTY_ELEMENT_MAIN,
+    };
+    let mut device_id: AudioObjectID = 0;
+    let mut data_size = size_of::<AudioObjectID>() as u32;
+    let status = unsafe {
+        AudioObjectGetPropertyData(
+            K_AUDIO_OBJECT_SYSTEM_OBJECT,
+            &address,
+            0,
+            std::ptr::null(),
+            &mut data_size,
+            &mut device_id as *mut AudioObjectID as *mut c_void,
+        )
+    };
+    if status == 0 && device_id != 0 {
+        Some(device_id)
+    } else {
+        None
+    }
+}
+
+impl AudioBackend for CoreAudioBackend {
+    fn name(&self) -> &'static str {
+        "CoreAudio"
+    }
 
-    pub fn devices(&self) -> Vec<Device> {
+    fn devices(&self) -> Vec<Device> {
         AudioDevice::system_devices()
             .unwrap_or_default()
             .into_iter()
             .filter(|d| d.output_channel_count().unwrap_or(0) > 0)
-            .map(|audio| Device {
-                name: audio.name().unwrap_or_else(|_| "Unknown".to_string()),
-                audio,
-            })
+            .map(Self::device)
             .collect()
     }
 
-    pub fn find(&self, device: &str) -> Option<Device> {
-        self.devices()
-            .into_iter()
-            .find(|d| d.name.as_str() == device)
+    fn default_device(&self) -> Option<Device> {
+        AudioDevice::default_output().ok().map(Self::device)
     }
-}
 
-pub struct Output {
-    pub device: Device,
-    pub sample_rate: u32,
-    pub channels: u32,
-}
-
-unsafe impl Send for Output {}
-
-pub fn try_new_output(device: Device, sample_rate: Option<u32>) -> Option<Output> {
-    let channels = match device.audio.output_channel_count() {
-        Ok(0) | Err(_) => 2,
-        Ok(c) => c,
-    };
-
-    let sample_rate = match sample_rate {
-        Some(rate) => {
-            if !COMMON_SAMPLE_RATES.contains(&rate) {
-                return None;
+    fn open(
+        &self,
+        device: &Device,
+        sample_rate: Option<u32>,
+        _: usize,
+    ) -> Option<Box<dyn OutputStream>> {
+        let audio = AudioDevice::new(device.id.parse().ok()?);
+        let channels = match audio.output_channel_count() {
+            Ok(0) | Err(_) => 2,
+            Ok(c) => c,
+        };
+
+        let sample_rate = match sample_rate {
+            Some(rate) => {
+                if !COMMON_SAMPLE_RATES.contains(&rate) {
+                    return None;
+                }
+                rate
             }
-            rate
-        }
-        None => device.audio.sample_rate().unwrap_or(44100.0) as u32,
-    };
+            None => audio.sample_rate().unwrap_or(44100.0) as u32,
+        };
 
-    Some(Output {
-        device,
-        sample_rate,
-        channels,
-    })
-}
+        Some(Box::new(CoreAudioOutput {
+            device: device.clone(),
+            audio,
+            sample_rate,
+            channels,
+            stream: None,
+            callback: None,
+        }))
+    }
 
-pub fn new_output(device: Device, sample_rate: Option<u32>) -> Output {
-    try_new_output(device, sample_rate).expect("failed to open output")
+    /// Compares ids first so the device name is only looked up when the default moved.
+    fn default_changed(&self, current: &Device) -> Option<Device> {
+        let id = default_output_id()?;
+        if id.to_string() == current.id {
+            return None;
+        }
+        Some(Self::device(AudioDevice::new(id)))
+    }
 }
 
-struct AudioCtx {
-    state: Arc<PlayerState>,
-    decoder: Option<Symphonia>,
-    channels: u32,
+/// Owned by the stream and only touched by the callback until the unit is stopped.
+struct Callback {
+    renderer: Box<Renderer>,
+    channels: usize,
 }
 
 extern "C" fn render_callback(context: *mut c_void, buffer_ptr: *mut f32, total_samples: usize) {
     unsafe {
-        let ctx = &mut *(context as *mut AudioCtx);
-        let state = &*ctx.state;
+        let callback = &mut *(context as *mut Callback);
+        let renderer = &mut *callback.renderer;
 
-        if state.shutdown.load(Relaxed) {
-            let buffer =
-                std::slice::from_raw_parts_mut(buffer_ptr, total_samples);
+        if renderer.state.shutdown.load(Relaxed) {
+            let buffer = std::slice::from_raw_parts_mut(buffer_ptr, total_samples);
             buffer.fill(0.0);
             return;
         }
 
-        if let Some(new_decoder) = state.pending_decoder.take() {
-            state.finished.store(false, Relaxed);
-            ctx.decoder = Some(new_decoder);
-            state.decoder_pending.store(false, Relaxed);
-        }
-
-        let seek = state.seek.swap(u64::MAX, AcqRel);
-        if seek != u64::MAX {
-            if let Some(decoder) = ctx.decoder.as_mut() {
-                decoder.seek(Duration::from_nanos(seek), state);
-            }
-        }
-
-        let channels = ctx.channels as usize;
         let buffer =
             std::slice::from_raw_parts_mut(buffer_ptr as *mut u8, total_samples * size_of::<f32>());
-        fill_f32_le(state, &mut ctx.decoder, buffer, channels);
+        renderer.fill(buffer, callback.channels);
     }
 }
 
-fn start_stream(output: &Output, ctx: *mut c_void) -> Option<AudioStream> {
-    unsafe {
-        AudioStream::start_output(
-            output.device.audio,
-            output.sample_rate as f64,
-            output.channels,
-            render_callback,
-            ctx,
-        )
-        .ok()
-    }
+/// The audio unit is started by `start`, its callback owns the renderer until `stop`.
+pub struct CoreAudioOutput {
+    pub device: Device,
+    pub audio: AudioDevice,
+    pub sample_rate: u32,
+    pub channels: u32,
+    stream: Option<AudioStream>,
+    callback: Option<Box<Callback>>,
 }
 
-fn default_output_id() -> Option<AudioObjectID> {
-    let address = AudioObjectPropertyAddress {
-        m_selector: K_AUDIO_HARDWARE_PROPERTY_DEFAULT_OUTPUT_DEVICE,
-        m_scope: K_AUDIO_OBJECT_PROPERTY_SCOPE_GLOBAL,
-        m_element: K_AUDIO_OBJECT_PROPERTY_ELEMENT_MAIN,
-    };
-    let mut device_id: AudioObjectID = 0;
-    let mut data_size = size_of::<AudioObjectID>() as u32;
-    let status = unsafe {
-        AudioObjectGetPropertyData(
-            K_AUDIO_OBJECT_SYSTEM_OBJECT,
-            &address,
-            0,
-            std::ptr::null(),
-            &mut data_size,
-            &mut device_id as *mut AudioObjectID as *mut c_void,
-        )
-    };
-    if status == 0 && device_id != 0 {
-        Some(device_id)
-    } else {
-        None
+unsafe impl Send for CoreAudioOutput {}
+
+impl Drop for CoreAudioOutput {
+    fn drop(&mut self) {
+        // Stop the unit before the callback context goes away.
+        drop(self.stream.take());
     }
 }
 
-pub fn run_output(state: Arc<PlayerState>, mut output: Output) {
-    let mut ctx = Box::new(AudioCtx {
-        state: Arc::clone(&state),
-        decoder: None,
-        channels: output.channels,
-    });
-    let ctx_ptr = ctx.as_mut() as *mut AudioCtx as *mut c_void;
-
-    let mut stream = match start_stream(&output, ctx_ptr) {
-        Some(s) => Some(s),
-        None => {
-            state.set_error(RuntimeError::StreamStart);
-            None
-        }
-    };
-
-    let mut last_default = default_output_id();
+impl OutputStream for CoreAudioOutput {
+    fn device(&self) -> &Device {
+        &self.device
+    }
 
-    loop {
-        if state.shutdown.load(Relaxed) {
-            break;
-        }
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
 
-        if let Some(new_output) = state.pending_output.take() {
-            drop(stream.take());
-            output = new_output;
-            ctx.channels = output.channels;
-            stream = match start_stream(&output, ctx_ptr) {
-                Some(s) => Some(s),
-                None => {
-                    state.set_error(RuntimeError::StreamStart);
-                    None
-                }
-            };
-        }
+    fn channels(&self) -> usize {
+        self.channels as usize
+    }
 
-        if state.follow_default.load(Relaxed) {
-            let current_default = default_output_id();
-            if current_default.is_some() && current_default != last_default {
-                last_default = current_default;
-                if let Some(id) = current_default {
-                    if id != output.device.audio.id {
-                        let device = Device {
-                            name: AudioDevice::new(id)
-                                .name()
-                                .unwrap_or_else(|_| "Unknown".to_string()),
-                            audio: AudioDevice::new(id),
-                        };
-                        if let Some(new_output) =
-                            try_new_output(device, Some(output.sample_rate))
-                        {
-                            drop(stream.take());
-                            output = new_output;
-                            ctx.channels = output.channels;
-                            stream = match start_stream(&output, ctx_ptr) {
-                                Some(s) => Some(s),
-                                None => {
-                                    state.set_error(RuntimeError::StreamStart);
-                                    None
-                                }
-                            };
-                        } else {
-                            state.set_error(RuntimeError::OutputOpen);
-                        }
-                    }
-                }
-            }
+    fn start(&mut self, renderer: Box<Renderer>) -> Option<Box<Renderer>> {
+        let callback = self.callback.insert(Box::new(Callback {
+            renderer,
+            channels: self.channels as usize,
+        }));
+        let ctx = callback.as_mut() as *mut Callback as *mut c_void;
+
+        self.stream = unsafe {
+            AudioStream::start_output(
+                self.audio,
+                self.sample_rate as f64,
+                self.channels,
+                render_callback,
+                ctx,
+            )
+            .ok()
+        };
+
+        if self.stream.is_some() {
+            return None;
         }
+        let renderer = self.callback.take()?.renderer;
+        renderer.state.set_error(RuntimeError::StreamStart);
+        Some(renderer)
+    }
 
+    /// Only called when the unit failed to start, there is nothing to do but wait.
+    fn render(&mut self, _renderer: &mut Renderer) {
         std::thread::park_timeout(Duration::from_millis(10));
     }
 
-    drop(stream.take());
+    fn stop(&mut self) -> Option<Box<Renderer>> {
+        // The callback never runs again once the unit is disposed.
+        drop(self.stream.take());
+        self.callback.take().map(|callback| callback.renderer)
+    }
+
+    fn latency(&self) -> Duration {
+        let frames = self.audio.output_latency().unwrap_or(0);
+        Duration::from_secs_f64(frames as f64 / self.sample_rate as f64)
+    }
 }
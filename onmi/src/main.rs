@@ -1,17 +1,17 @@
 use onmi::*;
 
 fn main() {
-    let devices = OutputDevices::new();
-    let list = devices.devices();
-    println!("Output devices:");
+    let backend = default_backend();
+    let list = backend.devices();
+    println!("Output devices ({}):", backend.name());
     for d in &list {
         println!("  - {}", d.name);
     }
 
-    let device = devices.default_device();
+    let device = backend.default_device().expect("no output device");
     println!("Using default: {}", device.name);
 
-    let mut player = Player::new(device);
+    let mut player = Player::with_backend(backend, device);
     player.set_volume(10);
 
     let path = std::env::args().nth(1);
@@ -22,7 +22,10 @@ fn main() {
     };
 
     println!("Playing: {path}");
-    player.play_song(&path, None, true).unwrap();
+    if let Err(err) = player.play_song(&path, None, true) {
+        println!("Failed to play {path}: {err}");
+        return;
+    }
 
     std::thread::park();
 }
@@ -1,19 +1,11 @@
 use crate::*;
 use std::{
     fs::File,
-    io::{BufReader, Read},
+    io::{self, BufReader, Read},
     path::Path,
 };
 use symphonia::core::common::Limit;
-use symphonia::core::meta::{StandardTag, StandardVisualKey};
-use symphonia::{
-    core::{
-        formats::{FormatOptions, probe::Hint},
-        io::MediaSourceStream,
-        meta::MetadataOptions,
-    },
-    default::get_probe,
-};
+use symphonia::core::meta::{MetadataOptions, StandardTag, StandardVisualKey};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Artwork {
@@ -29,7 +21,12 @@ pub struct Song {
     pub path: String,
     pub disc_number: u8,
     pub track_number: u8,
+    /// ReplayGain track gain, linear. Zero when untagged, like the rest.
     pub gain: f32,
+    pub album_gain: f32,
+    /// Peaks are linear where 1.0 is full scale.
+    pub peak: f32,
+    pub album_peak: f32,
     pub year: u16,
     pub artwork: Option<Artwork>,
 }
@@ -44,10 +41,35 @@ impl Song {
             disc_number: 1,
             track_number: 1,
             gain: 0.0,
+            album_gain: 0.0,
+            peak: 0.0,
+            album_peak: 0.0,
             year: 0,
             artwork: None,
         }
     }
+
+    pub fn replay_gain(&self) -> ReplayGain {
+        ReplayGain {
+            track_gain: self.gain,
+            track_peak: self.peak,
+            album_gain: self.album_gain,
+            album_peak: self.album_peak,
+        }
+    }
+
+    fn set_replay_gain(&mut self, replay_gain: ReplayGain) {
+        self.gain = replay_gain.track_gain;
+        self.peak = replay_gain.track_peak;
+        self.album_gain = replay_gain.album_gain;
+        self.album_peak = replay_gain.album_peak;
+    }
+}
+
+impl Default for Song {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 pub fn parse_year(s: &str) -> u16 {
@@ -63,45 +85,32 @@ pub fn metadata(
     path: impl AsRef<Path>,
     force_symphonia: bool,
     load_artwork: bool,
-) -> Result<Song, String> {
+) -> Result<Song, Error> {
     let path = path.as_ref();
-    let extension = path.extension().ok_or("Path is not audio")?;
+    let Some(extension) = path.extension() else {
+        return Err(Error::UnsupportedFormat(String::from("no file extension")));
+    };
 
     let is_flac = extension.eq_ignore_ascii_case("flac");
     if is_flac && !force_symphonia {
-        return flac_metadata(path, load_artwork)
-            .map_err(|err| format!("Error: ({err}) @ {}", path.to_string_lossy()));
+        return flac_metadata(path, load_artwork);
     }
 
-    let file = match File::open(path) {
-        Ok(file) => file,
-        Err(err) => return Err(format!("Error: ({err}) @ {}", path.to_string_lossy())),
-    };
+    let file = File::open(path)?;
 
-    let mss = MediaSourceStream::new(Box::new(file), Default::default());
     let meta_opts = if load_artwork {
         MetadataOptions::default()
     } else {
         MetadataOptions::default().limit_visual_bytes(Limit::Maximum(0))
     };
-    let mut format_reader = match get_probe().probe(
-        &Hint::new(),
-        mss,
-        FormatOptions::default()
-            .prebuild_seek_index(false)
-            .seek_index_fill_period_ms(20),
-        meta_opts,
-    ) {
-        Ok(format_reader) => format_reader,
-        Err(err) => return Err(format!("Error: ({err}) @ {}", path.to_string_lossy()))?,
-    };
+    let mut format_reader = probe(Box::new(file), extension.to_str(), None, meta_opts)?;
 
     let mut title = String::from("Unknown Title");
     let mut album = String::from("Unknown Album");
     let mut artist = String::from("Unknown Artist");
     let mut track_number = 1;
     let mut disc_number = 1;
-    let mut gain = 0.0;
+    let mut replay_gain = ReplayGain::default();
     let mut year = 0u16;
     let mut artwork = None;
 
@@ -109,6 +118,7 @@ pub fn metadata(
     if let Some(latest_revision) = metadata.skip_to_latest() {
         for tag in &latest_revision.media.tags {
             if let Some(std) = &tag.std {
+                replay_gain.read_tag(std);
                 match std {
                     StandardTag::AlbumArtist(tag) => artist = tag.to_string(),
                     StandardTag::Artist(tag) if artist == "Unknown Artist" => {
@@ -125,24 +135,18 @@ pub fn metadata(
                     StandardTag::ReleaseYear(y)
                     | StandardTag::RecordingYear(y)
                     | StandardTag::OriginalReleaseYear(y)
-                    | StandardTag::OriginalRecordingYear(y) => {
-                        if year == 0 {
-                            year = *y;
-                        }
+                    | StandardTag::OriginalRecordingYear(y)
+                        if year == 0 =>
+                    {
+                        year = *y;
                     }
                     StandardTag::ReleaseDate(tag)
                     | StandardTag::RecordingDate(tag)
                     | StandardTag::OriginalReleaseDate(tag)
-                    | StandardTag::OriginalRecordingDate(tag) => {
-                        if year == 0 {
-                            year = parse_year(tag);
-                        }
-                    }
-                    StandardTag::ReplayGainTrackGain(tag) => {
-                        if let Some((_, tague)) = tag.split_once(' ') {
-                            let db: f32 = tague.parse().unwrap_or(0.0);
-                            gain = 10.0f32.powf(db / 20.0);
-                        }
+                    | StandardTag::OriginalRecordingDate(tag)
+                        if year == 0 =>
+                    {
+                        year = parse_year(tag);
                     }
                     _ => (),
                 }
@@ -165,44 +169,50 @@ pub fn metadata(
         }
     }
 
-    Ok(Song {
+    let Some(path) = path.to_str() else {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid UTF-8 in path",
+        )));
+    };
+
+    let mut song = Song {
         title,
         album,
         artist,
+        path: path.to_string(),
         disc_number,
         track_number,
-        path: path.to_str().ok_or("Invalid UTF-8 in path.")?.to_string(),
-        gain,
         year,
         artwork,
-    })
+        ..Song::new()
+    };
+    song.set_replay_gain(replay_gain);
+    Ok(song)
 }
 
 #[inline]
-pub fn u24_be(reader: &mut BufReader<File>) -> u32 {
+pub fn u24_be(reader: &mut BufReader<File>) -> io::Result<u32> {
     let mut triple = [0; 4];
-    reader.read_exact(&mut triple[0..3]).unwrap();
-    u32::from_be_bytes(triple) >> 8
+    reader.read_exact(&mut triple[0..3])?;
+    Ok(u32::from_be_bytes(triple) >> 8)
 }
 
 #[inline]
-pub fn u32_le(reader: &mut BufReader<File>) -> u32 {
+pub fn u32_le(reader: &mut BufReader<File>) -> io::Result<u32> {
     let mut buffer = [0; 4];
-    reader.read_exact(&mut buffer).unwrap();
-    u32::from_le_bytes(buffer)
+    reader.read_exact(&mut buffer)?;
+    Ok(u32::from_le_bytes(buffer))
 }
 
 #[inline]
-pub fn u32_be(reader: &mut BufReader<File>) -> u32 {
+pub fn u32_be(reader: &mut BufReader<File>) -> io::Result<u32> {
     let mut buffer = [0; 4];
-    reader.read_exact(&mut buffer).unwrap();
-    u32::from_be_bytes(buffer)
+    reader.read_exact(&mut buffer)?;
+    Ok(u32::from_be_bytes(buffer))
 }
 
-pub fn flac_metadata(
-    path: impl AsRef<Path>,
-    load_artwork: bool,
-) -> Result<Song, Box<dyn std::error::Error>> {
+pub fn flac_metadata(path: impl AsRef<Path>, load_artwork: bool) -> Result<Song, Error> {
     let file = File::open(&path)?;
     let mut reader = BufReader::new(file);
 
@@ -210,7 +220,7 @@ pub fn flac_metadata(
     reader.read_exact(&mut flac)?;
 
     if &flac != b"fLaC" {
-        Err("File is not FLAC.")?;
+        return Err(Error::UnsupportedFormat(String::from("not a FLAC file")));
     }
 
     let mut song: Song = Song::new();
@@ -219,6 +229,7 @@ pub fn flac_metadata(
     let mut flag = [0; 1];
     let mut got_comments = false;
     let mut has_front_cover = false;
+    let mut replay_gain = ReplayGain::default();
 
     loop {
         reader.read_exact(&mut flag)?;
@@ -228,22 +239,25 @@ pub fn flac_metadata(
 
         // The next 7 bits of the header indicates the block type.
         let block_type = flag[0] & 0x7f;
-        let block_len = u24_be(&mut reader);
+        let block_len = u24_be(&mut reader)? as usize;
 
         match block_type {
             // VorbisComment https://www.xiph.org/vorbis/doc/v-comment.html
             4 => {
-                let vendor_length = u32_le(&mut reader);
+                let vendor_length = u32_le(&mut reader)?;
                 reader.seek_relative(vendor_length as i64)?;
 
-                let comment_list_length = u32_le(&mut reader);
+                let comment_list_length = u32_le(&mut reader)?;
                 for _ in 0..comment_list_length {
-                    let length = u32_le(&mut reader) as usize;
+                    let length = u32_le(&mut reader)? as usize;
+                    if length > block_len {
+                        return Err(Error::Decode(String::from("comment longer than its block")));
+                    }
                     let mut buffer = vec![0; length];
                     reader.read_exact(&mut buffer)?;
 
                     let Ok(tag) = core::str::from_utf8(&buffer) else {
-                        return Err(format!("Invalid utf8 in {}", path.as_ref().display()))?;
+                        return Err(Error::Decode(String::from("invalid UTF-8 in comment")));
                     };
                     let (k, v) = match tag.split_once('=') {
                         Some((left, right)) => (left, right),
@@ -262,39 +276,40 @@ pub fn flac_metadata(
                                 song.year = parse_year(v);
                             }
                         }
-                        "replaygain_track_gain" => {
-                            // Remove the trailing " dB" from "-5.39 dB".
-                            if let Some(slice) = v.get(..v.len() - 3) {
-                                if let Ok(db) = slice.parse::<f32>() {
-                                    song.gain = 10.0f32.powf(db / 20.0);
-                                }
-                            }
+                        key => {
+                            replay_gain.read_comment(key, v);
                         }
-                        _ => {}
                     }
                 }
 
                 got_comments = true;
+                song.set_replay_gain(replay_gain);
                 if !load_artwork {
                     return Ok(song);
                 }
             }
             // Picture
             6 if load_artwork && !has_front_cover => {
-                let pic_type = u32_be(&mut reader);
+                let pic_type = u32_be(&mut reader)?;
 
-                let mime_len = u32_be(&mut reader) as usize;
+                let mime_len = u32_be(&mut reader)? as usize;
+                if mime_len > block_len {
+                    return Err(Error::Decode(String::from("picture longer than its block")));
+                }
                 let mut mime = vec![0; mime_len];
                 reader.read_exact(&mut mime)?;
                 let mime = String::from_utf8_lossy(&mime).into_owned();
 
-                let desc_len = u32_be(&mut reader) as i64;
+                let desc_len = u32_be(&mut reader)? as i64;
                 reader.seek_relative(desc_len)?;
 
                 // width, height, depth, indexed colors
                 reader.seek_relative(16)?;
 
-                let data_len = u32_be(&mut reader) as usize;
+                let data_len = u32_be(&mut reader)? as usize;
+                if data_len > block_len {
+                    return Err(Error::Decode(String::from("picture longer than its block")));
+                }
                 let mut data = vec![0; data_len];
                 reader.read_exact(&mut data)?;
 
@@ -316,7 +331,7 @@ pub fn flac_metadata(
     if got_comments {
         Ok(song)
     } else {
-        Err("Could not parse metadata.")?
+        Err(Error::Decode(String::from("no Vorbis comment block")))
     }
 }
 
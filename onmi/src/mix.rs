@@ -0,0 +1,327 @@
+use symphonia::core::audio::{Channels, Position};
+
+/// Most channels a frame is mixed from or into, anything past this is dropped.
+pub const MAX_CHANNELS: usize = 16;
+
+/// -3dB, used when one speaker is shared between two.
+const HALF_POWER: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Speaker positions as a WAVE channel mask. Channels are interleaved in bit order.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Layout(pub u32);
+
+impl Layout {
+    pub const FRONT_LEFT: u32 = 0x1;
+    pub const FRONT_RIGHT: u32 = 0x2;
+    pub const FRONT_CENTER: u32 = 0x4;
+    pub const LFE: u32 = 0x8;
+    pub const BACK_LEFT: u32 = 0x10;
+    pub const BACK_RIGHT: u32 = 0x20;
+    pub const FRONT_LEFT_CENTER: u32 = 0x40;
+    pub const FRONT_RIGHT_CENTER: u32 = 0x80;
+    pub const BACK_CENTER: u32 = 0x100;
+    pub const SIDE_LEFT: u32 = 0x200;
+    pub const SIDE_RIGHT: u32 = 0x400;
+
+    pub const MONO: Layout = Layout(Self::FRONT_CENTER);
+    pub const STEREO: Layout = Layout(Self::FRONT_LEFT | Self::FRONT_RIGHT);
+    pub const QUAD: Layout = Layout(Self::STEREO.0 | Self::BACK_LEFT | Self::BACK_RIGHT);
+    pub const SURROUND_5_1: Layout = Layout(Self::QUAD.0 | Self::FRONT_CENTER | Self::LFE);
+    pub const SURROUND_7_1: Layout =
+        Layout(Self::SURROUND_5_1.0 | Self::SIDE_LEFT | Self::SIDE_RIGHT);
+
+    pub fn channels(self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub fn has(self, speaker: u32) -> bool {
+        self.0 & speaker != 0
+    }
+
+    /// The usual WAVE/FLAC layout for `channels` channels when the file doesn't say.
+    pub fn default_for(channels: usize) -> Layout {
+        match channels {
+            1 => Self::MONO,
+            2 => Self::STEREO,
+            3 => Layout(Self::STEREO.0 | Self::FRONT_CENTER),
+            4 => Self::QUAD,
+            5 => Layout(Self::QUAD.0 | Self::FRONT_CENTER),
+            6 => Self::SURROUND_5_1,
+            7 => Layout(
+                Self::SURROUND_5_1.0 ^ Self::BACK_LEFT ^ Self::BACK_RIGHT | Self::BACK_CENTER,
+            )
+            .with(Self::SIDE_LEFT | Self::SIDE_RIGHT),
+            8 => Self::SURROUND_7_1,
+            n => Layout((1u32 << n.min(31)) - 1),
+        }
+    }
+
+    /// Speakers Symphonia has no equivalent for here fall back to the default layout.
+    pub fn from_symphonia(channels: &Channels) -> Layout {
+        const POSITIONS: [(Position, u32); 11] = [
+            (Position::FRONT_LEFT, Layout::FRONT_LEFT),
+            (Position::FRONT_RIGHT, Layout::FRONT_RIGHT),
+            (Position::FRONT_CENTER, Layout::FRONT_CENTER),
+            (Position::LFE1, Layout::LFE),
+            (Position::REAR_LEFT, Layout::BACK_LEFT),
+            (Position::REAR_RIGHT, Layout::BACK_RIGHT),
+            (Position::FRONT_LEFT_CENTER, Layout::FRONT_LEFT_CENTER),
+            (Position::FRONT_RIGHT_CENTER, Layout::FRONT_RIGHT_CENTER),
+            (Position::REAR_CENTER, Layout::BACK_CENTER),
+            (Position::SIDE_LEFT, Layout::SIDE_LEFT),
+            (Position::SIDE_RIGHT, Layout::SIDE_RIGHT),
+        ];
+
+        if let Channels::Positioned(positions) = channels {
+            let mut layout = 0;
+            for (position, speaker) in POSITIONS {
+                if positions.contains(position) {
+                    layout |= speaker;
+                }
+            }
+            if layout.count_ones() as usize == channels.count() {
+                return Layout(layout);
+            }
+        }
+        Self::default_for(channels.count())
+    }
+
+    fn with(self, speakers: u32) -> Layout {
+        Layout(self.0 | speakers)
+    }
+
+    /// Index of `speaker` in an interleaved frame.
+    fn index(self, speaker: u32) -> Option<usize> {
+        self.has(speaker)
+            .then(|| (self.0 & (speaker - 1)).count_ones() as usize)
+    }
+}
+
+/// Gains from every source channel to every output channel.
+///
+/// Speakers the output has are routed straight across, the rest are folded into the
+/// nearest ones following ITU-R BS.775: centre and surrounds at -3dB into the front pair,
+/// LFE dropped. Output channels that gather more than unity gain are scaled back to it,
+/// so full scale on every source channel doesn't clip. Mono output is the average of the
+/// stereo fold-down and a mono source plays at full level on both front speakers.
+#[derive(Clone, Copy, Debug)]
+pub struct Mix {
+    pub inputs: usize,
+    pub outputs: usize,
+    matrix: [[f32; MAX_CHANNELS]; MAX_CHANNELS],
+}
+
+impl Mix {
+    pub fn new(src: Layout, dst: Layout) -> Self {
+        let mut mix = Self {
+            inputs: src.channels().min(MAX_CHANNELS),
+            outputs: dst.channels().min(MAX_CHANNELS),
+            matrix: [[0.0; MAX_CHANNELS]; MAX_CHANNELS],
+        };
+
+        if dst == Layout::MONO {
+            let stereo = Mix::new(src, Layout::STEREO);
+            for input in 0..mix.inputs {
+                mix.matrix[0][input] = (stereo.gain(0, input) + stereo.gain(1, input)) * 0.5;
+            }
+            return mix;
+        }
+
+        if src == Layout::MONO && dst.has(Layout::FRONT_LEFT) && dst.has(Layout::FRONT_RIGHT) {
+            mix.set(dst, Layout::FRONT_LEFT, 0, 1.0);
+            mix.set(dst, Layout::FRONT_RIGHT, 0, 1.0);
+            return mix;
+        }
+
+        for bit in 0..32 {
+            let speaker = 1 << bit;
+            let Some(input) = src.index(speaker).filter(|i| *i < MAX_CHANNELS) else {
+                continue;
+            };
+            if dst.has(speaker) {
+                mix.set(dst, speaker, input, 1.0);
+                continue;
+            }
+            for &(target, gain) in route(speaker, dst) {
+                mix.set(dst, target, input, gain);
+            }
+        }
+        for row in &mut mix.matrix {
+            let sum: f32 = row.iter().sum();
+            if sum > 1.0 {
+                row.iter_mut().for_each(|gain| *gain /= sum);
+            }
+        }
+        mix
+    }
+
+    pub fn gain(&self, output: usize, input: usize) -> f32 {
+        self.matrix[output][input]
+    }
+
+    /// Mixes one interleaved frame, `output` is as long as there are output channels.
+    pub fn apply(&self, input: &[f32], output: &mut [f32]) {
+        let inputs = input.len().min(self.inputs);
+        for (row, sample) in self.matrix.iter().zip(output.iter_mut()) {
+            *sample = row[..inputs]
+                .iter()
+                .zip(input)
+                .map(|(gain, sample)| gain * sample)
+                .sum();
+        }
+    }
+
+    fn set(&mut self, dst: Layout, speaker: u32, input: usize, gain: f32) {
+        if let Some(output) = dst.index(speaker).filter(|o| *o < MAX_CHANNELS) {
+            self.matrix[output][input] += gain;
+        }
+    }
+}
+
+/// Where a source speaker `dst` doesn't have goes instead, with gains.
+fn route(speaker: u32, dst: Layout) -> &'static [(u32, f32)] {
+    const L: u32 = Layout::FRONT_LEFT;
+    const R: u32 = Layout::FRONT_RIGHT;
+    const C: u32 = Layout::FRONT_CENTER;
+    const BL: u32 = Layout::BACK_LEFT;
+    const BR: u32 = Layout::BACK_RIGHT;
+    const SL: u32 = Layout::SIDE_LEFT;
+    const SR: u32 = Layout::SIDE_RIGHT;
+
+    match speaker {
+        C => &[(L, HALF_POWER), (R, HALF_POWER)],
+        Layout::FRONT_LEFT_CENTER if dst.has(C) => &[(L, HALF_POWER), (C, HALF_POWER)],
+        Layout::FRONT_RIGHT_CENTER if dst.has(C) => &[(R, HALF_POWER), (C, HALF_POWER)],
+        Layout::FRONT_LEFT_CENTER => &[(L, 1.0)],
+        Layout::FRONT_RIGHT_CENTER => &[(R, 1.0)],
+        // Surrounds move to the other surround pair before folding into the front.
+        BL if dst.has(SL) => &[(SL, 1.0)],
+        BR if dst.has(SR) => &[(SR, 1.0)],
+        SL if dst.has(BL) => &[(BL, 1.0)],
+        SR if dst.has(BR) => &[(BR, 1.0)],
+        BL | SL => &[(L, HALF_POWER)],
+        BR | SR => &[(R, HALF_POWER)],
+        Layout::BACK_CENTER if dst.has(BL) && dst.has(BR) => &[(BL, HALF_POWER), (BR, HALF_POWER)],
+        Layout::BACK_CENTER if dst.has(SL) && dst.has(SR) => &[(SL, HALF_POWER), (SR, HALF_POWER)],
+        Layout::BACK_CENTER => &[(L, HALF_POWER * HALF_POWER), (R, HALF_POWER * HALF_POWER)],
+        // LFE is left out of downmixes, and anything without a known position.
+        _ => &[],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const H: f32 = HALF_POWER;
+
+    fn matrix(mix: &Mix) -> Vec<Vec<f32>> {
+        (0..mix.outputs)
+            .map(|o| (0..mix.inputs).map(|i| mix.gain(o, i)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn stereo() {
+        let mix = Mix::new(Layout::STEREO, Layout::STEREO);
+        assert_eq!(matrix(&mix), [[1.0, 0.0], [0.0, 1.0]]);
+
+        let mono = Mix::new(Layout::STEREO, Layout::MONO);
+        assert_eq!(matrix(&mono), [[0.5, 0.5]]);
+
+        let mut out = [0.0; 2];
+        Mix::new(Layout::MONO, Layout::STEREO).apply(&[0.25], &mut out);
+        assert_eq!(out, [0.25, 0.25]);
+    }
+
+    #[test]
+    fn surround_5_1_to_stereo() {
+        // FL FR FC LFE BL BR
+        let mix = Mix::new(Layout::SURROUND_5_1, Layout::STEREO);
+        // Scaled back from 1 + 2 * -3dB.
+        let (f, h) = (1.0 / (1.0 + H + H), H / (1.0 + H + H));
+        assert_eq!(
+            matrix(&mix),
+            [[f, 0.0, h, 0.0, h, 0.0], [0.0, f, h, 0.0, 0.0, h]]
+        );
+
+        let mono = Mix::new(Layout::SURROUND_5_1, Layout::MONO);
+        assert_eq!(
+            matrix(&mono),
+            [[f * 0.5, f * 0.5, h, 0.0, h * 0.5, h * 0.5]]
+        );
+
+        // Full scale everywhere stays in range.
+        let mut out = [0.0; 2];
+        mix.apply(&[1.0; 6], &mut out);
+        assert!(out.iter().all(|s| *s <= 1.0), "{out:?}");
+        let mut out = [0.0; 1];
+        mono.apply(&[1.0; 6], &mut out);
+        assert!(out[0] <= 1.0, "{out:?}");
+    }
+
+    #[test]
+    fn surround_7_1_to_stereo() {
+        // FL FR FC LFE BL BR SL SR
+        let mix = Mix::new(Layout::SURROUND_7_1, Layout::STEREO);
+        let (f, h) = (1.0 / (1.0 + H + H + H), H / (1.0 + H + H + H));
+        assert_eq!(
+            matrix(&mix),
+            [
+                [f, 0.0, h, 0.0, h, 0.0, h, 0.0],
+                [0.0, f, h, 0.0, 0.0, h, 0.0, h]
+            ]
+        );
+    }
+
+    #[test]
+    fn routes_onto_more_speakers() {
+        // Stereo stays in front on a 7.1 output.
+        let mix = Mix::new(Layout::STEREO, Layout::SURROUND_7_1);
+        let mut out = [0.0; 8];
+        mix.apply(&[0.5, -0.5], &mut out);
+        assert_eq!(out, [0.5, -0.5, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+
+        // Every 5.1 channel lands on its own speaker.
+        let mix = Mix::new(Layout::SURROUND_5_1, Layout::SURROUND_7_1);
+        let input = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        mix.apply(&input, &mut out);
+        assert_eq!(out, [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 0.0, 0.0]);
+
+        // Side surrounds move to the back pair on a 5.1 output.
+        let sides = Layout(Layout::SURROUND_5_1.0 ^ Layout::BACK_LEFT ^ Layout::BACK_RIGHT)
+            .with(Layout::SIDE_LEFT | Layout::SIDE_RIGHT);
+        let mix = Mix::new(sides, Layout::SURROUND_5_1);
+        let mut out = [0.0; 6];
+        mix.apply(&input, &mut out);
+        assert_eq!(out, input);
+    }
+
+    #[test]
+    fn layouts() {
+        for channels in 1..=8 {
+            assert_eq!(Layout::default_for(channels).channels(), channels);
+        }
+        assert_eq!(
+            Layout::from_symphonia(&Channels::Discrete(6)),
+            Layout::SURROUND_5_1
+        );
+
+        let quad = Position::FRONT_LEFT
+            | Position::FRONT_RIGHT
+            | Position::REAR_LEFT
+            | Position::REAR_RIGHT;
+        assert_eq!(
+            Layout::from_symphonia(&Channels::Positioned(quad)),
+            Layout::QUAD
+        );
+        let sides = Position::FRONT_LEFT
+            | Position::FRONT_RIGHT
+            | Position::SIDE_LEFT
+            | Position::SIDE_RIGHT;
+        assert_eq!(
+            Layout::from_symphonia(&Channels::Positioned(sides)).channels(),
+            4
+        );
+    }
+}
@@ -0,0 +1,325 @@
+use crate::*;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const PERIOD_FRAMES: u64 = 512;
+
+/// Drives the null output. Real time clocks render at the stream's sample rate,
+/// manual clocks only render the frames handed out by `advance`.
+pub struct NullClock {
+    pub manual: bool,
+    pub budget: AtomicU64,
+    pub frames: AtomicU64,
+}
+
+impl NullClock {
+    pub fn realtime() -> Arc<Self> {
+        Arc::new(Self {
+            manual: false,
+            budget: AtomicU64::new(0),
+            frames: AtomicU64::new(0),
+        })
+    }
+
+    pub fn manual() -> Arc<Self> {
+        Arc::new(Self {
+            manual: true,
+            budget: AtomicU64::new(0),
+            frames: AtomicU64::new(0),
+        })
+    }
+
+    /// Lets a manual clock render `frames` more frames.
+    pub fn advance(&self, frames: u64) {
+        self.budget.fetch_add(frames, Relaxed);
+    }
+
+    /// Waits for a manual clock to render everything handed out so far.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let start = Instant::now();
+        while self.budget.load(Relaxed) != 0 {
+            if start.elapsed() > timeout {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        true
+    }
+
+    /// Frames rendered so far, across every stream opened on this clock.
+    pub fn frames(&self) -> u64 {
+        self.frames.load(Relaxed)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum NullSink {
+    Discard,
+    /// Interleaved samples are appended across every stream opened on the backend.
+    Memory(Arc<Mutex<Vec<f32>>>),
+    /// A 32-bit float WAV file, truncated each time a stream is opened.
+    Wav(PathBuf),
+}
+
+/// An output with no device behind it, for running `Player` in CI.
+pub struct NullBackend {
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub clock: Arc<NullClock>,
+    pub sink: NullSink,
+}
+
+impl NullBackend {
+    pub fn new(sample_rate: u32, channels: usize, clock: Arc<NullClock>, sink: NullSink) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            clock,
+            sink,
+        }
+    }
+
+    pub fn device() -> Device {
+        Device {
+            name: "Null".to_string(),
+            id: "null".to_string(),
+        }
+    }
+}
+
+impl Default for NullBackend {
+    fn default() -> Self {
+        Self::new(44100, 2, NullClock::realtime(), NullSink::Discard)
+    }
+}
+
+impl AudioBackend for NullBackend {
+    fn name(&self) -> &'static str {
+        "Null"
+    }
+
+    fn devices(&self) -> Vec<Device> {
+        vec![Self::device()]
+    }
+
+    fn default_device(&self) -> Option<Device> {
+        Some(Self::device())
+    }
+
+    fn open(
+        &self,
+        device: &Device,
+        sample_rate: Option<u32>,
+        _: usize,
+    ) -> Option<Box<dyn OutputStream>> {
+        let sample_rate = match sample_rate {
+            Some(rate) => {
+                if !COMMON_SAMPLE_RATES.contains(&rate) {
+                    return None;
+                }
+                rate
+            }
+            None => self.sample_rate,
+        };
+
+        let wav = match &self.sink {
+            NullSink::Wav(path) => {
+                Some(WavWriter::create(path, sample_rate, self.channels as u16).ok()?)
+            }
+            _ => None,
+        };
+
+        Some(Box::new(NullOutput {
+            device: device.clone(),
+            sample_rate,
+            channels: self.channels,
+            clock: Arc::clone(&self.clock),
+            sink: self.sink.clone(),
+            wav,
+            buffer: Vec::new(),
+            next: None,
+        }))
+    }
+}
+
+pub struct NullOutput {
+    pub device: Device,
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub clock: Arc<NullClock>,
+    sink: NullSink,
+    wav: Option<WavWriter>,
+    buffer: Vec<u8>,
+    next: Option<Instant>,
+}
+
+impl NullOutput {
+    fn write(&mut self) {
+        let samples = self
+            .buffer
+            .chunks_exact(size_of::<f32>())
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+
+        match &self.sink {
+            NullSink::Discard => {}
+            NullSink::Memory(memory) => memory.lock().unwrap().extend(samples),
+            NullSink::Wav(_) => {
+                if let Some(wav) = self.wav.as_mut() {
+                    let _ = wav.write(&self.buffer);
+                }
+            }
+        }
+    }
+}
+
+impl OutputStream for NullOutput {
+    fn device(&self) -> &Device {
+        &self.device
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn render(&mut self, renderer: &mut Renderer) {
+        let frames = if self.clock.manual {
+            self.clock.budget.load(Relaxed).min(PERIOD_FRAMES)
+        } else {
+            // Sleep until the previous period would have been played.
+            let period = Duration::from_secs_f64(PERIOD_FRAMES as f64 / self.sample_rate as f64);
+            let now = Instant::now();
+            let next = self.next.map_or(now, |next| next.max(now - period));
+            if let Some(wait) = next.checked_duration_since(now) {
+                std::thread::sleep(wait);
+            }
+            self.next = Some(next + period);
+            PERIOD_FRAMES
+        };
+
+        // A manual clock stands in for a device that never underruns,
+        // so it waits for the decoder instead of rendering silence.
+        if frames == 0 || (self.clock.manual && !renderer.is_ready(frames as usize)) {
+            renderer.update();
+            std::thread::sleep(Duration::from_millis(1));
+            return;
+        }
+
+        self.buffer
+            .resize(frames as usize * self.channels * size_of::<f32>(), 0);
+        renderer.fill(&mut self.buffer, self.channels);
+        self.write();
+
+        self.clock.frames.fetch_add(frames, Relaxed);
+        if self.clock.manual {
+            self.clock.budget.fetch_sub(frames, Relaxed);
+        }
+    }
+}
+
+/// Writes 32-bit float WAV files, the chunk sizes are filled in on drop.
+pub struct WavWriter {
+    pub writer: BufWriter<File>,
+    pub data_len: u32,
+}
+
+impl WavWriter {
+    pub fn create(
+        path: impl AsRef<Path>,
+        sample_rate: u32,
+        channels: u16,
+    ) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let block_align = channels * size_of::<f32>() as u16;
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        // WAVE_FORMAT_IEEE_FLOAT
+        writer.write_all(&3u16.to_le_bytes())?;
+        writer.write_all(&channels.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&32u16.to_le_bytes())?;
+
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        Ok(Self {
+            writer,
+            data_len: 0,
+        })
+    }
+
+    /// Appends interleaved little endian `f32` samples.
+    pub fn write(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.data_len += bytes.len() as u32;
+        Ok(())
+    }
+
+    pub fn write_samples(&mut self, samples: &[f32]) -> std::io::Result<()> {
+        for sample in samples {
+            self.write(&sample.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> std::io::Result<()> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(36 + self.data_len).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&self.data_len.to_le_bytes())?;
+        self.writer.flush()
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        let _ = self.finalize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const RATE: u32 = 44100;
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn wav_sink() {
+        let path = std::env::temp_dir().join(format!("onmi_sink_{}.wav", std::process::id()));
+        let clock = NullClock::manual();
+        let backend = NullBackend::new(RATE, 2, Arc::clone(&clock), NullSink::Wav(path.clone()));
+        let mut player = Player::with_backend(Arc::new(backend), NullBackend::device());
+
+        clock.advance(1024);
+        assert!(clock.wait(TIMEOUT));
+        player.shutdown();
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(bytes[24..28].try_into().unwrap()), RATE);
+        assert_eq!(
+            u32::from_le_bytes(bytes[40..44].try_into().unwrap()),
+            1024 * 8
+        );
+        assert_eq!(bytes.len(), 44 + 1024 * 8);
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,217 @@
+use crate::*;
+use pulseaudio::{Context, Simple};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const WAIT_MS: u64 = 50;
+const PERIOD_MS: u32 = 10;
+const LATENCY: Duration = Duration::from_millis(50);
+const APP_NAME: &str = "onmi";
+
+/// Device ids are sink names, e.g. `alsa_output.pci-0000_00_1f.3.analog-stereo`.
+pub struct PulseBackend {
+    pub context: Mutex<Context>,
+}
+
+impl PulseBackend {
+    /// Returns `None` if no server is running.
+    pub fn new() -> Option<Self> {
+        let context = Context::connect(APP_NAME).ok()?;
+        context.subscribe().ok()?;
+        Some(Self {
+            context: Mutex::new(context),
+        })
+    }
+}
+
+impl AudioBackend for PulseBackend {
+    fn name(&self) -> &'static str {
+        "PulseAudio"
+    }
+
+    fn devices(&self) -> Vec<Device> {
+        let context = self.context.lock().unwrap();
+        context
+            .sinks()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|sink| Device {
+                name: sink.description.unwrap_or_else(|| sink.name.clone()),
+                id: sink.name,
+            })
+            .collect()
+    }
+
+    fn default_device(&self) -> Option<Device> {
+        let name = self.context.lock().unwrap().default_sink().ok()??;
+        Some(
+            self.devices()
+                .into_iter()
+                .find(|d| d.id == name)
+                .unwrap_or(Device {
+                    name: name.clone(),
+                    id: name,
+                }),
+        )
+    }
+
+    fn open(
+        &self,
+        device: &Device,
+        sample_rate: Option<u32>,
+        channels: usize,
+    ) -> Option<Box<dyn OutputStream>> {
+        let sample_rate = match sample_rate {
+            Some(rate) => {
+                if !COMMON_SAMPLE_RATES.contains(&rate) {
+                    return None;
+                }
+                rate
+            }
+            None => 48000,
+        };
+
+        // The server remixes whatever it's given, but may still turn down a count.
+        let wanted = channels.clamp(1, MAX_CHANNELS) as u32;
+        let (channels, stream) = [wanted, 2].into_iter().find_map(|channels| {
+            let stream = Simple::new(
+                APP_NAME,
+                Some(&device.id),
+                sample_rate,
+                channels as u8,
+                LATENCY,
+            );
+            Some((channels, stream.ok()?))
+        })?;
+
+        Some(Box::new(PulseOutput {
+            stream,
+            device: device.clone(),
+            sample_rate,
+            channels,
+            buffer: Vec::new(),
+        }))
+    }
+
+    /// Only asks the server once it has reported a sink or default sink change.
+    fn default_changed(&self, current: &Device) -> Option<Device> {
+        let changed = self.context.lock().unwrap().poll_changes().unwrap_or(false);
+        if !changed {
+            return None;
+        }
+        self.default_device().filter(|d| d.id != current.id)
+    }
+}
+
+pub struct PulseOutput {
+    pub stream: Simple,
+    pub device: Device,
+    pub sample_rate: u32,
+    pub channels: u32,
+    buffer: Vec<u8>,
+}
+
+impl PulseOutput {
+    /// Blocks until a period has been queued on the server.
+    pub fn fill_buffer(&mut self, renderer: &mut Renderer) -> usize {
+        let frames = (self.sample_rate * PERIOD_MS / 1000) as usize;
+        let channels = self.channels as usize;
+        self.buffer.resize(frames * channels * size_of::<f32>(), 0);
+        renderer.fill(&mut self.buffer, channels);
+
+        match self.stream.write(&self.buffer) {
+            Ok(()) => frames,
+            Err(_) => {
+                renderer.state.set_error(RuntimeError::StreamStart);
+                0
+            }
+        }
+    }
+}
+
+impl OutputStream for PulseOutput {
+    fn device(&self) -> &Device {
+        &self.device
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        self.channels as usize
+    }
+
+    fn render(&mut self, renderer: &mut Renderer) {
+        if renderer.update() {
+            let _ = self.stream.flush();
+        }
+
+        if !renderer.is_active() {
+            std::thread::sleep(Duration::from_millis(WAIT_MS));
+            return;
+        }
+
+        self.fill_buffer(renderer);
+    }
+
+    fn latency(&self) -> Duration {
+        self.stream.latency().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::process::Command;
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::sync::mpsc::Receiver;
+    use std::time::{Duration, Instant};
+
+    fn pactl(args: &[&str]) -> String {
+        let output = Command::new("pactl").args(args).output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    /// Waits for the output thread to move onto a device `to` accepts.
+    fn moved(events: &Receiver<Event>, to: impl Fn(&Device) -> bool) -> Device {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            match events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(Event::DeviceChanged(device)) if to(&device) => return device,
+                Ok(_) => {}
+                Err(_) => panic!("the output didn't move"),
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a running PulseAudio or pipewire-pulse server"]
+    fn hotplug() {
+        let backend = Arc::new(PulseBackend::new().unwrap());
+        let mut player = Player::with_backend(backend.clone(), backend.default_device().unwrap());
+        let events = player.subscribe();
+        player.follow_default_device(true).unwrap();
+        player.play();
+
+        let module = pactl(&["load-module", "module-null-sink", "sink_name=onmi_test"]);
+        pactl(&["set-default-sink", "onmi_test"]);
+        // The output thread picks the change up from its subscription.
+        moved(&events, |device| device.id == "onmi_test");
+        assert!(backend.devices().iter().any(|d| d.id == "onmi_test"));
+
+        // And moves off it again once the sink is gone.
+        pactl(&["unload-module", &module]);
+        let device = moved(&events, |device| device.id != "onmi_test");
+        assert!(backend.find("onmi_test").is_none());
+        assert_eq!(backend.default_device().unwrap().id, device.id);
+
+        player.shutdown();
+        assert_eq!(
+            player.state.last_error.load(Relaxed),
+            RuntimeError::None as u8
+        );
+    }
+}
@@ -0,0 +1,315 @@
+use crate::Song;
+
+/// Id of a song that was not played from the queue.
+pub const NOT_QUEUED: u64 = u64::MAX;
+
+#[repr(u8)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Repeat {
+    Off = 0,
+    One = 1,
+    All = 2,
+}
+
+#[repr(u8)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Shuffle {
+    Off = 0,
+    Songs = 1,
+    /// Albums play in a random order, songs within an album keep theirs.
+    Albums = 2,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueEntry {
+    /// Stays the same when entries around it move, so the playing song can be found again.
+    pub id: u64,
+    pub song: Song,
+}
+
+/// Songs in the order they were added, plus the order they play in.
+/// The output thread never touches this, only `Player` and the decoder thread.
+pub struct Queue {
+    pub entries: Vec<QueueEntry>,
+    /// Ids in play order, the same as `entries` unless shuffled.
+    pub order: Vec<u64>,
+    pub repeat: Repeat,
+    pub shuffle: Shuffle,
+    next_id: u64,
+    rng: u64,
+    /// Last removed id and where it was in `order`, so the song after it can still be found.
+    removed: Option<(u64, usize)>,
+}
+
+impl Queue {
+    pub fn new() -> Self {
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Self::with_seed(seed)
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            entries: Vec::new(),
+            order: Vec::new(),
+            repeat: Repeat::Off,
+            shuffle: Shuffle::Off,
+            next_id: 0,
+            rng: seed | 1,
+            removed: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&QueueEntry> {
+        self.entries.get(index)
+    }
+
+    pub fn index_of(&self, id: u64) -> Option<usize> {
+        self.entries.iter().position(|e| e.id == id)
+    }
+
+    pub fn entry(&self, id: u64) -> Option<&QueueEntry> {
+        self.entries.iter().find(|e| e.id == id)
+    }
+
+    pub fn append(&mut self, song: Song) -> u64 {
+        self.insert(self.entries.len(), song)
+    }
+
+    /// Inserts before `index`. When shuffled the song goes somewhere random in the play order.
+    pub fn insert(&mut self, index: usize, song: Song) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        let index = index.min(self.entries.len());
+        self.entries.insert(index, QueueEntry { id, song });
+
+        if self.shuffle == Shuffle::Off {
+            self.order = self.entries.iter().map(|e| e.id).collect();
+        } else {
+            let at = self.random(self.order.len() + 1);
+            self.order.insert(at, id);
+        }
+        id
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Song> {
+        if index >= self.entries.len() {
+            return None;
+        }
+        let entry = self.entries.remove(index);
+        if let Some(pos) = self.order.iter().position(|id| *id == entry.id) {
+            self.order.remove(pos);
+            self.removed = Some((entry.id, pos));
+        }
+        Some(entry.song)
+    }
+
+    pub fn move_entry(&mut self, from: usize, to: usize) {
+        if from >= self.entries.len() {
+            return;
+        }
+        let entry = self.entries.remove(from);
+        self.entries.insert(to.min(self.entries.len()), entry);
+        if self.shuffle == Shuffle::Off {
+            self.order = self.entries.iter().map(|e| e.id).collect();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.removed = None;
+    }
+
+    /// Reshuffles with `current` first, so the playing song carries on and everything else is still ahead.
+    pub fn set_shuffle(&mut self, shuffle: Shuffle, current: Option<u64>) {
+        self.shuffle = shuffle;
+        self.order = self.entries.iter().map(|e| e.id).collect();
+
+        match shuffle {
+            Shuffle::Off => return,
+            Shuffle::Songs => {
+                for i in (1..self.order.len()).rev() {
+                    let j = self.random(i + 1);
+                    self.order.swap(i, j);
+                }
+            }
+            Shuffle::Albums => {
+                let mut albums: Vec<(&str, Vec<u64>)> = Vec::new();
+                for entry in &self.entries {
+                    match albums
+                        .iter_mut()
+                        .find(|(album, _)| *album == entry.song.album)
+                    {
+                        Some((_, ids)) => ids.push(entry.id),
+                        None => albums.push((&entry.song.album, vec![entry.id])),
+                    }
+                }
+                let mut albums: Vec<Vec<u64>> = albums.into_iter().map(|(_, ids)| ids).collect();
+                for i in (1..albums.len()).rev() {
+                    let j = self.random(i + 1);
+                    albums.swap(i, j);
+                }
+                if let Some(current) = current
+                    && let Some(i) = albums.iter().position(|a| a.contains(&current))
+                {
+                    let album = albums.remove(i);
+                    albums.insert(0, album);
+                }
+                self.order = albums.concat();
+                return;
+            }
+        }
+
+        if let Some(current) = current
+            && let Some(i) = self.order.iter().position(|id| *id == current)
+        {
+            self.order.remove(i);
+            self.order.insert(0, current);
+        }
+    }
+
+    /// The song to play after `current`, `None` at the end of the queue unless repeating.
+    pub fn next_after(&self, current: Option<u64>) -> Option<u64> {
+        self.after(current, self.repeat == Repeat::One)
+    }
+
+    /// Like `next_after` but moves on even when repeating one song, for skipping.
+    pub fn skip_after(&self, current: Option<u64>) -> Option<u64> {
+        self.after(current, false)
+    }
+
+    fn after(&self, current: Option<u64>, repeat_one: bool) -> Option<u64> {
+        let Some(current) = current else {
+            return self.order.first().copied();
+        };
+
+        let pos = match self.order.iter().position(|id| *id == current) {
+            Some(pos) if repeat_one => return Some(self.order[pos]),
+            Some(pos) => pos + 1,
+            // Removed while playing, carry on with whatever took its place.
+            None => match self.removed {
+                Some((id, pos)) if id == current => pos,
+                _ => return self.order.first().copied(),
+            },
+        };
+
+        match self.order.get(pos) {
+            Some(id) => Some(*id),
+            None if self.repeat == Repeat::All => self.order.first().copied(),
+            None => None,
+        }
+    }
+
+    pub fn previous_before(&self, current: Option<u64>) -> Option<u64> {
+        let pos = current.and_then(|current| self.order.iter().position(|id| *id == current));
+        match pos {
+            Some(0) if self.repeat == Repeat::All => self.order.last().copied(),
+            Some(0) => None,
+            Some(pos) => Some(self.order[pos - 1]),
+            None => self.order.first().copied(),
+        }
+    }
+
+    /// Xorshift, a shuffle doesn't need more.
+    fn random(&mut self, n: usize) -> usize {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng % n as u64) as usize
+    }
+}
+
+impl Default for Queue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(album: &str, track: u8) -> Song {
+        let mut song = Song::new();
+        song.album = album.to_string();
+        song.track_number = track;
+        song.path = format!("{album}/{track}.flac");
+        song
+    }
+
+    #[test]
+    fn order_and_repeat() {
+        let mut queue = Queue::with_seed(7);
+        let a = queue.append(song("a", 1));
+        let c = queue.append(song("a", 3));
+        let b = queue.insert(1, song("a", 2));
+        assert_eq!(queue.order, [a, b, c]);
+
+        assert_eq!(queue.next_after(None), Some(a));
+        assert_eq!(queue.next_after(Some(b)), Some(c));
+        assert_eq!(queue.next_after(Some(c)), None);
+        assert_eq!(queue.previous_before(Some(a)), None);
+
+        queue.repeat = Repeat::All;
+        assert_eq!(queue.next_after(Some(c)), Some(a));
+        assert_eq!(queue.previous_before(Some(a)), Some(c));
+        queue.repeat = Repeat::One;
+        assert_eq!(queue.next_after(Some(b)), Some(b));
+        assert_eq!(queue.skip_after(Some(b)), Some(c));
+        queue.repeat = Repeat::Off;
+
+        queue.move_entry(2, 0);
+        assert_eq!(queue.order, [c, a, b]);
+
+        // Removing the playing song continues with the one after it.
+        assert_eq!(queue.remove(1).unwrap().track_number, 1);
+        assert_eq!(queue.next_after(Some(a)), Some(b));
+        assert_eq!(queue.index_of(b), Some(1));
+
+        queue.clear();
+        assert!(queue.is_empty());
+        assert_eq!(queue.next_after(Some(b)), None);
+    }
+
+    #[test]
+    fn shuffle() {
+        let mut queue = Queue::with_seed(42);
+        let mut ids = Vec::new();
+        for album in ["a", "b", "c", "d"] {
+            for track in 1..=4 {
+                ids.push(queue.append(song(album, track)));
+            }
+        }
+
+        queue.set_shuffle(Shuffle::Songs, Some(ids[5]));
+        assert_eq!(queue.order[0], ids[5]);
+        assert_ne!(queue.order, ids);
+        let mut sorted = queue.order.clone();
+        sorted.sort();
+        assert_eq!(sorted, ids);
+
+        queue.set_shuffle(Shuffle::Albums, Some(ids[9]));
+        assert_eq!(queue.order[..4], ids[8..12]);
+        for album in queue.order.chunks(4) {
+            let first = queue.index_of(album[0]).unwrap();
+            assert_eq!(first % 4, 0);
+            for (i, id) in album.iter().enumerate() {
+                assert_eq!(queue.index_of(*id), Some(first + i));
+            }
+        }
+
+        queue.set_shuffle(Shuffle::Off, Some(ids[9]));
+        assert_eq!(queue.order, ids);
+    }
+}
@@ -0,0 +1,203 @@
+use symphonia::core::meta::StandardTag;
+
+/// Gain for songs without ReplayGain tags, about -6dB to sit near typically tagged songs.
+pub const DEFAULT_FALLBACK_GAIN: f32 = 0.5;
+/// Furthest the preamp raises or lowers tagged songs, in dB.
+pub const MAX_REPLAY_GAIN_PREAMP: f32 = 20.0;
+
+#[repr(u8)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ReplayGainMode {
+    /// Every song plays at unity gain.
+    Off = 0,
+    Track = 1,
+    /// Falls back to the track gain for songs without an album gain.
+    Album = 2,
+    /// Album gain for songs played from the queue in album order,
+    /// track gain when shuffled by song or played on their own.
+    Auto = 3,
+}
+
+impl ReplayGainMode {
+    pub fn from_u8(mode: u8) -> Self {
+        match mode {
+            x if x == ReplayGainMode::Off as u8 => ReplayGainMode::Off,
+            x if x == ReplayGainMode::Album as u8 => ReplayGainMode::Album,
+            x if x == ReplayGainMode::Auto as u8 => ReplayGainMode::Auto,
+            _ => ReplayGainMode::Track,
+        }
+    }
+}
+
+/// ReplayGain tags of a song. Gains and peaks are linear, zero when the tag is missing.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReplayGain {
+    pub track_gain: f32,
+    pub track_peak: f32,
+    pub album_gain: f32,
+    pub album_peak: f32,
+}
+
+impl ReplayGain {
+    pub fn is_empty(&self) -> bool {
+        self.track_gain == 0.0 && self.album_gain == 0.0
+    }
+
+    /// Picks up a ReplayGain tag read by Symphonia, other tags are ignored.
+    pub fn read_tag(&mut self, tag: &StandardTag) {
+        match tag {
+            StandardTag::ReplayGainTrackGain(v) => self.track_gain = parse_gain(v).unwrap_or(0.0),
+            StandardTag::ReplayGainTrackPeak(v) => self.track_peak = parse_peak(v).unwrap_or(0.0),
+            StandardTag::ReplayGainAlbumGain(v) => self.album_gain = parse_gain(v).unwrap_or(0.0),
+            StandardTag::ReplayGainAlbumPeak(v) => self.album_peak = parse_peak(v).unwrap_or(0.0),
+            _ => {}
+        }
+    }
+
+    /// Picks up a ReplayGain Vorbis comment, `key` is lowercase.
+    /// Returns false if it wasn't one.
+    pub fn read_comment(&mut self, key: &str, value: &str) -> bool {
+        match key {
+            "replaygain_track_gain" => self.track_gain = parse_gain(value).unwrap_or(0.0),
+            "replaygain_track_peak" => self.track_peak = parse_peak(value).unwrap_or(0.0),
+            "replaygain_album_gain" => self.album_gain = parse_gain(value).unwrap_or(0.0),
+            "replaygain_album_peak" => self.album_peak = parse_peak(value).unwrap_or(0.0),
+            _ => return false,
+        }
+        true
+    }
+
+    /// The gain to play at.
+    ///
+    /// The chosen gain is raised by `preamp_db`, then lowered if needed so the peak
+    /// stays at or below full scale. Untagged songs play at `fallback`, which is linear.
+    /// `album` is whether the song plays in album order, only used by `Auto`.
+    pub fn resolve(&self, mode: ReplayGainMode, preamp_db: f32, fallback: f32, album: bool) -> f32 {
+        let track = (self.track_gain, self.track_peak);
+        let (gain, peak) = match mode {
+            ReplayGainMode::Off => return 1.0,
+            ReplayGainMode::Track => track,
+            ReplayGainMode::Album | ReplayGainMode::Auto
+                if self.album_gain == 0.0 || (mode == ReplayGainMode::Auto && !album) =>
+            {
+                track
+            }
+            ReplayGainMode::Album | ReplayGainMode::Auto => (self.album_gain, self.album_peak),
+        };
+
+        if gain == 0.0 {
+            return fallback;
+        }
+        let gain = gain * db_to_linear(preamp_db);
+        if peak > 0.0 {
+            gain.min(1.0 / peak)
+        } else {
+            gain
+        }
+    }
+}
+
+pub fn db_to_linear(db: f32) -> f32 {
+    10.0f32.powf(db / 20.0)
+}
+
+/// Parses a gain like `"-5.39 dB"` to a linear gain.
+pub fn parse_gain(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let value = match value.len().checked_sub(2) {
+        Some(end) if value.is_char_boundary(end) && value[end..].eq_ignore_ascii_case("db") => {
+            &value[..end]
+        }
+        _ => value,
+    };
+    let db: f32 = value.trim().parse().ok()?;
+    db.is_finite().then(|| db_to_linear(db))
+}
+
+/// Parses a peak like `"0.988312"`, where 1.0 is full scale.
+pub fn parse_peak(value: &str) -> Option<f32> {
+    let peak: f32 = value.trim().parse().ok()?;
+    (peak.is_finite() && peak > 0.0).then_some(peak)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn parsing() {
+        assert!(close(parse_gain("-6.02 dB").unwrap(), 0.5));
+        assert!(close(parse_gain("+6.0206dB").unwrap(), 2.0));
+        assert!(close(parse_gain(" 0.00 DB ").unwrap(), 1.0));
+        assert!(close(parse_gain("-6.02").unwrap(), 0.5));
+        assert_eq!(parse_gain("dB"), None);
+        assert_eq!(parse_gain("loud"), None);
+        assert_eq!(parse_peak("0.988312"), Some(0.988312));
+        assert_eq!(parse_peak("0"), None);
+
+        let mut tags = ReplayGain::default();
+        assert!(tags.read_comment("replaygain_album_gain", "-6.02 dB"));
+        assert!(tags.read_comment("replaygain_album_peak", "1.5"));
+        assert!(!tags.read_comment("title", "-6.02 dB"));
+        assert!(close(tags.album_gain, 0.5));
+        assert_eq!(tags.album_peak, 1.5);
+        assert_eq!(tags.track_gain, 0.0);
+    }
+
+    #[test]
+    fn modes() {
+        let tags = ReplayGain {
+            track_gain: 0.5,
+            track_peak: 0.5,
+            album_gain: 0.25,
+            album_peak: 0.9,
+        };
+        let resolve = |mode, album| tags.resolve(mode, 0.0, 0.7, album);
+
+        assert_eq!(resolve(ReplayGainMode::Off, true), 1.0);
+        assert_eq!(resolve(ReplayGainMode::Track, true), 0.5);
+        assert_eq!(resolve(ReplayGainMode::Album, false), 0.25);
+        assert_eq!(resolve(ReplayGainMode::Auto, true), 0.25);
+        assert_eq!(resolve(ReplayGainMode::Auto, false), 0.5);
+
+        // No album gain, no tags at all.
+        let track_only = ReplayGain {
+            album_gain: 0.0,
+            ..tags
+        };
+        assert_eq!(
+            track_only.resolve(ReplayGainMode::Album, 0.0, 0.7, true),
+            0.5
+        );
+        let untagged = ReplayGain::default();
+        assert_eq!(untagged.resolve(ReplayGainMode::Album, 6.0, 0.7, true), 0.7);
+        assert_eq!(untagged.resolve(ReplayGainMode::Off, 6.0, 0.7, true), 1.0);
+    }
+
+    #[test]
+    fn preamp_and_peak() {
+        let tags = ReplayGain {
+            track_gain: 1.0,
+            track_peak: 0.8,
+            album_gain: 0.5,
+            album_peak: 0.0,
+        };
+
+        assert!(close(
+            tags.resolve(ReplayGainMode::Track, -6.02, 0.5, false),
+            0.5
+        ));
+        // +6dB would put the peak at 1.6, it's held at full scale instead.
+        assert_eq!(tags.resolve(ReplayGainMode::Track, 6.02, 0.5, false), 1.25);
+        assert!(tags.resolve(ReplayGainMode::Track, 6.02, 0.5, false) * tags.track_peak <= 1.0);
+        // Without a peak the preamp applies in full.
+        assert!(close(
+            tags.resolve(ReplayGainMode::Album, 6.02, 0.5, false),
+            1.0
+        ));
+    }
+}
@@ -0,0 +1,425 @@
+use std::f64::consts::PI;
+
+/// Filter length the downsampling filter is allowed to grow to, in input frames.
+pub const MAX_TAPS: usize = 1024;
+/// Input frames that can be pushed between two pulls without `push` allocating.
+pub const MAX_PUSH: usize = 1024;
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResampleQuality {
+    /// Short filter that rolls off from around 13kHz at 44.1kHz, about 55dB of rejection.
+    Low = 0,
+    /// Flat to about 18kHz at 44.1kHz with about 90dB of rejection.
+    Medium = 1,
+    /// Flat to about 20kHz at 44.1kHz with over 110dB of rejection.
+    High = 2,
+}
+
+impl ResampleQuality {
+    pub fn from_u8(quality: u8) -> Self {
+        match quality {
+            x if x == ResampleQuality::Low as u8 => ResampleQuality::Low,
+            x if x == ResampleQuality::High as u8 => ResampleQuality::High,
+            _ => ResampleQuality::Medium,
+        }
+    }
+
+    /// Taps, cutoff as a fraction of the lower Nyquist frequency, Kaiser beta and
+    /// how many filter phases are tabulated between two input frames.
+    fn params(self) -> (usize, f64, f64, usize) {
+        match self {
+            ResampleQuality::Low => (16, 0.80, 5.0, 64),
+            ResampleQuality::Medium => (64, 0.90, 8.6, 256),
+            ResampleQuality::High => (160, 0.95, 12.0, 512),
+        }
+    }
+}
+
+/// Windowed-sinc resampler for interleaved frames.
+///
+/// The sinc is tabulated at a fixed number of phases between two input frames and
+/// interpolated linearly in between, so any pair of rates works without a table per ratio.
+/// Input is pushed as it's read from the ring and output pulled as the device asks for it.
+pub struct Resampler {
+    pub from: u32,
+    pub to: u32,
+    pub channels: usize,
+    pub quality: ResampleQuality,
+    taps: usize,
+    phases: usize,
+    /// `phases + 1` rows of `taps` coefficients.
+    table: Vec<f32>,
+    /// The row for the current output, interpolated from two rows of `table`.
+    coefs: Vec<f32>,
+    /// Interleaved input, starting with the oldest frame the filter still needs.
+    input: Vec<f32>,
+    /// Position of the next output in `input`, `frame + frac / step_den` frames.
+    frame: usize,
+    frac: u64,
+    step_num: u64,
+    step_den: u64,
+    /// Input frames the output has moved through since the last `take_advanced`.
+    advanced: usize,
+    /// Where the input stops in `input` once `finish` was called.
+    end: Option<usize>,
+}
+
+impl Resampler {
+    pub fn new(from: u32, to: u32, channels: usize, quality: ResampleQuality) -> Self {
+        let (taps, cutoff, beta, phases) = quality.params();
+        let from = from.max(1);
+        let to = to.max(1);
+        let channels = channels.max(1);
+
+        // Downsampling lowers the cutoff, the filter is made longer to keep the same transition.
+        let scale = (to as f64 / from as f64).min(1.0);
+        let taps = ((taps as f64 / scale).ceil() as usize)
+            .next_multiple_of(2)
+            .min(MAX_TAPS);
+        let cutoff = cutoff * scale;
+        let half = (taps / 2) as f64;
+
+        let mut table = Vec::with_capacity((phases + 1) * taps);
+        for phase in 0..=phases {
+            let offset = phase as f64 / phases as f64;
+            let start = table.len();
+            for tap in 0..taps {
+                let x = tap as f64 + 1.0 - half - offset;
+                table.push(cutoff * sinc(cutoff * x) * kaiser(x / half, beta));
+            }
+            // Unity gain at DC for every phase.
+            let sum: f64 = table[start..].iter().sum();
+            for coef in &mut table[start..] {
+                *coef /= sum;
+            }
+        }
+
+        let gcd = gcd(from as u64, to as u64);
+        let mut resampler = Self {
+            from,
+            to,
+            channels,
+            quality,
+            taps,
+            phases,
+            table: table.into_iter().map(|c| c as f32).collect(),
+            coefs: vec![0.0; taps],
+            // The filter's frames, a push and the silence `finish` adds,
+            // the output can't reallocate it.
+            input: Vec::with_capacity((taps + MAX_PUSH + taps / 2 + 1) * channels),
+            frame: 0,
+            frac: 0,
+            step_num: from as u64 / gcd,
+            step_den: to as u64 / gcd,
+            advanced: 0,
+            end: None,
+        };
+        resampler.reset();
+        resampler
+    }
+
+    /// Forgets everything pushed so far, call on seeks and when the stream is flushed.
+    pub fn reset(&mut self) {
+        // Silence before the first frame, so output starts at the first input frame.
+        let history = self.taps / 2 - 1;
+        self.input.clear();
+        self.input.resize(history * self.channels, 0.0);
+        self.frame = history;
+        self.frac = 0;
+        self.advanced = 0;
+        self.end = None;
+    }
+
+    /// Frames of delay the filter adds, at the input rate.
+    pub fn latency(&self) -> usize {
+        self.taps / 2
+    }
+
+    /// Queues interleaved input frames. Pushing after `finish` carries on after its silence.
+    pub fn push(&mut self, input: &[f32]) {
+        if !input.is_empty() {
+            self.end = None;
+        }
+        self.input.extend_from_slice(input);
+    }
+
+    /// Input frames the output has moved through since the last call, to count
+    /// what has been played at the input rate.
+    pub fn take_advanced(&mut self) -> usize {
+        std::mem::take(&mut self.advanced)
+    }
+
+    /// Marks the end of the input, so the frames still in the filter can be pulled.
+    /// Returns false once they all have been.
+    pub fn finish(&mut self) -> bool {
+        let end = *self.end.get_or_insert_with(|| {
+            let end = self.input.len() / self.channels;
+            // Silence after the last frame for the filter to run into.
+            self.input
+                .resize((end + self.taps / 2 + 1) * self.channels, 0.0);
+            end
+        });
+        self.frame < end
+    }
+
+    /// Input frames to push before `frames` frames can be pulled.
+    pub fn input_needed(&self, frames: usize) -> usize {
+        if frames == 0 {
+            return 0;
+        }
+        let last =
+            self.frame as u64 + (self.frac + (frames as u64 - 1) * self.step_num) / self.step_den;
+        let needed = last as usize + self.taps / 2 + 1;
+        needed.saturating_sub(self.input.len() / self.channels)
+    }
+
+    /// Fills `output` with as many interleaved frames as the input allows.
+    /// Returns the number of frames written.
+    pub fn pull(&mut self, output: &mut [f32]) -> usize {
+        let channels = self.channels;
+        let taps = self.taps;
+        let half = taps / 2;
+        let available = self.input.len() / channels;
+        let mut written = 0;
+
+        for out in output.chunks_exact_mut(channels) {
+            if self.frame + half >= available || self.end.is_some_and(|end| self.frame >= end) {
+                break;
+            }
+
+            let position = self.frac as f64 * self.phases as f64 / self.step_den as f64;
+            let phase = (position as usize).min(self.phases - 1);
+            let t = (position - phase as f64) as f32;
+            let a = &self.table[phase * taps..(phase + 1) * taps];
+            let b = &self.table[(phase + 1) * taps..(phase + 2) * taps];
+            for ((coef, a), b) in self.coefs.iter_mut().zip(a).zip(b) {
+                *coef = a + (b - a) * t;
+            }
+
+            let start = (self.frame + 1 - half) * channels;
+            let frames = &self.input[start..start + taps * channels];
+            for (c, sample) in out.iter_mut().enumerate() {
+                *sample = self
+                    .coefs
+                    .iter()
+                    .zip(frames[c..].iter().step_by(channels))
+                    .map(|(coef, x)| coef * x)
+                    .sum();
+            }
+
+            self.frac += self.step_num;
+            let step = (self.frac / self.step_den) as usize;
+            self.frame += step;
+            self.advanced += step;
+            self.frac %= self.step_den;
+            written += 1;
+        }
+
+        // Drop the frames that have left the filter.
+        let used = (self.frame + 1 - half).min(available);
+        self.input.drain(..used * channels);
+        self.frame -= used;
+        if let Some(end) = self.end.as_mut() {
+            *end = end.saturating_sub(used);
+        }
+        written
+    }
+}
+
+pub(crate) fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Kaiser window over -1..1.
+pub(crate) fn kaiser(x: f64, beta: f64) -> f64 {
+    if x.abs() > 1.0 {
+        return 0.0;
+    }
+    bessel_i0(beta * (1.0 - x * x).sqrt()) / bessel_i0(beta)
+}
+
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    for k in 1..64 {
+        let half = x / (2.0 * k as f64);
+        term *= half * half;
+        sum += term;
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+    sum
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUALITIES: [ResampleQuality; 3] = [
+        ResampleQuality::Low,
+        ResampleQuality::Medium,
+        ResampleQuality::High,
+    ];
+
+    fn sine(rate: u32, freq: f64, seconds: f64) -> Vec<f32> {
+        (0..(rate as f64 * seconds) as usize)
+            .map(|i| (i as f64 * freq * 2.0 * PI / rate as f64).sin() as f32)
+            .collect()
+    }
+
+    fn resample(input: &[f32], from: u32, to: u32, quality: ResampleQuality) -> Vec<f32> {
+        let mut resampler = Resampler::new(from, to, 1, quality);
+        resampler.push(input);
+        let mut output = vec![0.0; input.len() * to as usize / from as usize + 1];
+        let n = resampler.pull(&mut output);
+        output.truncate(n);
+        output
+    }
+
+    /// Amplitude of `freq` in `samples`, Hann windowed so a loud tone elsewhere doesn't leak in.
+    fn amplitude(samples: &[f32], rate: u32, freq: f64) -> f64 {
+        let n = samples.len();
+        let (mut re, mut im, mut norm) = (0.0, 0.0, 0.0);
+        for (i, x) in samples.iter().enumerate() {
+            let w = 0.5 - 0.5 * (2.0 * PI * i as f64 / n as f64).cos();
+            let angle = 2.0 * PI * freq * i as f64 / rate as f64;
+            re += w * *x as f64 * angle.cos();
+            im += w * *x as f64 * angle.sin();
+            norm += w;
+        }
+        2.0 * (re * re + im * im).sqrt() / norm
+    }
+
+    fn db(amplitude: f64) -> f64 {
+        20.0 * amplitude.log10()
+    }
+
+    /// Skips the filter settling in at the start and running out at the end.
+    fn steady(samples: &[f32]) -> &[f32] {
+        &samples[2048..samples.len() - 2048]
+    }
+
+    #[test]
+    fn frequency_response() {
+        for (quality, flat_to) in QUALITIES.into_iter().zip([8000.0, 16000.0, 19000.0]) {
+            for (from, to) in [
+                (44100, 48000),
+                (48000, 44100),
+                (44100, 96000),
+                (96000, 44100),
+            ] {
+                for freq in [100.0, 1000.0, 5000.0, flat_to] {
+                    let output = resample(&sine(from, freq, 0.25), from, to, quality);
+                    let gain = db(amplitude(steady(&output), to, freq));
+                    assert!(
+                        gain.abs() < 0.1,
+                        "{quality:?} {from}->{to} {freq}Hz: {gain:.3}dB"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn aliasing() {
+        for (quality, floor) in QUALITIES.into_iter().zip([-50.0, -90.0, -110.0]) {
+            // Above the output's Nyquist frequency, it would fold back to 21.1kHz.
+            let output = resample(&sine(48000, 23000.0, 0.25), 48000, 44100, quality);
+            let alias = db(amplitude(steady(&output), 44100, 21100.0));
+            assert!(
+                alias < floor,
+                "{quality:?} downsampling alias: {alias:.1}dB"
+            );
+
+            // The image of 20kHz at 24.1kHz folds back to 23.9kHz.
+            let output = resample(&sine(44100, 20000.0, 0.25), 44100, 48000, quality);
+            let image = db(amplitude(steady(&output), 48000, 23900.0));
+            assert!(image < floor, "{quality:?} upsampling image: {image:.1}dB");
+        }
+    }
+
+    #[test]
+    fn streaming() {
+        let input: Vec<f32> = sine(44100, 1000.0, 0.2)
+            .into_iter()
+            .flat_map(|x| [x, -x])
+            .collect();
+        let frames = input.len() / 2;
+        let mut resampler = Resampler::new(44100, 48000, 2, ResampleQuality::Medium);
+        let mut whole = vec![0.0; input.len() * 2];
+        resampler.push(&input);
+        let n = resampler.pull(&mut whole);
+        whole.truncate(n * 2);
+        // Everything but the last half filter comes out.
+        let expected = (frames - resampler.latency()) * 48000 / 44100;
+        assert!(n.abs_diff(expected) <= 1, "{n} frames, expected {expected}");
+        assert!(whole.chunks_exact(2).all(|f| f[0] == -f[1]));
+
+        // Odd sized pushes and pulls give the same samples.
+        resampler.reset();
+        let mut chunked = Vec::new();
+        let mut block = [0.0; 2 * 37];
+        for chunk in input.chunks(2 * 101) {
+            resampler.push(chunk);
+            loop {
+                let n = resampler.pull(&mut block);
+                chunked.extend_from_slice(&block[..n * 2]);
+                if n < 37 {
+                    break;
+                }
+            }
+        }
+        assert_eq!(chunked, whole);
+
+        // Pushing exactly what `input_needed` asks for is enough.
+        resampler.reset();
+        let mut pushed = 0;
+        for _ in 0..50 {
+            let needed = resampler.input_needed(37);
+            resampler.push(&input[pushed * 2..(pushed + needed) * 2]);
+            pushed += needed;
+            assert_eq!(resampler.pull(&mut block), 37);
+            assert_eq!(resampler.input_needed(0), 0);
+        }
+        assert_eq!(resampler.pull(&mut block), 0);
+    }
+
+    #[test]
+    fn finish() {
+        let input = sine(44100, 1000.0, 0.2);
+        let mut resampler = Resampler::new(44100, 48000, 1, ResampleQuality::Medium);
+        let mut output = vec![0.0; input.len() * 2];
+        resampler.push(&input);
+        let mut n = resampler.pull(&mut output);
+        assert!(resampler.finish());
+        n += resampler.pull(&mut output[n..]);
+        assert!(!resampler.finish());
+
+        // Every input frame comes out, the position moved through all of them.
+        assert_eq!(n, (input.len() * 48000).div_ceil(44100));
+        assert_eq!(resampler.take_advanced(), input.len());
+        assert_eq!(resampler.take_advanced(), 0);
+
+        // Ending after a full push fits in what was reserved up front.
+        let mut resampler = Resampler::new(44100, 48000, 2, ResampleQuality::High);
+        let capacity = resampler.input.capacity();
+        resampler.push(&[0.5; MAX_PUSH * 2]);
+        resampler.pull(&mut [0.0; MAX_PUSH * 4]);
+        resampler.push(&[0.5; MAX_PUSH * 2]);
+        assert!(resampler.finish());
+        assert_eq!(resampler.input.capacity(), capacity);
+    }
+}
@@ -0,0 +1,324 @@
+use crate::Layout;
+use std::cell::UnsafeCell;
+use std::sync::Arc;
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, Release};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU32, AtomicUsize};
+use std::time::Duration;
+
+/// `Ring::end` while the decoder is still pushing.
+const OPEN: u8 = 0;
+/// The decoder reached the end of the stream.
+const ENDED: u8 = 1;
+/// The consumer drained the ended stream, it can't be reopened.
+const CLOSED: u8 = 2;
+
+/// Single producer, single consumer queue of interleaved samples between
+/// the decoder thread and the output. Only the consumer ever moves `read`.
+pub struct Ring {
+    buffer: Box<[UnsafeCell<f32>]>,
+    mask: usize,
+    read: AtomicUsize,
+    write: AtomicUsize,
+    flush: AtomicBool,
+    layout: AtomicU32,
+    end: AtomicU8,
+    boundary: AtomicUsize,
+    boundary_set: AtomicBool,
+}
+
+unsafe impl Send for Ring {}
+unsafe impl Sync for Ring {}
+
+impl Ring {
+    /// `capacity` is in samples and rounded up to a power of two.
+    pub fn with_capacity(capacity: usize) -> (Producer, Consumer) {
+        let capacity = capacity.next_power_of_two();
+        let ring = Arc::new(Ring {
+            buffer: (0..capacity).map(|_| UnsafeCell::new(0.0)).collect(),
+            mask: capacity - 1,
+            read: AtomicUsize::new(0),
+            write: AtomicUsize::new(0),
+            flush: AtomicBool::new(false),
+            layout: AtomicU32::new(Layout::STEREO.0),
+            end: AtomicU8::new(OPEN),
+            boundary: AtomicUsize::new(0),
+            boundary_set: AtomicBool::new(false),
+        });
+
+        (
+            Producer {
+                ring: Arc::clone(&ring),
+            },
+            Consumer {
+                ring,
+                layout: Layout::STEREO,
+            },
+        )
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.write
+            .load(Acquire)
+            .wrapping_sub(self.read.load(Acquire))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The decoder reached the end of the stream, some of it may still be buffered.
+    pub fn ended(&self) -> bool {
+        self.end.load(Acquire) != OPEN
+    }
+
+    /// The decoder reached the end of the stream and everything has been consumed.
+    pub fn is_drained(&self) -> bool {
+        self.ended() && self.is_empty()
+    }
+
+    pub fn flush_requested(&self) -> bool {
+        self.flush.load(Acquire)
+    }
+
+    /// A track boundary was pushed that the consumer has not reached yet.
+    pub fn boundary_pending(&self) -> bool {
+        self.boundary_set.load(Acquire)
+    }
+}
+
+pub struct Producer {
+    pub ring: Arc<Ring>,
+}
+
+impl Producer {
+    pub fn free(&self) -> usize {
+        let ring = &*self.ring;
+        ring.capacity()
+            - ring
+                .write
+                .load(Relaxed)
+                .wrapping_sub(ring.read.load(Acquire))
+    }
+
+    /// Channel count of the samples pushed since the last flush.
+    pub fn channels(&self) -> usize {
+        Layout(self.ring.layout.load(Relaxed)).channels().max(1)
+    }
+
+    /// Returns how many samples fit.
+    pub fn push(&mut self, samples: &[f32]) -> usize {
+        let ring = &*self.ring;
+        let n = samples.len().min(self.free());
+        let write = ring.write.load(Relaxed);
+        for (i, sample) in samples[..n].iter().enumerate() {
+            unsafe { *ring.buffer[(write + i) & ring.mask].get() = *sample };
+        }
+        ring.write.store(write.wrapping_add(n), Release);
+        n
+    }
+
+    pub fn set_end(&self) {
+        self.ring.end.store(ENDED, Release);
+    }
+
+    /// Takes back `set_end` so more can be pushed.
+    /// Returns false if the consumer already closed the stream.
+    pub fn reopen(&self) -> bool {
+        self.ring
+            .end
+            .compare_exchange(ENDED, OPEN, AcqRel, Acquire)
+            .is_ok()
+    }
+
+    /// Marks the next sample pushed as the first one of a new track.
+    /// Only one boundary can be pending at a time.
+    pub fn mark_boundary(&mut self) {
+        let ring = &*self.ring;
+        debug_assert!(!ring.boundary_pending());
+        ring.boundary.store(ring.write.load(Relaxed), Relaxed);
+        ring.boundary_set.store(true, Release);
+    }
+
+    /// Asks the consumer to drop everything buffered and waits until it has.
+    /// Samples pushed afterwards are read as frames of `layout`.
+    /// Returns false if `cancel` was set while waiting.
+    pub fn flush(&mut self, layout: Layout, cancel: &AtomicBool) -> bool {
+        let ring = &*self.ring;
+        ring.end.store(OPEN, Relaxed);
+        ring.layout.store(layout.0, Relaxed);
+        ring.flush.store(true, Release);
+
+        while ring.flush.load(Acquire) {
+            if cancel.load(Relaxed) {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        true
+    }
+}
+
+pub struct Consumer {
+    pub ring: Arc<Ring>,
+    layout: Layout,
+}
+
+impl Consumer {
+    /// Channel count of the samples currently buffered.
+    pub fn channels(&self) -> usize {
+        self.layout.channels().max(1)
+    }
+
+    /// Speaker layout of the samples currently buffered.
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Acknowledges a pending flush. Returns true if one happened.
+    pub fn poll_flush(&mut self) -> bool {
+        let ring = &*self.ring;
+        if !ring.flush.load(Acquire) {
+            return false;
+        }
+        ring.read.store(ring.write.load(Acquire), Release);
+        self.layout = Layout(ring.layout.load(Relaxed));
+        ring.boundary_set.store(false, Release);
+        ring.flush.store(false, Release);
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ring.is_empty()
+    }
+
+    pub fn is_drained(&self) -> bool {
+        self.ring.is_drained()
+    }
+
+    /// Call once drained, after which the producer can't reopen the stream.
+    /// Returns false if it was reopened first.
+    pub fn close(&self) -> bool {
+        self.ring
+            .end
+            .compare_exchange(ENDED, CLOSED, AcqRel, Acquire)
+            .is_ok()
+    }
+
+    /// Everything before the pending boundary has been consumed.
+    /// Call `pass_boundary` before reading on.
+    pub fn at_boundary(&self) -> bool {
+        let ring = &*self.ring;
+        ring.boundary_set.load(Acquire) && ring.boundary.load(Relaxed) == ring.read.load(Relaxed)
+    }
+
+    pub fn pass_boundary(&mut self) {
+        self.ring.boundary_set.store(false, Release);
+    }
+
+    /// Samples that can be popped without crossing the pending boundary.
+    pub fn available(&self) -> usize {
+        let ring = &*self.ring;
+        let read = ring.read.load(Relaxed);
+        // `write` first, anything pushed after a boundary is only visible once the boundary is.
+        let len = ring.write.load(Acquire).wrapping_sub(read);
+        if ring.boundary_set.load(Acquire) {
+            len.min(ring.boundary.load(Relaxed).wrapping_sub(read))
+        } else {
+            len
+        }
+    }
+
+    /// Returns how many samples were copied into `out`. Use `available` to stop at a boundary.
+    pub fn pop(&mut self, out: &mut [f32]) -> usize {
+        let ring = &*self.ring;
+        let read = ring.read.load(Relaxed);
+        let n = out.len().min(ring.write.load(Acquire).wrapping_sub(read));
+        for (i, sample) in out[..n].iter_mut().enumerate() {
+            *sample = unsafe { *ring.buffer[(read + i) & ring.mask].get() };
+        }
+        ring.read.store(read.wrapping_add(n), Release);
+        n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_and_flushes() {
+        let (mut producer, mut consumer) = Ring::with_capacity(6);
+        assert_eq!(producer.free(), 8);
+
+        let mut out = [0.0; 8];
+        for round in 0..5 {
+            let base = round as f32 * 10.0;
+            assert_eq!(producer.push(&[base, base + 1.0, base + 2.0]), 3);
+            assert_eq!(consumer.pop(&mut out[..2]), 2);
+            assert_eq!(consumer.pop(&mut out[2..]), 1);
+            assert_eq!(&out[..3], &[base, base + 1.0, base + 2.0]);
+        }
+
+        assert_eq!(producer.push(&[1.0; 10]), 8);
+        producer.set_end();
+        assert!(!consumer.is_drained());
+        assert!(producer.reopen());
+        assert!(!consumer.ring.ended());
+        producer.set_end();
+
+        let cancel = AtomicBool::new(false);
+        let thread = std::thread::spawn(move || {
+            producer.flush(Layout::SURROUND_5_1, &cancel);
+            producer
+        });
+        while !consumer.poll_flush() {
+            std::thread::yield_now();
+        }
+        let producer = thread.join().unwrap();
+
+        assert!(consumer.is_empty());
+        assert!(!consumer.is_drained());
+        assert_eq!(consumer.channels(), 6);
+        assert_eq!(consumer.layout(), Layout::SURROUND_5_1);
+        assert_eq!(producer.free(), 8);
+    }
+
+    #[test]
+    fn boundary() {
+        let (mut producer, mut consumer) = Ring::with_capacity(8);
+        producer.push(&[1.0, 2.0]);
+        producer.mark_boundary();
+        producer.push(&[3.0, 4.0]);
+        assert!(!consumer.at_boundary());
+        assert_eq!(consumer.available(), 2);
+
+        let mut out = [0.0; 2];
+        consumer.pop(&mut out);
+        assert!(consumer.at_boundary());
+        assert_eq!(consumer.available(), 0);
+        consumer.pass_boundary();
+        assert!(!consumer.ring.boundary_pending());
+        assert_eq!(consumer.available(), 2);
+        consumer.pop(&mut out);
+        assert_eq!(out, [3.0, 4.0]);
+    }
+
+    #[test]
+    fn close() {
+        let (producer, consumer) = Ring::with_capacity(8);
+        assert!(!consumer.close());
+        producer.set_end();
+        assert!(consumer.close());
+        // Too late to take the end back.
+        assert!(!producer.reopen());
+        assert!(consumer.is_drained());
+    }
+}
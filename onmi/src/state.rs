@@ -1,9 +1,19 @@
-use crate::{Output, State, Symphonia};
+use crate::{
+    DEFAULT_FALLBACK_GAIN, DEFAULT_LIMITER_CEILING, DEFAULT_LIMITER_RELEASE, Dither, DspChain,
+    EqPreset, Equalizer, Event, FadeCurve, Limiter, NOT_QUEUED, NextSong, OutputStream, Queue,
+    ReplayGain, ReplayGainMode, ResampleQuality, Resampler, State, Symphonia, VolumeCurve,
+    linear_to_percent,
+};
 use std::ptr;
-use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, AtomicU8, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU8, AtomicU32, AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 pub const DEFAULT_VOLUME_REDUCTION: f32 = 75.0;
+pub const DEFAULT_FADE_MS: u32 = 10;
+/// Longer fades make pausing and seeking feel sluggish.
+pub const MAX_FADE_MS: u32 = 50;
 
 #[repr(u8)]
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -13,6 +23,16 @@ pub enum RuntimeError {
     StreamStart = 2,
 }
 
+impl RuntimeError {
+    pub fn from_u8(error: u8) -> Self {
+        match error {
+            x if x == RuntimeError::OutputOpen as u8 => RuntimeError::OutputOpen,
+            x if x == RuntimeError::StreamStart as u8 => RuntimeError::StreamStart,
+            _ => RuntimeError::None,
+        }
+    }
+}
+
 pub struct Mailbox<T> {
     pub ptr: AtomicPtr<T>,
 }
@@ -42,6 +62,39 @@ impl<T> Mailbox<T> {
             unsafe { Some(*Box::from_raw(p)) }
         }
     }
+
+    /// Like `take`, but the value stays boxed so nothing is freed by the taker.
+    pub fn take_box(&self) -> Option<Box<T>> {
+        let p = self.ptr.swap(ptr::null_mut(), Ordering::AcqRel);
+        if p.is_null() {
+            None
+        } else {
+            unsafe { Some(Box::from_raw(p)) }
+        }
+    }
+
+    /// Hands `value` over if the mailbox is empty, otherwise gives it back.
+    /// Never drops anything, so the output can send values back to be freed elsewhere.
+    pub fn put_box(&self, value: Box<T>) -> Result<(), Box<T>> {
+        let new = Box::into_raw(value);
+        match self
+            .ptr
+            .compare_exchange(ptr::null_mut(), new, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => Ok(()),
+            Err(_) => Err(unsafe { Box::from_raw(new) }),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ptr.load(Ordering::Acquire).is_null()
+    }
+}
+
+impl<T> Default for Mailbox<T> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<T> Drop for Mailbox<T> {
@@ -60,19 +113,110 @@ unsafe impl<T: Send> Sync for Mailbox<T> {}
 
 pub struct PlayerState {
     pub state: AtomicU8,
+    /// Linear, kept while muted so unmuting goes back to it.
     pub volume: AtomicU32,
     pub gain: AtomicU32,
     pub volume_reduction: AtomicU32,
-    pub elapsed: AtomicU64,
+    /// How `Player::set_volume` maps 0 to 100 onto `volume`.
+    pub volume_curve: AtomicU8,
+    pub muted: AtomicBool,
+    /// Frames of the current song written to the output, including ones not heard yet.
+    pub position: AtomicU64,
+    /// Reported by the output after every render, in nanoseconds.
+    pub latency: AtomicU64,
     pub duration: AtomicU64,
     pub seek: AtomicU64,
+    /// Decode up to the exact frame on seeks instead of the start of its packet.
+    pub accurate_seek: AtomicBool,
+    /// Sample rate of the song being played, 0 before the first one.
+    pub sample_rate: AtomicU32,
+    /// Channel count of the song being played, outputs are opened with up to this many.
+    pub channels: AtomicU32,
+    /// Asks the output thread to reopen at this rate, 0 if nothing is pending.
+    pub pending_rate: AtomicU32,
+    /// Rate of the open output, songs at any other rate are resampled to it.
+    pub output_rate: AtomicU32,
+    /// Channels of the open output, 0 before one is open.
+    pub output_channels: AtomicU32,
+    pub resample_quality: AtomicU8,
+    /// Applied by outputs opened with an integer format.
+    pub dither: AtomicU8,
+    pub replay_gain_mode: AtomicU8,
+    /// In dB, added to tagged songs.
+    pub replay_gain_preamp: AtomicU32,
+    /// Linear gain for songs without tags.
+    pub fallback_gain: AtomicU32,
+    /// Runs the output through a `Limiter` after the volume.
+    pub limiter: AtomicBool,
+    /// In dBTP.
+    pub limiter_ceiling: AtomicU32,
+    pub limiter_release_ms: AtomicU32,
+    /// Delay the limiter adds, on or off, in nanoseconds.
+    pub limiter_latency: AtomicU64,
     pub finished: AtomicBool,
     pub decoder_pending: AtomicBool,
     pub shutdown: AtomicBool,
     pub follow_default: AtomicBool,
     pub last_error: AtomicU8,
+    /// Counters the output thread turns into events.
+    pub errors: AtomicU64,
+    pub songs_started: AtomicU64,
+    pub songs_finished: AtomicU64,
+    pub seeks: AtomicU64,
+    /// Where the last seek landed.
+    pub seeked_to: AtomicU64,
+    /// Only ever `try_lock`ed by the output thread.
+    pub subscribers: Mutex<Vec<Sender<Event>>>,
+    /// Times the output found the decoder ring empty mid song.
+    pub underruns: AtomicU64,
+    /// Frames of silence written because of underruns.
+    pub underrun_frames: AtomicU64,
+    /// Samples the limiter turned down.
+    pub limited_samples: AtomicU64,
+    /// Samples past full scale when written to the output.
+    pub clipped_samples: AtomicU64,
     pub pending_decoder: Mailbox<Symphonia>,
-    pub pending_output: Mailbox<Output>,
+    pub pending_next: Mailbox<NextSong>,
+    /// Set from `queue_next` until the output reaches the first sample of that song.
+    pub next_queued: AtomicBool,
+    /// Applied by `start_next` once the output reaches the track boundary.
+    pub next_gain: AtomicU32,
+    pub next_duration: AtomicU64,
+    pub next_sample_rate: AtomicU32,
+    /// False if the next song needs the output reopened or its channels changed.
+    pub next_gapless: AtomicBool,
+    /// Zero turns crossfading off.
+    pub crossfade_ms: AtomicU32,
+    pub crossfade_curve: AtomicU8,
+    pub smart_crossfade: AtomicBool,
+    /// Length of the fades on pause, resume, seek, stop and volume changes, zero turns them off.
+    pub fade_ms: AtomicU32,
+    /// Locked by `Player` and the decoder thread, never by the output.
+    pub queue: Mutex<Queue>,
+    /// Queue id of the song being heard, `NOT_QUEUED` if it was played directly.
+    pub playing_id: AtomicU64,
+    /// Queue id of the song after the pending boundary.
+    pub next_id: AtomicU64,
+    pub pending_output: Mailbox<Box<dyn OutputStream>>,
+    /// Picked up by the output on its next fill.
+    pub pending_eq: Mailbox<Equalizer>,
+    /// The output's old equalizer, dropped by the decoder thread.
+    pub retired_eq: Mailbox<Equalizer>,
+    /// The preset last sent to the output and the rate its filters were built for.
+    /// Locked by `Player` and the decoder thread, never by the output.
+    pub eq_preset: Mutex<(EqPreset, u32)>,
+    /// Prepared by `Player::set_dsp_chain` for the output's rate and channels.
+    pub pending_dsp: Mailbox<DspChain>,
+    /// The output's old chain, dropped by the decoder thread.
+    pub retired_dsp: Mailbox<DspChain>,
+    /// Built by the decoder thread whenever the rates differ, so the output never allocates one.
+    pub pending_resampler: Mailbox<Resampler>,
+    /// The output's old resampler, dropped by the decoder thread.
+    pub retired_resampler: Mailbox<Resampler>,
+    /// Built by the decoder thread for the output's rate and channels.
+    pub pending_limiter: Mailbox<Limiter>,
+    /// The output's old limiter, dropped by the decoder thread.
+    pub retired_limiter: Mailbox<Limiter>,
 }
 
 impl PlayerState {
@@ -80,27 +224,176 @@ impl PlayerState {
         Arc::new(PlayerState {
             state: AtomicU8::new(State::Stopped as u8),
             volume: AtomicU32::new(((15.0 / DEFAULT_VOLUME_REDUCTION) * 0.5).to_bits()),
-            gain: AtomicU32::new(0.5f32.to_bits()),
+            gain: AtomicU32::new(DEFAULT_FALLBACK_GAIN.to_bits()),
             volume_reduction: AtomicU32::new(DEFAULT_VOLUME_REDUCTION.to_bits()),
-            elapsed: AtomicU64::new(0),
+            volume_curve: AtomicU8::new(VolumeCurve::Linear as u8),
+            muted: AtomicBool::new(false),
+            position: AtomicU64::new(0),
+            latency: AtomicU64::new(0),
             duration: AtomicU64::new(0),
             seek: AtomicU64::new(u64::MAX),
+            accurate_seek: AtomicBool::new(false),
+            sample_rate: AtomicU32::new(0),
+            channels: AtomicU32::new(2),
+            pending_rate: AtomicU32::new(0),
+            output_rate: AtomicU32::new(0),
+            output_channels: AtomicU32::new(0),
+            resample_quality: AtomicU8::new(ResampleQuality::Medium as u8),
+            dither: AtomicU8::new(Dither::Tpdf as u8),
+            replay_gain_mode: AtomicU8::new(ReplayGainMode::Track as u8),
+            replay_gain_preamp: AtomicU32::new(0f32.to_bits()),
+            fallback_gain: AtomicU32::new(DEFAULT_FALLBACK_GAIN.to_bits()),
+            limiter: AtomicBool::new(false),
+            limiter_ceiling: AtomicU32::new(DEFAULT_LIMITER_CEILING.to_bits()),
+            limiter_release_ms: AtomicU32::new(DEFAULT_LIMITER_RELEASE.as_millis() as u32),
+            limiter_latency: AtomicU64::new(0),
             finished: AtomicBool::new(false),
             decoder_pending: AtomicBool::new(false),
             shutdown: AtomicBool::new(false),
             follow_default: AtomicBool::new(false),
             last_error: AtomicU8::new(RuntimeError::None as u8),
+            errors: AtomicU64::new(0),
+            songs_started: AtomicU64::new(0),
+            songs_finished: AtomicU64::new(0),
+            seeks: AtomicU64::new(0),
+            seeked_to: AtomicU64::new(0),
+            subscribers: Mutex::new(Vec::new()),
+            underruns: AtomicU64::new(0),
+            underrun_frames: AtomicU64::new(0),
+            limited_samples: AtomicU64::new(0),
+            clipped_samples: AtomicU64::new(0),
             pending_decoder: Mailbox::new(),
+            pending_next: Mailbox::new(),
+            next_queued: AtomicBool::new(false),
+            next_gain: AtomicU32::new(DEFAULT_FALLBACK_GAIN.to_bits()),
+            next_duration: AtomicU64::new(0),
+            next_sample_rate: AtomicU32::new(0),
+            next_gapless: AtomicBool::new(true),
+            crossfade_ms: AtomicU32::new(0),
+            crossfade_curve: AtomicU8::new(FadeCurve::Linear as u8),
+            smart_crossfade: AtomicBool::new(false),
+            fade_ms: AtomicU32::new(DEFAULT_FADE_MS),
+            queue: Mutex::new(Queue::new()),
+            playing_id: AtomicU64::new(NOT_QUEUED),
+            next_id: AtomicU64::new(NOT_QUEUED),
             pending_output: Mailbox::new(),
+            pending_eq: Mailbox::new(),
+            retired_eq: Mailbox::new(),
+            eq_preset: Mutex::new((EqPreset::default(), 0)),
+            pending_dsp: Mailbox::new(),
+            retired_dsp: Mailbox::new(),
+            pending_resampler: Mailbox::new(),
+            retired_resampler: Mailbox::new(),
+            pending_limiter: Mailbox::new(),
+            retired_limiter: Mailbox::new(),
         })
     }
 
+    /// Volume from 0 to 100, as set with `Player::set_volume`.
+    pub fn volume_percent(&self) -> u8 {
+        linear_to_percent(
+            f32::from_bits(self.volume.load(Ordering::Relaxed)),
+            VolumeCurve::from_u8(self.volume_curve.load(Ordering::Relaxed)),
+            f32::from_bits(self.volume_reduction.load(Ordering::Relaxed)),
+        )
+        .round() as u8
+    }
+
+    /// The gain to play a song with `tags` at, with the current ReplayGain settings.
+    pub fn replay_gain(&self, tags: &ReplayGain, in_album_order: bool) -> f32 {
+        tags.resolve(
+            ReplayGainMode::from_u8(self.replay_gain_mode.load(Ordering::Relaxed)),
+            f32::from_bits(self.replay_gain_preamp.load(Ordering::Relaxed)),
+            f32::from_bits(self.fallback_gain.load(Ordering::Relaxed)),
+            in_album_order,
+        )
+    }
+
+    /// Rate the output's processors run at, the song's until an output is open.
+    pub fn processing_rate(&self) -> u32 {
+        match self.output_rate.load(Ordering::Relaxed) {
+            0 => self.sample_rate.load(Ordering::Relaxed),
+            rate => rate,
+        }
+    }
+
+    /// Song and output rate, when they differ and the output has to resample.
+    pub fn resample_rates(&self) -> Option<(u32, u32)> {
+        let from = self.sample_rate.load(Ordering::Relaxed);
+        let to = self.output_rate.load(Ordering::Relaxed);
+        (from != 0 && to != 0 && from != to).then_some((from, to))
+    }
+
     pub fn set_error(&self, error: RuntimeError) {
         self.last_error.store(error as u8, Ordering::Relaxed);
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Time of the last frame written to the output, including what the limiter holds back.
+    pub fn rendered(&self) -> Duration {
+        match self.sample_rate.load(Ordering::Relaxed) {
+            0 => Duration::ZERO,
+            rate => {
+                let frames = self.position.load(Ordering::Relaxed);
+                Duration::from_secs_f64(frames as f64 / rate as f64)
+            }
+        }
+    }
+
+    /// Time of the frame being heard. Once paused the output drains up to what the limiter holds.
+    pub fn elapsed(&self) -> Duration {
+        let mut latency = self.limiter_latency.load(Ordering::Relaxed);
+        if self.state.load(Ordering::Relaxed) == State::Playing as u8 {
+            latency += self.latency.load(Ordering::Relaxed);
+        }
+        self.rendered()
+            .saturating_sub(Duration::from_nanos(latency))
+    }
+
+    /// Describes a song that's about to replace whatever is playing, before its decoder
+    /// is sent to the decoder thread. Nothing is rendered until it gets there.
+    pub fn load(&self, decoder: &Symphonia, gain: f32, id: u64) {
+        self.next_queued.store(false, Ordering::Relaxed);
+        self.sample_rate
+            .store(decoder.sample_rate, Ordering::Relaxed);
+        self.channels
+            .store(decoder.layout.channels() as u32, Ordering::Relaxed);
+        self.position.store(0, Ordering::Relaxed);
+        self.finished.store(false, Ordering::Relaxed);
+        self.duration
+            .store(decoder.duration.as_nanos() as u64, Ordering::Relaxed);
+        self.gain.store(gain.to_bits(), Ordering::Relaxed);
+        self.decoder_pending.store(true, Ordering::Relaxed);
+        self.playing_id.store(id, Ordering::Relaxed);
+    }
+
+    /// Called by the output when it reaches the first sample of a queued song.
+    pub fn start_next(&self) {
+        self.gain
+            .store(self.next_gain.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.duration.store(
+            self.next_duration.load(Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.sample_rate.store(
+            self.next_sample_rate.load(Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.playing_id
+            .store(self.next_id.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.position.store(0, Ordering::Relaxed);
+        self.next_queued.store(false, Ordering::Relaxed);
+        self.songs_finished.fetch_add(1, Ordering::Relaxed);
+        self.songs_started.fetch_add(1, Ordering::Relaxed);
+        if !self.next_gapless.load(Ordering::Relaxed) {
+            // Nothing more is rendered until the decoder thread has switched formats.
+            self.decoder_pending.store(true, Ordering::Relaxed);
+        }
     }
 
     pub fn mark_finished(&self) {
         self.finished.store(true, Ordering::Relaxed);
+        self.songs_finished.fetch_add(1, Ordering::Relaxed);
         self.state.store(State::Stopped as u8, Ordering::Relaxed);
     }
 }
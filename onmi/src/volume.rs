@@ -0,0 +1,111 @@
+use crate::db_to_linear;
+
+/// Decibels below full volume that the `Logarithmic` curve starts at, just above 0%.
+pub const VOLUME_RANGE_DB: f32 = 60.0;
+/// Louder volumes are limited to this, well past where most songs clip.
+pub const MAX_VOLUME_DB: f32 = 12.0;
+
+#[repr(u8)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum VolumeCurve {
+    /// Gain grows with the percentage, most of the audible change is in the bottom quarter.
+    Linear = 0,
+    /// Every percent is the same step in decibels, over `VOLUME_RANGE_DB`.
+    Logarithmic = 1,
+    /// Gain is the cube of the fraction, close to how loud it sounds without the logarithmic
+    /// curve's jump to silence at 0%.
+    Cubic = 2,
+}
+
+impl VolumeCurve {
+    pub fn from_u8(curve: u8) -> Self {
+        match curve {
+            x if x == VolumeCurve::Logarithmic as u8 => VolumeCurve::Logarithmic,
+            x if x == VolumeCurve::Cubic as u8 => VolumeCurve::Cubic,
+            _ => VolumeCurve::Linear,
+        }
+    }
+
+    /// Gain for `fraction` of full volume, from 0 to 1.
+    pub fn gain(self, fraction: f32) -> f32 {
+        let fraction = fraction.clamp(0.0, 1.0);
+        match self {
+            VolumeCurve::Linear => fraction,
+            VolumeCurve::Logarithmic if fraction == 0.0 => 0.0,
+            VolumeCurve::Logarithmic => db_to_linear((fraction - 1.0) * VOLUME_RANGE_DB),
+            VolumeCurve::Cubic => fraction * fraction * fraction,
+        }
+    }
+
+    /// The fraction of full volume giving `gain`, the inverse of `gain`.
+    pub fn fraction(self, gain: f32) -> f32 {
+        let gain = gain.clamp(0.0, 1.0);
+        match self {
+            VolumeCurve::Linear => gain,
+            VolumeCurve::Logarithmic if gain == 0.0 => 0.0,
+            VolumeCurve::Logarithmic => (1.0 + linear_to_db(gain) / VOLUME_RANGE_DB).max(0.0),
+            VolumeCurve::Cubic => gain.cbrt(),
+        }
+    }
+}
+
+/// Decibels for a linear gain, the inverse of `db_to_linear`: 1.0 is 0dB, 0.5 about -6dB
+/// and silence negative infinity.
+pub fn linear_to_db(linear: f32) -> f32 {
+    20.0 * linear.log10()
+}
+
+/// Linear gain for `percent` from 0 to 100, `curve(percent / 100) * 100 / reduction`.
+/// With the linear curve that is the `percent / reduction` of `Player::set_volume`.
+pub fn percent_to_linear(percent: f32, curve: VolumeCurve, reduction: f32) -> f32 {
+    curve.gain(percent / 100.0) * 100.0 / reduction
+}
+
+/// Percent for a linear gain, the inverse of `percent_to_linear`. Limited to 100 even when the
+/// gain was set louder in decibels.
+pub fn linear_to_percent(linear: f32, curve: VolumeCurve, reduction: f32) -> f32 {
+    curve.fraction(linear * reduction / 100.0) * 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURVES: [VolumeCurve; 3] = [
+        VolumeCurve::Linear,
+        VolumeCurve::Logarithmic,
+        VolumeCurve::Cubic,
+    ];
+
+    #[test]
+    fn round_trip() {
+        for curve in CURVES {
+            for reduction in [75.0, 100.0] {
+                for percent in 0..=100 {
+                    let linear = percent_to_linear(percent as f32, curve, reduction);
+                    let back = linear_to_percent(linear, curve, reduction);
+                    assert!(
+                        (back - percent as f32).abs() < 1e-3,
+                        "{curve:?} {percent} {back}"
+                    );
+                }
+            }
+            assert_eq!(curve.gain(0.0), 0.0);
+            assert_eq!(curve.gain(1.0), 1.0);
+        }
+        assert_eq!(linear_to_percent(4.0, VolumeCurve::Cubic, 100.0), 100.0);
+    }
+
+    #[test]
+    fn curves() {
+        assert!((percent_to_linear(30.0, VolumeCurve::Linear, 75.0) - 0.4).abs() < 1e-6);
+        assert!((linear_to_db(VolumeCurve::Logarithmic.gain(0.5)) + 30.0).abs() < 1e-4);
+        assert!((VolumeCurve::Cubic.gain(0.5) - 0.125).abs() < 1e-6);
+        assert_eq!(linear_to_db(0.0), f32::NEG_INFINITY);
+        assert!((linear_to_db(db_to_linear(-12.5)) + 12.5).abs() < 1e-4);
+        // The perceptual curves keep the bottom of the range useful.
+        for curve in [VolumeCurve::Logarithmic, VolumeCurve::Cubic] {
+            assert!(curve.gain(0.25) < VolumeCurve::Linear.gain(0.25) / 4.0);
+        }
+    }
+}
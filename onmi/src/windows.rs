@@ -1,29 +1,16 @@
-pub use wasapi::IMMDevice;
-
-#[derive(Debug, Clone)]
-pub struct Device {
-    pub imm: IMMDevice,
-    pub name: String,
-}
-
-unsafe impl Send for Device {}
-unsafe impl Sync for Device {}
-
 use crate::*;
-use std::sync::atomic::Ordering::{AcqRel, Relaxed};
-use std::sync::{Arc, Once};
-use std::time::Duration;
+use std::sync::Once;
 use wasapi::*;
 
 static ONCE: Once = Once::new();
 
 const WAIT_MS: u32 = 50;
 
-pub struct OutputDevices {
+pub struct WasapiBackend {
     pub enumerator: IMMDeviceEnumerator,
 }
 
-impl OutputDevices {
+impl WasapiBackend {
     pub fn new() -> Self {
         unsafe { ONCE.call_once(|| CoInitializeEx(ConcurrencyModel::MultiThreaded).unwrap()) };
 
@@ -32,47 +19,122 @@ impl OutputDevices {
         }
     }
 
-    pub fn default_device(&self) -> Device {
+    fn device(imm: &IMMDevice) -> Option<Device> {
         unsafe {
-            let imm = self
-                .enumerator
-                .GetDefaultAudioEndpoint(DataFlow::Render, Role::Console)
-                .unwrap();
-
-            Device {
+            Some(Device {
+                id: imm.GetId().ok()?,
                 name: imm.name(),
-                imm,
-            }
+            })
         }
     }
 
-    pub fn devices(&self) -> Vec<Device> {
+    fn imm(&self, device: &Device) -> Option<IMMDevice> {
+        let id: Vec<u16> = device.id.encode_utf16().chain(Some(0)).collect();
+        unsafe { self.enumerator.GetDevice(id.as_ptr()).ok() }
+    }
+}
+
+impl AudioBackend for WasapiBackend {
+    fn name(&self) -> &'static str {
+        "WASAPI"
+    }
+
+    fn devices(&self) -> Vec<Device> {
         unsafe {
-            let collection = self
+            let Ok(collection) = self
                 .enumerator
                 .EnumAudioEndpoints(DataFlow::Render, DeviceState::Active)
-                .unwrap();
-
-            (0..collection.GetCount().unwrap())
-                .map(|i| {
-                    let imm = collection.Item(i).unwrap();
-                    Device {
-                        name: imm.name(),
-                        imm,
-                    }
-                })
+            else {
+                return Vec::new();
+            };
+
+            (0..collection.GetCount().unwrap_or(0))
+                .filter_map(|i| Self::device(&collection.Item(i).ok()?))
                 .collect()
         }
     }
 
-    pub fn find(&self, device: &str) -> Option<Device> {
-        self.devices()
-            .into_iter()
-            .find(|d| d.name.as_str() == device)
+    fn default_device(&self) -> Option<Device> {
+        unsafe {
+            let imm = self
+                .enumerator
+                .GetDefaultAudioEndpoint(DataFlow::Render, Role::Console)
+                .ok()?;
+            Self::device(&imm)
+        }
+    }
+
+    fn open(
+        &self,
+        device: &Device,
+        sample_rate: Option<u32>,
+        _: usize,
+    ) -> Option<Box<dyn OutputStream>> {
+        unsafe {
+            ONCE.call_once(|| {
+                let _ = CoInitializeEx(ConcurrencyModel::MultiThreaded);
+            });
+
+            let imm = self.imm(device)?;
+            let client: IAudioClient = imm.Activate(ExecutionContext::All).ok()?;
+            let mut format =
+                (client.GetMixFormat().ok()? as *const _ as *const WAVEFORMATEXTENSIBLE).read();
+
+            if format.Format.nChannels == 0 {
+                return None;
+            }
+
+            if let Some(sample_rate) = sample_rate {
+                if !COMMON_SAMPLE_RATES.contains(&sample_rate) {
+                    return None;
+                }
+                format.Format.nSamplesPerSec = sample_rate;
+                format.Format.nAvgBytesPerSec = sample_rate * format.Format.nBlockAlign as u32;
+            }
+
+            let (default, _) = client.GetDevicePeriod().ok()?;
+
+            client
+                .Initialize(
+                    ShareMode::Shared,
+                    AUDCLNT_STREAMFLAGS_EVENTCALLBACK
+                        | AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM
+                        | AUDCLNT_STREAMFLAGS_SRC_DEFAULT_QUALITY,
+                    default,
+                    0,
+                    &format as *const _ as *const WAVEFORMATEX,
+                    None,
+                )
+                .ok()?;
+
+            let event = CreateEventA(core::ptr::null_mut(), 0, 0, core::ptr::null_mut());
+            if event.is_null() {
+                return None;
+            }
+            if client.SetEventHandle(event as isize).is_err() {
+                CloseHandle(event);
+                return None;
+            }
+
+            let render: IAudioRenderClient = client.GetService().ok()?;
+            client.Start().ok()?;
+
+            Some(Box::new(WasapiOutput {
+                client,
+                render,
+                format,
+                event,
+                device: device.clone(),
+            }))
+        }
+    }
+
+    fn init_thread(&self) {
+        set_pro_audio_thread();
     }
 }
 
-pub struct Output {
+pub struct WasapiOutput {
     pub client: IAudioClient,
     pub render: IAudioRenderClient,
     pub format: WAVEFORMATEXTENSIBLE,
@@ -80,9 +142,9 @@ pub struct Output {
     pub device: Device,
 }
 
-unsafe impl Send for Output {}
+unsafe impl Send for WasapiOutput {}
 
-impl Drop for Output {
+impl Drop for WasapiOutput {
     fn drop(&mut self) {
         unsafe {
             let _ = self.client.Stop();
@@ -94,204 +156,82 @@ impl Drop for Output {
     }
 }
 
-pub fn try_new_output(device: Device, sample_rate: Option<u32>) -> Option<Output> {
-    unsafe {
-        ONCE.call_once(|| {
-            let _ = CoInitializeEx(ConcurrencyModel::MultiThreaded);
-        });
-
-        let client: IAudioClient = device.imm.Activate(ExecutionContext::All).ok()?;
-        let mut format =
-            (client.GetMixFormat().ok()? as *const _ as *const WAVEFORMATEXTENSIBLE).read();
-
-        if format.Format.nChannels == 0 {
-            return None;
-        }
+impl WasapiOutput {
+    pub fn fill_buffer(&self, renderer: &mut Renderer) -> u32 {
+        unsafe {
+            let padding = match self.client.GetCurrentPadding() {
+                Ok(p) => p,
+                Err(_) => {
+                    renderer.state.set_error(RuntimeError::StreamStart);
+                    return 0;
+                }
+            };
+            let buffer_size = match self.client.GetBufferSize() {
+                Ok(s) => s,
+                Err(_) => {
+                    renderer.state.set_error(RuntimeError::StreamStart);
+                    return 0;
+                }
+            };
+            let block_align = self.format.Format.nBlockAlign;
+            let frames = buffer_size - padding;
 
-        if let Some(sample_rate) = sample_rate {
-            if !COMMON_SAMPLE_RATES.contains(&sample_rate) {
-                return None;
+            if frames == 0 {
+                return frames;
             }
-            format.Format.nSamplesPerSec = sample_rate;
-            format.Format.nAvgBytesPerSec = sample_rate * format.Format.nBlockAlign as u32;
-        }
-
-        let (default, _) = client.GetDevicePeriod().ok()?;
-
-        client
-            .Initialize(
-                ShareMode::Shared,
-                AUDCLNT_STREAMFLAGS_EVENTCALLBACK
-                    | AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM
-                    | AUDCLNT_STREAMFLAGS_SRC_DEFAULT_QUALITY,
-                default,
-                0,
-                &format as *const _ as *const WAVEFORMATEX,
-                None,
-            )
-            .ok()?;
-
-        let event = CreateEventA(core::ptr::null_mut(), 0, 0, core::ptr::null_mut());
-        if event.is_null() {
-            return None;
-        }
-        if client.SetEventHandle(event as isize).is_err() {
-            CloseHandle(event);
-            return None;
-        }
-
-        let render: IAudioRenderClient = client.GetService().ok()?;
-        client.Start().ok()?;
-
-        Some(Output {
-            client,
-            render,
-            format,
-            event,
-            device,
-        })
-    }
-}
 
-pub fn new_output(device: Device, sample_rate: Option<u32>) -> Output {
-    try_new_output(device, sample_rate).expect("failed to open output")
-}
+            let size = (frames * block_align as u32) as usize;
+            let ptr = match self.render.GetBuffer(frames) {
+                Ok(p) => p,
+                Err(_) => {
+                    renderer.state.set_error(RuntimeError::StreamStart);
+                    return 0;
+                }
+            };
+            let buffer = std::slice::from_raw_parts_mut(ptr, size);
+            let channels = self.format.Format.nChannels as usize;
 
-pub fn fill_buffer(
-    state: &PlayerState,
-    output: &Output,
-    decoder: &mut Option<Symphonia>,
-) -> u32 {
-    unsafe {
-        let padding = match output.client.GetCurrentPadding() {
-            Ok(p) => p,
-            Err(_) => {
-                state.set_error(RuntimeError::StreamStart);
-                return 0;
-            }
-        };
-        let buffer_size = match output.client.GetBufferSize() {
-            Ok(s) => s,
-            Err(_) => {
-                state.set_error(RuntimeError::StreamStart);
-                return 0;
-            }
-        };
-        let block_align = output.format.Format.nBlockAlign;
-        let frames = buffer_size - padding;
+            renderer.fill(buffer, channels);
 
-        if frames == 0 {
-            return frames;
+            let _ = self.render.ReleaseBuffer(frames, 0);
+            frames
         }
-
-        let size = (frames * block_align as u32) as usize;
-        let ptr = match output.render.GetBuffer(frames) {
-            Ok(p) => p,
-            Err(_) => {
-                state.set_error(RuntimeError::StreamStart);
-                return 0;
-            }
-        };
-        let buffer = std::slice::from_raw_parts_mut(ptr, size);
-        let channels = output.format.Format.nChannels as usize;
-
-        fill_f32_le(state, decoder, buffer, channels);
-
-        let _ = output.render.ReleaseBuffer(frames, 0);
-        frames
     }
 }
 
-fn same_device(a: &Device, b: &Device) -> bool {
-    a.name == b.name
-}
-
-pub fn run_output(state: Arc<PlayerState>, output: Output) {
-    unsafe {
-        set_pro_audio_thread();
-
-        let mut output = Some(output);
-        let mut decoder: Option<Symphonia> = None;
-        let devices = OutputDevices::new();
-
-        loop {
-            if state.shutdown.load(Relaxed) {
-                break;
-            }
-
-            if let Some(new_output) = state.pending_output.take() {
-                if let Some(old) = output.take() {
-                    let _ = old.client.Stop();
-                    drop(old);
-                }
-                output = Some(new_output);
-            }
-
-            if state.follow_default.load(Relaxed) {
-                if let Some(current) = output.as_ref() {
-                    let def = devices.default_device();
-                    if !same_device(&current.device, &def) {
-                        let rate = current.format.Format.nSamplesPerSec;
-                        if let Some(new_output) = try_new_output(def, Some(rate)) {
-                            if let Some(old) = output.take() {
-                                let _ = old.client.Stop();
-                                drop(old);
-                            }
-                            output = Some(new_output);
-                        } else {
-                            state.set_error(RuntimeError::OutputOpen);
-                        }
-                    }
-                }
-            }
+impl OutputStream for WasapiOutput {
+    fn device(&self) -> &Device {
+        &self.device
+    }
 
-            if let Some(new_decoder) = state.pending_decoder.take() {
-                state.finished.store(false, Relaxed);
-                if let Some(out) = output.as_ref() {
-                    let _ = out.client.Stop();
-                    let _ = out.client.Reset();
-                    let _ = out.client.Start();
-                }
-                decoder = Some(new_decoder);
-                state.decoder_pending.store(false, Relaxed);
-            }
+    fn sample_rate(&self) -> u32 {
+        self.format.Format.nSamplesPerSec
+    }
 
-            let Some(out) = output.as_mut() else {
-                std::thread::sleep(Duration::from_millis(WAIT_MS as u64));
-                continue;
-            };
+    fn channels(&self) -> usize {
+        self.format.Format.nChannels as usize
+    }
 
-            let seek = state.seek.swap(u64::MAX, AcqRel);
-            if seek != u64::MAX {
-                if let Some(decoder) = decoder.as_mut() {
-                    decoder.seek(Duration::from_nanos(seek), &state);
-                }
+    fn render(&mut self, renderer: &mut Renderer) {
+        unsafe {
+            if renderer.update() {
+                let _ = self.client.Stop();
+                let _ = self.client.Reset();
+                let _ = self.client.Start();
             }
 
-            WaitForSingleObject(out.event, WAIT_MS);
-
-            if state.shutdown.load(Relaxed) {
-                break;
-            }
+            WaitForSingleObject(self.event, WAIT_MS);
 
             let mut frames = u32::MAX;
-
-            while frames != 0 {
-                if state.state.load(Relaxed) != State::Playing as u8
-                    || state.finished.load(Relaxed)
-                    || state.decoder_pending.load(Relaxed)
-                    || state.shutdown.load(Relaxed)
-                {
-                    break;
-                }
-
-                frames = fill_buffer(&state, out, &mut decoder);
+            while frames != 0 && renderer.is_active() {
+                frames = self.fill_buffer(renderer);
             }
         }
+    }
 
-        if let Some(out) = output.take() {
-            let _ = out.client.Stop();
-            drop(out);
-        }
+    /// Only what is queued in the endpoint buffer, not the device's own delay.
+    fn latency(&self) -> std::time::Duration {
+        let padding = unsafe { self.client.GetCurrentPadding() }.unwrap_or(0);
+        std::time::Duration::from_secs_f64(padding as f64 / self.sample_rate() as f64)
     }
 }
@@ -0,0 +1,100 @@
+use onmi::*;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub const RATE: u32 = 44100;
+pub const FRAMES: usize = RATE as usize;
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A player on the null backend that renders into memory one step of the clock at a time.
+/// Shuts the player down and removes its files when dropped.
+pub struct Fixture {
+    pub player: Player,
+    pub clock: Arc<NullClock>,
+    pub memory: Arc<Mutex<Vec<f32>>>,
+    /// Frames the limiter holds the output back by, whether it's on or not.
+    pub latency: usize,
+    files: Vec<PathBuf>,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        Self::at(RATE)
+    }
+
+    /// Opens the output at `sample_rate`.
+    pub fn at(sample_rate: u32) -> Self {
+        let clock = NullClock::manual();
+        let memory = Arc::new(Mutex::new(Vec::new()));
+        let backend = NullBackend::new(
+            sample_rate,
+            2,
+            Arc::clone(&clock),
+            NullSink::Memory(Arc::clone(&memory)),
+        );
+        let player = Player::with_backend(Arc::new(backend), NullBackend::device());
+        player.set_volume_reduction(100.0);
+        player.set_volume(100);
+        // Output is compared sample for sample, fades have their own test.
+        player.set_fade_duration(Duration::ZERO);
+        Self {
+            player,
+            clock,
+            memory,
+            latency: Limiter::new(sample_rate, 2).latency(),
+            files: Vec::new(),
+        }
+    }
+
+    /// A path in the temp directory that is removed along with the fixture.
+    pub fn file(&mut self, name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("onmi_{}_{name}", std::process::id()));
+        self.files.push(path.clone());
+        path
+    }
+
+    /// One second of stereo where the left channel is a ramp and the right channel is its negation,
+    /// so every frame can be traced back to its position in the file.
+    pub fn ramp(&mut self, name: &str) -> (PathBuf, Vec<f32>) {
+        self.ramp_at(name, RATE)
+    }
+
+    pub fn ramp_at(&mut self, name: &str, sample_rate: u32) -> (PathBuf, Vec<f32>) {
+        let path = self.file(&format!("{name}.wav"));
+        let frames = sample_rate as usize;
+        let samples: Vec<f32> = (0..frames)
+            .flat_map(|i| {
+                let x = i as f32 / frames as f32;
+                [x, -x]
+            })
+            .collect();
+        let mut wav = WavWriter::create(&path, sample_rate, 2).unwrap();
+        wav.write_samples(&samples).unwrap();
+        drop(wav);
+        (path, samples)
+    }
+
+    /// Renders the silence the limiter puts in front of a song or a seek,
+    /// so what is rendered next lines up with the input.
+    pub fn skip_latency(&self) {
+        assert!(self.render(self.latency).iter().all(|s| *s == 0.0));
+    }
+
+    /// Advances the clock by `frames` and returns what was rendered.
+    pub fn render(&self, frames: usize) -> Vec<f32> {
+        self.memory.lock().unwrap().clear();
+        self.clock.advance(frames as u64);
+        assert!(self.clock.wait(TIMEOUT));
+        std::mem::take(&mut *self.memory.lock().unwrap())
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        self.player.shutdown();
+        for path in &self.files {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
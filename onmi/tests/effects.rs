@@ -0,0 +1,255 @@
+mod common;
+
+use common::*;
+use onmi::*;
+use std::f32::consts::TAU;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[test]
+fn equalizer() {
+    let mut f = Fixture::new();
+    let (path, input) = f.ramp("equalizer");
+
+    f.player.play_song(&path, Some(1.0), true).unwrap();
+    f.skip_latency();
+    f.render(1000);
+    f.player.set_equalizer(EqPreset {
+        preamp: -6.0206,
+        bands: Vec::new(),
+    });
+    // Glides down over the first few milliseconds, then stays at half.
+    let output = f.render(4000);
+    assert!(output[2] > 0.9 * input[2002]);
+    for (out, input) in output[4000..].iter().zip(&input[6000..]) {
+        assert!((out - input * 0.5).abs() < 1e-4, "{out} {input}");
+    }
+}
+
+#[test]
+fn dsp_chain() {
+    /// Flips the signal and reports what it was prepared for and how often it was reset.
+    struct Invert(Arc<Mutex<(u32, usize, usize)>>);
+
+    impl Processor for Invert {
+        fn prepare(&mut self, sample_rate: u32, channels: usize) {
+            let mut seen = self.0.lock().unwrap();
+            (seen.0, seen.1) = (sample_rate, channels);
+        }
+
+        fn process(&mut self, samples: &mut [f32]) {
+            samples.iter_mut().for_each(|s| *s = -*s);
+        }
+
+        fn reset(&mut self) {
+            self.0.lock().unwrap().2 += 1;
+        }
+    }
+
+    let mut f = Fixture::new();
+    let (path, input) = f.ramp("dsp_chain");
+    let seen = Arc::new(Mutex::new((0, 0, 0)));
+
+    f.player.play_song(&path, Some(1.0), true).unwrap();
+    f.skip_latency();
+    f.render(1000);
+    f.player
+        .set_dsp_chain(DspChain::new().with(Invert(Arc::clone(&seen))));
+    // Prepared by the player, not the output.
+    assert_eq!(*seen.lock().unwrap(), (RATE, 2, 0));
+    // What the limiter's look-ahead holds went through before the change.
+    let delay = f.latency * 2;
+    let output = f.render(1000);
+    assert_eq!(output[..delay], input[2000..2000 + delay]);
+    let expected: Vec<f32> = input[2000 + delay..4000].iter().map(|s| -s).collect();
+    assert_eq!(output[delay..], expected);
+
+    f.player.seek_to(Duration::from_millis(500));
+    f.skip_latency();
+    f.render(1);
+    assert_eq!(seen.lock().unwrap().2, 1);
+
+    f.player.set_dsp_chain(DspChain::new());
+    let output = f.render(f.latency + 1);
+    assert!(output[delay] > 0.0);
+}
+
+#[test]
+fn fades() {
+    let mut f = Fixture::new();
+    let (path, input) = f.ramp("fades");
+    let left = |samples: &[f32]| samples.iter().step_by(2).copied().collect::<Vec<f32>>();
+    let input = left(&input);
+    f.player.set_fade_duration(Duration::from_millis(10));
+    let fade = FRAMES / 100;
+
+    // Fades in from silence, the start of it over the limiter's look-ahead, then plays untouched.
+    f.player.play_song(&path, Some(1.0), true).unwrap();
+    f.skip_latency();
+    let output = left(&f.render(1000));
+    let half = fade / 2 - f.latency;
+    assert!((output[half] / input[half] - 0.5).abs() < 0.01);
+    assert_eq!(output[fade..], input[fade..1000]);
+
+    // Pausing fades out and only then goes silent.
+    f.player.pause();
+    let output = left(&f.render(1000));
+    assert!(output[0] > 0.99 * input[1000]);
+    assert!(output[..fade].windows(2).all(|w| w[1] <= w[0]));
+    assert!(output[fade..].iter().all(|s| *s == 0.0));
+
+    // Resuming carries on from where the fade out stopped.
+    f.player.play();
+    let output = left(&f.render(1000));
+    let resumed = 1000 + fade;
+    assert!(output[0] < 0.01 * input[resumed]);
+    assert_eq!(output[fade..], input[resumed + fade..resumed + 1000]);
+
+    // Volume changes ramp to the new level, heard once through the look-ahead.
+    f.player.set_volume(50);
+    let output = left(&f.render(1000));
+    assert!(output[0] > 0.99 * input[resumed + 1000]);
+    let ramped = fade + f.latency;
+    let expected: Vec<f32> = input[resumed + 1000 + ramped..resumed + 2000]
+        .iter()
+        .map(|s| s * 0.5)
+        .collect();
+    assert_eq!(output[ramped..], expected);
+
+    // Seeking fades out what was playing, then fades in at the new position.
+    f.player.seek_to(Duration::from_millis(500));
+    let output = left(&f.render(1000));
+    assert!(output[0] > 0.99 * 0.5 * input[resumed + 2000 - f.latency]);
+    assert_eq!(output[fade], 0.0);
+    let silence = output.iter().rposition(|s| *s == 0.0).unwrap();
+    assert!(output[silence + 1] < 0.25 * 2.0 * f.latency as f32 / fade as f32);
+    assert!((output[silence + 1 + fade - f.latency] - 0.25).abs() < 0.01);
+
+    // Stopping fades out as well.
+    f.player.stop();
+    let output = left(&f.render(1000));
+    assert!(output[0] > 0.2);
+    assert!(output[fade..].iter().all(|s| *s == 0.0));
+}
+
+#[test]
+fn volume_and_mute() {
+    let mut f = Fixture::new();
+    let (path, input) = f.ramp("volume");
+    let close = |output: &[f32], input: &[f32], gain: f32| {
+        output
+            .iter()
+            .zip(input)
+            .all(|(o, i)| (o - i * gain).abs() < 1e-6)
+    };
+
+    f.player.set_volume_db(-6.0206);
+    f.player.play_song(&path, Some(1.0), true).unwrap();
+    f.skip_latency();
+    let output = f.render(1000);
+    assert!(close(&output, &input[..2000], 0.5));
+    assert_eq!(f.player.volume(), 50);
+
+    // Same level, reported further up the slider.
+    f.player.set_volume_curve(VolumeCurve::Cubic);
+    assert_eq!(f.player.volume(), 79);
+
+    // What the limiter's look-ahead holds is heard before each change.
+    let delay = f.latency * 2;
+    assert!(f.player.toggle_mute());
+    let output = f.render(1000);
+    assert!(close(&output[..delay], &input[2000..], 0.5));
+    assert!(output[delay..].iter().all(|s| *s == 0.0));
+    assert!((f.player.volume_db() + 6.0206).abs() < 1e-3);
+
+    // Changes while muted are heard once unmuted.
+    f.player.set_volume(100);
+    let output = f.render(1000);
+    assert!(output.iter().all(|s| *s == 0.0));
+    assert!(!f.player.toggle_mute());
+    let output = f.render(1000);
+    assert!(output[..delay].iter().all(|s| *s == 0.0));
+    assert_eq!(output[delay..], input[6000 + delay..8000]);
+}
+
+#[test]
+fn limiter() {
+    let mut f = Fixture::new();
+    let (path, _) = f.ramp("limiter");
+
+    // Halfway through the ramp is about 2.0 at +12dB.
+    f.player.play_song(&path, Some(1.0), true).unwrap();
+    f.skip_latency();
+    f.player.set_volume_db(MAX_VOLUME_DB);
+    f.player.seek_to(Duration::from_millis(500));
+    f.skip_latency();
+    let output = f.render(1000);
+    assert!(output.iter().any(|s| s.abs() > 1.0));
+    let (limited, clipped) = f.player.limited_samples();
+    assert_eq!(limited, 0);
+    assert!(clipped > 0);
+
+    f.player.set_limiter(true);
+    f.player.set_limiter_ceiling(-3.0);
+    // Back at the ceiling soon after the crossfade from turning it on.
+    f.player.set_limiter_release(Duration::from_millis(10));
+    let on = f.render(2000);
+    let ceiling = db_to_linear(-3.0);
+    assert!(on[1000..].iter().all(|s| s.abs() <= ceiling));
+    assert!(on[1000..].iter().any(|s| s.abs() > 0.99 * ceiling));
+    let (limited, clipped_after) = f.player.limited_samples();
+    assert!(limited > 0);
+    // Only during the crossfade, before the limited output is all that's heard.
+    assert!(clipped_after - clipped <= 2 * FRAMES as u64 / 100);
+    assert!(f.player.elapsed() < f.player.state.rendered());
+
+    f.player.set_limiter(false);
+    let off = f.render(1000);
+    assert!(off[900..].iter().any(|s| s.abs() > 1.0));
+
+    // Neither toggle drops audio or adds silence, the ramp stays smooth across both.
+    let left: Vec<f32> = [&output[..], &on, &off]
+        .concat()
+        .into_iter()
+        .step_by(2)
+        .collect();
+    let largest = left
+        .windows(2)
+        .map(|w| (w[1] - w[0]).abs())
+        .fold(0f32, f32::max);
+    assert!(largest < 0.01, "{largest}");
+
+    // Under a millisecond would round down to nothing.
+    f.player.set_limiter_release(Duration::from_micros(10));
+    assert_eq!(f.player.state.limiter_release_ms.load(Relaxed), 1);
+    f.player.set_limiter_release(Duration::from_secs(60));
+    assert_eq!(f.player.state.limiter_release_ms.load(Relaxed), 5000);
+}
+
+#[test]
+fn limiter_toggle() {
+    let mut f = Fixture::new();
+    let path = f.file("limiter_toggle.wav");
+    // Well under the ceiling, so the limited side is just the delayed input as well.
+    let input: Vec<f32> = (0..FRAMES)
+        .flat_map(|i| {
+            let s = 0.5 * (i as f32 * 1000.0 * TAU / RATE as f32).sin();
+            [s, -s]
+        })
+        .collect();
+    let mut wav = WavWriter::create(&path, RATE, 2).unwrap();
+    wav.write_samples(&input).unwrap();
+    drop(wav);
+
+    // Both sides of each crossfade are the same frame, so toggling leaves no trace.
+    f.player.play_song(&path, Some(1.0), true).unwrap();
+    f.skip_latency();
+    let mut output = Vec::new();
+    for enabled in [true, false, true, false] {
+        f.player.set_limiter(enabled);
+        output.extend(f.render(1000));
+    }
+    assert_eq!(output, input[..output.len()]);
+    assert_eq!(f.player.limited_samples().0, 0);
+}
@@ -0,0 +1,270 @@
+mod common;
+
+use common::*;
+use onmi::*;
+use std::sync::Arc;
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::Duration;
+
+#[test]
+fn golden_output() {
+    let mut f = Fixture::new();
+    let (path, input) = f.ramp("golden");
+
+    f.player.play_song(&path, Some(1.0), true).unwrap();
+    f.skip_latency();
+    let output = f.render(1000);
+    assert_eq!(output, input[..2000]);
+
+    f.player.pause();
+    let output = f.render(500);
+    assert!(output.iter().all(|s| *s == 0.0));
+
+    // The limiter's look-ahead held on to the frames before the volume change.
+    f.player.play();
+    f.player.set_volume(50);
+    let delay = f.latency * 2;
+    let output = f.render(1000);
+    assert_eq!(output[..delay], input[2000..2000 + delay]);
+    let expected: Vec<f32> = input[2000 + delay..4000].iter().map(|s| s * 0.5).collect();
+    assert_eq!(output[delay..], expected);
+
+    f.player.seek_to(Duration::from_millis(500));
+    f.skip_latency();
+    let output = f.render(1);
+    // Volume is still at half.
+    let frame = (output[0] * 2.0 * FRAMES as f32).round() as i64;
+    assert!(
+        (frame - FRAMES as i64 / 2).abs() < 4096,
+        "landed on {frame}"
+    );
+
+    let output = f.render(FRAMES);
+    assert!(f.player.is_finished());
+    assert_eq!(f.player.state(), State::Stopped);
+    assert_eq!(*output.last().unwrap(), 0.0);
+}
+
+#[test]
+fn play_from_memory() {
+    let mut f = Fixture::new();
+    let (path, input) = f.ramp("memory");
+    let bytes: Arc<[u8]> = std::fs::read(&path).unwrap().into();
+
+    let source = Box::new(std::io::Cursor::new(Arc::clone(&bytes)));
+    f.player
+        .play_source(source, Some("audio/wav"), Some(1.0), true)
+        .unwrap();
+    f.skip_latency();
+    assert_eq!(f.render(1000), input[..2000]);
+
+    let decoder = Symphonia::from_bytes(bytes.to_vec(), Some("wav")).unwrap();
+    assert_eq!(decoder.path, None);
+    assert_eq!(decoder.sample_rate, RATE);
+}
+
+#[test]
+fn untagged_song_uses_fallback_gain() {
+    let mut f = Fixture::new();
+    let (path, input) = f.ramp("fallback_gain");
+
+    f.player.set_fallback_gain(0.25);
+    f.player.play_song(&path, None, true).unwrap();
+    f.skip_latency();
+    let output = f.render(1000);
+    let expected: Vec<f32> = input[..2000].iter().map(|s| s * 0.25).collect();
+    assert_eq!(output, expected);
+
+    // Off ignores the fallback as well.
+    f.player.set_replay_gain_mode(ReplayGainMode::Off);
+    f.player.play_song(&path, None, true).unwrap();
+    f.skip_latency();
+    assert_eq!(f.render(1000), input[..2000]);
+}
+
+#[test]
+fn replay_gain_preamp_range() {
+    let f = Fixture::new();
+    let preamp = || f32::from_bits(f.player.state.replay_gain_preamp.load(Relaxed));
+
+    f.player.set_replay_gain_preamp(6.0);
+    assert_eq!(preamp(), 6.0);
+    // Not a gain at all, the last one stays.
+    f.player.set_replay_gain_preamp(f32::NAN);
+    f.player.set_replay_gain_preamp(f32::INFINITY);
+    assert_eq!(preamp(), 6.0);
+
+    f.player.set_replay_gain_preamp(100.0);
+    assert_eq!(preamp(), MAX_REPLAY_GAIN_PREAMP);
+    f.player.set_replay_gain_preamp(f32::MIN);
+    assert_eq!(preamp(), -MAX_REPLAY_GAIN_PREAMP);
+}
+
+#[test]
+fn probe_fallback() {
+    let mut f = Fixture::new();
+    let (path, _) = f.ramp("fallback");
+    let misnamed = f.file("fallback.mp3");
+    std::fs::rename(&path, &misnamed).unwrap();
+
+    // The extension is wrong, probing without it still finds the WAV.
+    let decoder = Symphonia::open(&misnamed, Some("audio/mpeg")).unwrap();
+    assert_eq!(decoder.container, "wav");
+    assert_eq!(decoder.codec, "pcm_f32le");
+    assert_eq!(decoder.path.as_deref(), Some(misnamed.as_path()));
+}
+
+#[test]
+fn accurate_seek() {
+    let mut f = Fixture::new();
+    let (path, input) = f.ramp("accurate_seek");
+    let events = f.player.subscribe();
+    f.player.set_accurate_seek(true);
+
+    f.player.play_song(&path, Some(1.0), true).unwrap();
+    f.skip_latency();
+    f.render(1000);
+    for ms in [900, 123, 500] {
+        f.player.seek_to(Duration::from_millis(ms));
+        f.skip_latency();
+        let output = f.render(2);
+        let frame = FRAMES * ms as usize / 1000;
+        assert_eq!(output, input[frame * 2..frame * 2 + 4]);
+    }
+
+    let seeks: Vec<Event> = events
+        .try_iter()
+        .filter(|e| matches!(e, Event::Seeked(_)))
+        .collect();
+    // 123ms is between frames, it reports the one it landed on.
+    let landed = |frame: usize| Duration::from_secs_f64(frame as f64 / RATE as f64);
+    assert_eq!(
+        seeks,
+        [39690, 5424, 22050].map(|frame| Event::Seeked(landed(frame)))
+    );
+}
+
+#[test]
+fn position() {
+    let mut f = Fixture::new();
+    let (path, _) = f.ramp("position");
+    f.player.set_accurate_seek(true);
+
+    f.player.play_song(&path, Some(1.0), true).unwrap();
+    f.skip_latency();
+    f.render(1000);
+    // Read into the limiter's look-ahead, but not heard yet.
+    let held = f.latency as u64;
+    assert_eq!(f.player.position_frames(), 1000 + held);
+    let heard = Duration::from_secs_f64(1000.0 / RATE as f64);
+    assert!(f.player.elapsed().abs_diff(heard) < Duration::from_micros(1));
+
+    f.player.pause();
+    f.render(500);
+    assert_eq!(f.player.position_frames(), 1000 + held);
+
+    f.player.play();
+    f.player.seek_to(Duration::from_millis(500));
+    f.skip_latency();
+    f.render(10);
+    assert_eq!(f.player.position_frames(), FRAMES as u64 / 2 + held + 10);
+
+    // Audio still in the device isn't heard yet, unless paused and drained.
+    let rendered = f.player.elapsed();
+    f.player.state.latency.store(10_000_000, Relaxed);
+    assert_eq!(f.player.elapsed(), rendered - Duration::from_millis(10));
+    f.player.pause();
+    assert_eq!(f.player.elapsed(), rendered);
+}
+
+#[test]
+fn events() {
+    let mut f = Fixture::new();
+    let (path, _) = f.ramp("events");
+    let events = f.player.subscribe();
+    // Coarse seeks report the start of the packet they landed in.
+    f.player.set_accurate_seek(true);
+
+    f.player.play_song(&path, Some(1.0), true).unwrap();
+    f.skip_latency();
+    f.render(1000);
+    f.player.set_volume(50);
+    f.player.seek_to(Duration::from_millis(900));
+    f.skip_latency();
+    f.render(FRAMES / 5);
+    assert!(f.player.is_finished());
+
+    let mut received = Vec::new();
+    while !received.contains(&Event::StateChanged(State::Stopped)) {
+        received.push(events.recv_timeout(TIMEOUT).unwrap());
+    }
+    // The fixture's volume change may or may not make it in before subscribing.
+    received.retain(|e| !matches!(e, Event::Position(_) | Event::VolumeChanged(100)));
+    // The song starts on the decoder thread, playback was already set to playing.
+    assert!(received[..2].contains(&Event::StateChanged(State::Playing)));
+    assert!(received[..2].contains(&Event::TrackStarted {
+        duration: Duration::from_secs(1)
+    }));
+    assert_eq!(
+        received[2..],
+        [
+            Event::VolumeChanged(50),
+            Event::Seeked(Duration::from_millis(900)),
+            Event::TrackFinished,
+            Event::StateChanged(State::Stopped),
+        ]
+    );
+    assert_eq!(f.player.last_error(), RuntimeError::None);
+}
+
+#[test]
+fn reopens_at_song_rate() {
+    let mut f = Fixture::at(48000);
+    let (path, _) = f.ramp("rate");
+
+    f.player.play_song(&path, None, true).unwrap();
+    assert_eq!(f.player.current_song_sample_rate(), Some(RATE));
+    f.render(10);
+    // The backend's own rate is 48kHz, rendering means the output moved to the song's.
+    assert_eq!(f.player.output_sample_rate(), Some(RATE));
+    assert_eq!(f.player.last_error(), RuntimeError::None);
+}
+
+#[test]
+fn resamples_unsupported_rate() {
+    let mut f = Fixture::new();
+    let (path, _) = f.ramp_at("resample", 12345);
+
+    f.player.play_song(&path, Some(1.0), true).unwrap();
+    f.skip_latency();
+    let output = f.render(FRAMES / 2);
+    assert_eq!(f.player.current_song_sample_rate(), Some(12345));
+    assert_eq!(f.player.output_sample_rate(), Some(RATE));
+    assert_eq!(f.player.last_error(), RuntimeError::None);
+
+    // The ramp plays at the same speed, half a second in it's half way up.
+    for frame in (0..FRAMES / 2).step_by(997) {
+        let expected = frame as f32 / FRAMES as f32;
+        assert!(
+            (output[frame * 2] - expected).abs() < 1e-3,
+            "frame {frame}: {} != {expected}",
+            output[frame * 2]
+        );
+        assert_eq!(output[frame * 2 + 1], -output[frame * 2]);
+    }
+    // Counted in frames of the song as they come out, not as they're read,
+    // along with what the limiter's look-ahead holds.
+    let held = (f.latency * 12345 / FRAMES) as u64;
+    let position = f.player.state.position.load(Relaxed);
+    assert!(position.abs_diff(12345 / 2 + held) <= 1, "{position}");
+
+    // The end of the song comes out of the filter before it finishes.
+    let output = f.render(FRAMES / 2 + 100);
+    let end = FRAMES / 2;
+    let expected = (FRAMES - 200) as f32 / FRAMES as f32;
+    assert!((output[(end - 200) * 2] - expected).abs() < 1e-3);
+    // Only the filter running into silence past the last frame rounds it off.
+    assert!(output[(end - 20) * 2] > 0.5);
+    assert!(output[(end + 50) * 2..].iter().all(|s| *s == 0.0));
+    assert!(f.player.is_finished());
+}
@@ -0,0 +1,155 @@
+mod common;
+
+use common::*;
+use onmi::*;
+use std::time::Duration;
+
+#[test]
+fn gapless() {
+    let mut f = Fixture::new();
+    let (first, input) = f.ramp("gapless_first");
+    let (second, _) = f.ramp("gapless_second");
+
+    f.player.play_song(&first, Some(1.0), true).unwrap();
+    f.skip_latency();
+    f.player.queue_next(&second, Some(1.0)).unwrap();
+    assert!(f.player.is_next_queued());
+
+    let output = f.render(FRAMES * 2 + 100);
+    assert!(!f.player.is_next_queued());
+    assert_eq!(output[..FRAMES * 2], input);
+    assert_eq!(output[FRAMES * 2..FRAMES * 4], input);
+    assert!(output[FRAMES * 4..].iter().all(|s| *s == 0.0));
+    assert!(f.player.is_finished());
+    assert_eq!(f.player.underruns(), (0, 0));
+}
+
+#[test]
+fn queued_after_decoding_ended() {
+    let mut f = Fixture::new();
+    let (first, input) = f.ramp("late_first");
+    let (second, _) = f.ramp("late_second");
+
+    f.player.play_song(&first, Some(1.0), true).unwrap();
+    f.skip_latency();
+    f.render(FRAMES - 1000);
+    // The rest of the song is buffered, the decoder has nothing left to do.
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(!f.player.is_finished());
+
+    f.player.queue_next(&second, Some(1.0)).unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    let output = f.render(1000 + FRAMES + 100);
+    assert_eq!(output[..2000], input[(FRAMES - 1000) * 2..]);
+    assert_eq!(output[2000..2000 + FRAMES * 2], input);
+    assert!(f.player.is_finished());
+    assert!(!f.player.is_next_queued());
+}
+
+#[test]
+fn crossfade() {
+    let mut f = Fixture::new();
+    let (first, input) = f.ramp("crossfade_first");
+    let (second, _) = f.ramp("crossfade_second");
+    f.player
+        .set_crossfade(Duration::from_millis(100), FadeCurve::Linear);
+
+    f.player.play_song(&first, Some(1.0), true).unwrap();
+    f.skip_latency();
+    f.player.queue_next(&second, Some(1.0)).unwrap();
+    let output = f.render(FRAMES * 2);
+    assert!(f.player.is_finished());
+
+    // The fade starts on a decode chunk, at most 100ms from the end.
+    let end = output.iter().rposition(|s| *s != 0.0).unwrap() / 2 + 1;
+    let frames = FRAMES * 2 - end;
+    assert!(frames <= FRAMES / 10 && frames > FRAMES / 20, "{frames}");
+
+    let start = FRAMES - frames;
+    assert_eq!(output[..start * 2], input[..start * 2]);
+    for i in 0..frames {
+        let t = i as f32 / frames as f32;
+        for c in 0..2 {
+            let expected = input[(start + i) * 2 + c] * (1.0 - t) + input[i * 2 + c] * t;
+            assert!((output[(start + i) * 2 + c] - expected).abs() < 1e-5);
+        }
+    }
+    assert_eq!(output[start * 2 + frames * 2..end * 2], input[frames * 2..]);
+}
+
+#[test]
+fn queue_advances() {
+    let mut f = Fixture::new();
+    let (first, input) = f.ramp("queue_first");
+    let (second, _) = f.ramp("queue_second");
+
+    for path in [&first, &second] {
+        let mut song = Song::new();
+        song.path = path.to_string_lossy().into_owned();
+        song.gain = 1.0;
+        f.player.queue_append(song);
+    }
+    f.player.jump(0).unwrap();
+    f.skip_latency();
+    assert_eq!(f.player.queue_index(), Some(0));
+
+    let output = f.render(FRAMES + 10);
+    assert_eq!(f.player.queue_index(), Some(1));
+    assert_eq!(output[..FRAMES * 2], input);
+    assert_eq!(output[FRAMES * 2..], input[..20]);
+
+    f.player.set_repeat(Repeat::One);
+    let output = f.render(FRAMES);
+    assert_eq!(f.player.queue_index(), Some(1));
+    assert_eq!(output[..FRAMES * 2 - 20], input[20..]);
+    assert_eq!(output[FRAMES * 2 - 20..], input[..20]);
+
+    f.player.skip_next().unwrap();
+    assert_eq!(f.player.state(), State::Stopped);
+    f.player.set_repeat(Repeat::All);
+    f.player.skip_next().unwrap();
+    assert_eq!(f.player.queue_index(), Some(0));
+}
+
+#[test]
+fn queued_song_reopens_at_its_rate() {
+    let mut f = Fixture::new();
+    let (first, input) = f.ramp("queued_first");
+    let (second, next) = f.ramp_at("queued_second", 48000);
+
+    f.player.play_song(&first, Some(1.0), true).unwrap();
+    f.skip_latency();
+    f.player.queue_next(&second, Some(1.0)).unwrap();
+
+    let output = f.render(FRAMES + 1000);
+    assert_eq!(output[..FRAMES * 2], input);
+    assert_eq!(f.player.current_song_sample_rate(), Some(48000));
+
+    // The rest of the last period is silent, the next song starts in the reopened output.
+    let rest: Vec<f32> = output[FRAMES * 2..]
+        .iter()
+        .copied()
+        .skip_while(|s| *s == 0.0)
+        .collect();
+    assert!(rest.len() > 2 * 400);
+    assert_eq!(rest[..2 * 400], next[2..2 * 401]);
+}
+
+#[test]
+fn seek_before_boundary() {
+    let mut f = Fixture::new();
+    let (first, input) = f.ramp("boundary_first");
+    let (second, _) = f.ramp("boundary_second");
+
+    f.player.play_song(&first, Some(1.0), true).unwrap();
+    f.skip_latency();
+    f.player.queue_next(&second, Some(1.0)).unwrap();
+
+    // Close enough to the end that the next song is already being decoded.
+    f.render(FRAMES - 1000);
+    f.player.seek_to(Duration::ZERO);
+    f.skip_latency();
+    let output = f.render(1000);
+    assert_eq!(output, input[..2000]);
+    assert!(f.player.is_next_queued());
+}
@@ -0,0 +1,14 @@
+use pulseaudio::*;
+
+fn main() {
+    let context = Context::connect("list_sinks").expect("Failed to connect to the server");
+
+    println!("PulseAudio Sinks:\n-----------------");
+    for sink in context.sinks().expect("Failed to list sinks") {
+        println!("- #{} {}", sink.index, sink.name);
+        println!("  {}", sink.description.unwrap_or_default());
+        println!("  {} Hz, {} channels", sink.sample_rate, sink.channels);
+    }
+
+    println!("\nDefault Sink: {:?}", context.default_sink());
+}
@@ -0,0 +1,31 @@
+use pulseaudio::*;
+use std::time::Duration;
+
+// Try `pactl load-module module-null-sink sink_name=test` and
+// `pactl set-default-sink test` while this is running.
+fn main() {
+    let context = Context::connect("watch_default").expect("Failed to connect to the server");
+    context.subscribe().expect("Failed to subscribe");
+
+    let mut default = context.default_sink().unwrap_or_default();
+    println!("Default Sink: {default:?}");
+
+    loop {
+        if context.poll_changes().unwrap() {
+            let sinks: Vec<String> = context
+                .sinks()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|s| s.name)
+                .collect();
+            println!("Sinks: {sinks:?}");
+
+            let current = context.default_sink().unwrap_or_default();
+            if current != default {
+                println!("Default Sink: {current:?}");
+                default = current;
+            }
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,199 @@
+use crate::error::{PulseError, Result};
+use crate::ffi::*;
+use std::ffi::{CStr, CString, c_char, c_int, c_void};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SinkInfo {
+    pub name: String,
+    pub index: u32,
+    pub description: Option<String>,
+    pub sample_rate: u32,
+    pub channels: u8,
+}
+
+/// A server connection driven by its own non-threaded mainloop.
+pub struct Context {
+    mainloop: *mut pa_mainloop,
+    context: *mut pa_context,
+    changed: Box<AtomicBool>,
+}
+
+unsafe impl Send for Context {}
+
+unsafe fn string(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        None
+    } else {
+        unsafe { Some(CStr::from_ptr(ptr).to_string_lossy().into_owned()) }
+    }
+}
+
+unsafe extern "C" fn server_info_cb(
+    _c: *mut pa_context,
+    info: *const pa_server_info,
+    userdata: *mut c_void,
+) {
+    unsafe {
+        if !info.is_null() {
+            *(userdata as *mut Option<String>) = string((*info).default_sink_name);
+        }
+    }
+}
+
+unsafe extern "C" fn sink_info_cb(
+    _c: *mut pa_context,
+    info: *const pa_sink_info,
+    eol: c_int,
+    userdata: *mut c_void,
+) {
+    unsafe {
+        if eol != 0 || info.is_null() {
+            return;
+        }
+        let info = &*info;
+        let sinks = &mut *(userdata as *mut Vec<SinkInfo>);
+        if let Some(name) = string(info.name) {
+            sinks.push(SinkInfo {
+                name,
+                index: info.index,
+                description: string(info.description),
+                sample_rate: info.sample_spec.rate,
+                channels: info.sample_spec.channels,
+            });
+        }
+    }
+}
+
+unsafe extern "C" fn subscribe_cb(_c: *mut pa_context, _t: u32, _idx: u32, userdata: *mut c_void) {
+    unsafe { (*(userdata as *const AtomicBool)).store(true, Ordering::Relaxed) };
+}
+
+impl Context {
+    /// Connects to the default server without autospawning one.
+    pub fn connect(app_name: &str) -> Result<Self> {
+        let app_name = CString::new(app_name).map_err(|_| PulseError(-1))?;
+        unsafe {
+            let mainloop = pa_mainloop_new();
+            if mainloop.is_null() {
+                return Err(PulseError(-1));
+            }
+            let context = pa_context_new(pa_mainloop_get_api(mainloop), app_name.as_ptr());
+            if context.is_null() {
+                pa_mainloop_free(mainloop);
+                return Err(PulseError(-1));
+            }
+
+            let ctx = Self {
+                mainloop,
+                context,
+                changed: Box::new(AtomicBool::new(false)),
+            };
+
+            if pa_context_connect(
+                context,
+                std::ptr::null(),
+                PA_CONTEXT_NOAUTOSPAWN,
+                std::ptr::null(),
+            ) < 0
+            {
+                return Err(PulseError(pa_context_errno(context)));
+            }
+
+            loop {
+                match pa_context_get_state(context) {
+                    PA_CONTEXT_READY => break,
+                    PA_CONTEXT_FAILED | PA_CONTEXT_TERMINATED => {
+                        return Err(PulseError(pa_context_errno(context)));
+                    }
+                    _ => ctx.iterate(true)?,
+                }
+            }
+
+            Ok(ctx)
+        }
+    }
+
+    fn iterate(&self, block: bool) -> Result<()> {
+        unsafe {
+            if pa_mainloop_iterate(self.mainloop, block as c_int, std::ptr::null_mut()) < 0 {
+                Err(PulseError(pa_context_errno(self.context)))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    fn wait(&self, op: *mut pa_operation) -> Result<()> {
+        if op.is_null() {
+            return Err(PulseError(unsafe { pa_context_errno(self.context) }));
+        }
+        let mut result = Ok(());
+        while unsafe { pa_operation_get_state(op) } == PA_OPERATION_RUNNING {
+            result = self.iterate(true);
+            if result.is_err() {
+                break;
+            }
+        }
+        unsafe { pa_operation_unref(op) };
+        result
+    }
+
+    pub fn sinks(&self) -> Result<Vec<SinkInfo>> {
+        let mut sinks: Vec<SinkInfo> = Vec::new();
+        self.wait(unsafe {
+            pa_context_get_sink_info_list(
+                self.context,
+                sink_info_cb,
+                &mut sinks as *mut Vec<SinkInfo> as *mut c_void,
+            )
+        })?;
+        Ok(sinks)
+    }
+
+    pub fn default_sink(&self) -> Result<Option<String>> {
+        let mut name: Option<String> = None;
+        self.wait(unsafe {
+            pa_context_get_server_info(
+                self.context,
+                server_info_cb,
+                &mut name as *mut Option<String> as *mut c_void,
+            )
+        })?;
+        Ok(name)
+    }
+
+    /// Starts listening for sinks being added or removed and default sink changes.
+    pub fn subscribe(&self) -> Result<()> {
+        unsafe {
+            pa_context_set_subscribe_callback(
+                self.context,
+                subscribe_cb,
+                self.changed.as_ref() as *const AtomicBool as *mut c_void,
+            );
+            self.wait(pa_context_subscribe(
+                self.context,
+                PA_SUBSCRIPTION_MASK_SINK | PA_SUBSCRIPTION_MASK_SERVER,
+                std::ptr::null(),
+                std::ptr::null_mut(),
+            ))
+        }
+    }
+
+    /// Dispatches pending events without blocking and reports
+    /// whether anything changed since the last call.
+    pub fn poll_changes(&self) -> Result<bool> {
+        self.iterate(false)?;
+        Ok(self.changed.swap(false, Ordering::Relaxed))
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        unsafe {
+            pa_context_disconnect(self.context);
+            pa_context_unref(self.context);
+            pa_mainloop_free(self.mainloop);
+        }
+    }
+}
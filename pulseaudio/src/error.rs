@@ -0,0 +1,35 @@
+use crate::ffi::pa_strerror;
+use core::fmt;
+use std::ffi::{CStr, c_int};
+
+/// A `PA_ERR_*` code.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PulseError(pub c_int);
+
+impl PulseError {
+    pub fn message(&self) -> String {
+        unsafe {
+            let ptr = pa_strerror(self.0);
+            if ptr.is_null() {
+                return String::from("Unknown error");
+            }
+            CStr::from_ptr(ptr).to_string_lossy().into_owned()
+        }
+    }
+}
+
+impl fmt::Debug for PulseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PulseError({}) ({})", self.0, self.message())
+    }
+}
+
+impl fmt::Display for PulseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl std::error::Error for PulseError {}
+
+pub type Result<T> = std::result::Result<T, PulseError>;
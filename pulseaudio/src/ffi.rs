@@ -0,0 +1,179 @@
+use std::ffi::{c_char, c_int, c_void};
+
+#[repr(C)]
+pub struct pa_simple {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct pa_mainloop {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct pa_mainloop_api {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct pa_context {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct pa_operation {
+    _private: [u8; 0],
+}
+
+pub type pa_usec_t = u64;
+
+pub const PA_SAMPLE_FLOAT32LE: c_int = 5;
+
+pub const PA_STREAM_PLAYBACK: c_int = 1;
+
+pub const PA_CHANNELS_MAX: usize = 32;
+pub const PA_CHANNEL_MAP_WAVEEX: c_int = 3;
+
+pub const PA_CONTEXT_READY: c_int = 4;
+pub const PA_CONTEXT_FAILED: c_int = 5;
+pub const PA_CONTEXT_TERMINATED: c_int = 6;
+
+pub const PA_CONTEXT_NOAUTOSPAWN: c_int = 0x0001;
+
+pub const PA_OPERATION_RUNNING: c_int = 0;
+
+pub const PA_SUBSCRIPTION_MASK_SINK: u32 = 0x0001;
+pub const PA_SUBSCRIPTION_MASK_SERVER: u32 = 0x0080;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct pa_sample_spec {
+    pub format: c_int,
+    pub rate: u32,
+    pub channels: u8,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct pa_channel_map {
+    pub channels: u8,
+    pub map: [c_int; PA_CHANNELS_MAX],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct pa_buffer_attr {
+    pub maxlength: u32,
+    pub tlength: u32,
+    pub prebuf: u32,
+    pub minreq: u32,
+    pub fragsize: u32,
+}
+
+/// Only the leading fields are declared, the struct is always read through a pointer.
+#[repr(C)]
+pub struct pa_server_info {
+    pub user_name: *const c_char,
+    pub host_name: *const c_char,
+    pub server_version: *const c_char,
+    pub server_name: *const c_char,
+    pub sample_spec: pa_sample_spec,
+    pub default_sink_name: *const c_char,
+    pub default_source_name: *const c_char,
+}
+
+/// Only the leading fields are declared, the struct is always read through a pointer.
+#[repr(C)]
+pub struct pa_sink_info {
+    pub name: *const c_char,
+    pub index: u32,
+    pub description: *const c_char,
+    pub sample_spec: pa_sample_spec,
+}
+
+pub type pa_server_info_cb_t =
+    unsafe extern "C" fn(c: *mut pa_context, i: *const pa_server_info, userdata: *mut c_void);
+pub type pa_sink_info_cb_t = unsafe extern "C" fn(
+    c: *mut pa_context,
+    i: *const pa_sink_info,
+    eol: c_int,
+    userdata: *mut c_void,
+);
+pub type pa_context_subscribe_cb_t =
+    unsafe extern "C" fn(c: *mut pa_context, t: u32, idx: u32, userdata: *mut c_void);
+
+#[link(name = "pulse-simple")]
+unsafe extern "C" {
+    pub fn pa_simple_new(
+        server: *const c_char,
+        name: *const c_char,
+        dir: c_int,
+        dev: *const c_char,
+        stream_name: *const c_char,
+        ss: *const pa_sample_spec,
+        map: *const pa_channel_map,
+        attr: *const pa_buffer_attr,
+        error: *mut c_int,
+    ) -> *mut pa_simple;
+    pub fn pa_simple_free(s: *mut pa_simple);
+    pub fn pa_simple_write(
+        s: *mut pa_simple,
+        data: *const c_void,
+        bytes: usize,
+        error: *mut c_int,
+    ) -> c_int;
+    pub fn pa_simple_flush(s: *mut pa_simple, error: *mut c_int) -> c_int;
+    pub fn pa_simple_get_latency(s: *mut pa_simple, error: *mut c_int) -> pa_usec_t;
+}
+
+#[link(name = "pulse")]
+unsafe extern "C" {
+    pub fn pa_strerror(error: c_int) -> *const c_char;
+
+    pub fn pa_channel_map_init_extend(
+        m: *mut pa_channel_map,
+        channels: u32,
+        def: c_int,
+    ) -> *mut pa_channel_map;
+
+    pub fn pa_mainloop_new() -> *mut pa_mainloop;
+    pub fn pa_mainloop_free(m: *mut pa_mainloop);
+    pub fn pa_mainloop_get_api(m: *mut pa_mainloop) -> *mut pa_mainloop_api;
+    pub fn pa_mainloop_iterate(m: *mut pa_mainloop, block: c_int, retval: *mut c_int) -> c_int;
+
+    pub fn pa_context_new(api: *mut pa_mainloop_api, name: *const c_char) -> *mut pa_context;
+    pub fn pa_context_unref(c: *mut pa_context);
+    pub fn pa_context_connect(
+        c: *mut pa_context,
+        server: *const c_char,
+        flags: c_int,
+        api: *const c_void,
+    ) -> c_int;
+    pub fn pa_context_disconnect(c: *mut pa_context);
+    pub fn pa_context_get_state(c: *mut pa_context) -> c_int;
+    pub fn pa_context_errno(c: *mut pa_context) -> c_int;
+    pub fn pa_context_get_server_info(
+        c: *mut pa_context,
+        cb: pa_server_info_cb_t,
+        userdata: *mut c_void,
+    ) -> *mut pa_operation;
+    pub fn pa_context_get_sink_info_list(
+        c: *mut pa_context,
+        cb: pa_sink_info_cb_t,
+        userdata: *mut c_void,
+    ) -> *mut pa_operation;
+    pub fn pa_context_subscribe(
+        c: *mut pa_context,
+        mask: u32,
+        cb: *const c_void,
+        userdata: *mut c_void,
+    ) -> *mut pa_operation;
+    pub fn pa_context_set_subscribe_callback(
+        c: *mut pa_context,
+        cb: pa_context_subscribe_cb_t,
+        userdata: *mut c_void,
+    );
+
+    pub fn pa_operation_get_state(o: *mut pa_operation) -> c_int;
+    pub fn pa_operation_unref(o: *mut pa_operation);
+}
@@ -0,0 +1,12 @@
+#![cfg(target_os = "linux")]
+#![allow(non_camel_case_types)]
+
+pub mod context;
+pub mod error;
+pub mod ffi;
+pub mod simple;
+
+pub use context::*;
+pub use error::*;
+pub use ffi::*;
+pub use simple::*;
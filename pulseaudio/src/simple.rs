@@ -0,0 +1,114 @@
+use crate::error::{PulseError, Result};
+use crate::ffi::*;
+use std::ffi::{CString, c_void};
+use std::time::Duration;
+
+/// A blocking float32 playback stream.
+pub struct Simple {
+    ptr: *mut pa_simple,
+}
+
+unsafe impl Send for Simple {}
+
+impl Simple {
+    /// Opens a stream on `sink`, or the server's default sink when `None`.
+    /// `latency` is the amount of audio the server keeps queued. Channels are in WAVE order.
+    pub fn new(
+        app_name: &str,
+        sink: Option<&str>,
+        sample_rate: u32,
+        channels: u8,
+        latency: Duration,
+    ) -> Result<Self> {
+        let app_name = CString::new(app_name).map_err(|_| PulseError(-1))?;
+        let sink = match sink {
+            Some(sink) => Some(CString::new(sink).map_err(|_| PulseError(-1))?),
+            None => None,
+        };
+        let spec = pa_sample_spec {
+            format: PA_SAMPLE_FLOAT32LE,
+            rate: sample_rate,
+            channels,
+        };
+        let mut map = pa_channel_map {
+            channels: 0,
+            map: [0; PA_CHANNELS_MAX],
+        };
+        // The default map is AIFF, which orders surround channels differently.
+        unsafe { pa_channel_map_init_extend(&mut map, channels as u32, PA_CHANNEL_MAP_WAVEEX) };
+        let frame_bytes = size_of::<f32>() as u64 * channels as u64;
+        let attr = pa_buffer_attr {
+            maxlength: u32::MAX,
+            tlength: (sample_rate as u64 * latency.as_micros() as u64 / 1_000_000 * frame_bytes)
+                as u32,
+            prebuf: u32::MAX,
+            minreq: u32::MAX,
+            fragsize: u32::MAX,
+        };
+
+        let mut error = 0;
+        let ptr = unsafe {
+            pa_simple_new(
+                std::ptr::null(),
+                app_name.as_ptr(),
+                PA_STREAM_PLAYBACK,
+                sink.as_ref().map_or(std::ptr::null(), |s| s.as_ptr()),
+                c"Playback".as_ptr(),
+                &spec,
+                &map,
+                &attr,
+                &mut error,
+            )
+        };
+
+        if ptr.is_null() {
+            Err(PulseError(error))
+        } else {
+            Ok(Self { ptr })
+        }
+    }
+
+    /// Blocks until the whole buffer has been queued on the server.
+    pub fn write(&self, buffer: &[u8]) -> Result<()> {
+        let mut error = 0;
+        let result = unsafe {
+            pa_simple_write(
+                self.ptr,
+                buffer.as_ptr() as *const c_void,
+                buffer.len(),
+                &mut error,
+            )
+        };
+        if result < 0 {
+            Err(PulseError(error))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Discards everything queued on the server.
+    pub fn flush(&self) -> Result<()> {
+        let mut error = 0;
+        if unsafe { pa_simple_flush(self.ptr, &mut error) } < 0 {
+            Err(PulseError(error))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn latency(&self) -> Result<Duration> {
+        let mut error = 0;
+        let usec = unsafe { pa_simple_get_latency(self.ptr, &mut error) };
+        if usec == u64::MAX {
+            Err(PulseError(error))
+        } else {
+            Ok(Duration::from_micros(usec))
+        }
+    }
+}
+
+impl Drop for Simple {
+    fn drop(&mut self) {
+        unsafe { pa_simple_free(self.ptr) };
+    }
+}
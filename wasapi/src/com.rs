@@ -57,6 +57,13 @@ pub unsafe fn CoInitializeEx(model: ConcurrencyModel) -> Result<(), i32> {
     CoInitializeEx(core::mem::zeroed(), transmute(model)).as_result_owned(())
 }
 
+pub unsafe fn CoTaskMemFree(pv: *mut c_void) {
+    extern "system" {
+        fn CoTaskMemFree(pv: *mut c_void);
+    }
+    CoTaskMemFree(pv)
+}
+
 pub unsafe fn CoCreateInstance<T>(
     class_id: *const GUID,
     context: ExecutionContext,
@@ -247,10 +247,15 @@ impl IMMDevice {
         (vtable.OpenPropertyStore)(this, access_mode, &mut properties).as_result(properties)
     }
 
-    // #[inline]
-    // pub unsafe fn GetId(&self, ppstrId: *mut LPWSTR) -> i32 {
-    //     ((*self.lpVtbl).GetId)(self as *const _ as *mut _, ppstrId)
-    // }
+    #[inline]
+    pub unsafe fn GetId(&self) -> Result<String, i32> {
+        let (this, vtable) = self.vtable();
+        let mut str_id = core::ptr::null_mut();
+        (vtable.GetId)(this, &mut str_id).as_result_owned(())?;
+        let id = wide_string(str_id);
+        CoTaskMemFree(str_id as *mut c_void);
+        Ok(id)
+    }
 
     // #[inline]
     // pub unsafe fn GetState(&self, pdwState: *mut u32) -> i32 {